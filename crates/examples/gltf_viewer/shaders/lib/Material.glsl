@@ -2,8 +2,20 @@
 struct TextureInfo {
     int index;
     int coord;
+    // KHR_texture_transform as a column-major 3x2 matrix
+    float transform[6];
+    int _padding[2];
 };
 
+vec2 getUV(vec4 uv0And1, TextureInfo info) {
+    mat3x2 transform = mat3x2(
+        info.transform[0], info.transform[1],
+        info.transform[2], info.transform[3],
+        info.transform[4], info.transform[5]
+    );
+    return transform * vec3(getUV(uv0And1, info.coord), 1.);
+}
+
 struct TransmissionInfo {
     TextureInfo transmission_texture;
     float transmission_factor;
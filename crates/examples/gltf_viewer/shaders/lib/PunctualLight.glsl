@@ -8,15 +8,35 @@ const uint SPOT_LIGHT = 2;
 struct Light {
     vec4 color;
     vec4 transform;
+    vec4 direction;
     uint kind;
     float range;
     float intensity;
-    float _padding;
+    float light_angle_scale;
+    float light_angle_offset;
+    uint _padding[3];
 };
 
-vec3 getLightIntensityAtPoint(Light light, float distance) {
-    vec3 color = light.intensity * light.color.rgb;
-    if (light.kind == POINT_LIGHT) {
+// Range and spot cone attenuation as suggested by KHR_lights_punctual.
+// lightVector points from the shaded point to the light.
+float getLightAttenuation(Light light, vec3 lightVector) {
+    if (light.kind == DIRECT_LIGHT) {
+        return 1.;
+    }
+    float distance = length(lightVector);
+    float attenuation = clamp(1. - pow(distance / light.range, 4.), 0., 1.);
+    if (light.kind == SPOT_LIGHT && distance > 0.) {
+        float cd = dot(normalize(light.direction.xyz), -lightVector / distance);
+        float angular = clamp(cd * light.light_angle_scale + light.light_angle_offset, 0., 1.);
+        attenuation *= angular * angular;
+    }
+    return attenuation;
+}
+
+vec3 getLightIntensityAtPoint(Light light, vec3 lightVector) {
+    vec3 color = light.intensity * light.color.rgb * getLightAttenuation(light, lightVector);
+    float distance = length(lightVector);
+    if (light.kind == POINT_LIGHT || light.kind == SPOT_LIGHT) {
         // Cem Yuksel's improved attenuation avoiding singularity at distance=0
         // Source: http://www.cemyuksel.com/research/pointlightattenuation/
         const float radius = 0.5f; //< We hardcode radius at 0.5, but this should be a light parameter
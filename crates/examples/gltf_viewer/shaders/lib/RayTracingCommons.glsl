@@ -46,22 +46,92 @@ vec4 Mix(vec4 a, vec4 b, vec4 c, vec3 barycentrics)
 
 struct PrimInfo {
     uint v_offset;
+    // In units of the index size
     uint i_offset;
     uint material_id;
-    uint _padding;
+    uint index_u16;
+    // NO_COLORS without vertex colors
+    uint color_offset;
 };
 
+const uint NO_COLORS = 0xFFFFFFFFu;
+// Lowest bit of the second component of an encoded tangent
+const uint TANGENT_SIGN = 1u << 16;
+
+// Packed vertex streams, see asset_loader::CompactGeometry
+struct VertexAttributes {
+	// Octahedral, two snorm16
+	uint normal;
+	// Octahedral, the bitangent sign in TANGENT_SIGN
+	uint tangent;
+	vec2 uv0;
+	vec2 uv1;
+};
+
+// Morphed, then skinned
+struct AnimatedVertex {
+	uint vertex;
+	// NO_ANIMATION without skin
+	uint joint_offset;
+	// Four u16
+	uvec2 joints;
+	// Four unorm16
+	uvec2 weights;
+	// Index of the MorphGeometry, NO_ANIMATION without morph targets
+	uint morph;
+};
+
+struct MorphGeometry {
+	uint first_vertex;
+	uint vertices;
+	uint targets;
+	// Index of the first displacement, one target after the other
+	uint deltas;
+	// Index of the weight of the first target
+	uint weights;
+};
+
+const uint NO_ANIMATION = 0xFFFFFFFFu;
+
+// Unpacked vertex
 struct Vertex {
 	vec3 pos;
 	vec3 normal;
 	vec4 tangent;
 	vec4 color;
-	vec4 weights;
-	uvec4 joints;
 	vec4 uv0And1;
-	int skin_index;
 };
 
+vec2 signNotZero(vec2 v) {
+	return vec2(v.x >= 0. ? 1. : -1., v.y >= 0. ? 1. : -1.);
+}
+
+uint encodeOctahedral(vec3 n) {
+	float sum = abs(n.x) + abs(n.y) + abs(n.z);
+	if (sum == 0.) {
+		return 0u;
+	}
+	n /= sum;
+	vec2 e = n.z >= 0. ? n.xy : (1. - abs(n.yx)) * signNotZero(n.xy);
+	return packSnorm2x16(e);
+}
+
+vec3 decodeOctahedral(uint bits) {
+	vec2 e = unpackSnorm2x16(bits);
+	vec3 n = vec3(e, 1. - abs(e.x) - abs(e.y));
+	float t = max(-n.z, 0.);
+	n.xy -= signNotZero(n.xy) * t;
+	return normalize(n);
+}
+
+uint encodeTangent(vec4 t) {
+	return (encodeOctahedral(t.xyz) & ~TANGENT_SIGN) | (t.w < 0. ? TANGENT_SIGN : 0u);
+}
+
+vec4 decodeTangent(uint bits) {
+	return vec4(decodeOctahedral(bits & ~TANGENT_SIGN), (bits & TANGENT_SIGN) != 0 ? -1. : 1.);
+}
+
 vec2 getUV(vec4 uv0And1, uint index) {
 	if (index == 0) {
 		return uv0And1.xy;
@@ -92,7 +162,6 @@ out Vertex mix_v) {
 	mix_v.normal = normalize(Mix(normalize(v0.normal), normalize(v1.normal),
 	normalize(v2.normal), barycentricCoords));
 	mix_v.tangent = normalize(Mix(v0.tangent, v1.tangent, v2.tangent, barycentricCoords));
-	mix_v.skin_index = v0.skin_index;
 	return calculate_geo_normal(v0.pos, v1.pos, v2.pos);
 }
 
@@ -127,6 +196,7 @@ const uint VERTEX_BIND = 3;
 const uint INDEX_BIND = 4;
 const uint GEO_BIND = 5;
 const uint TEXTURE_BIND = 6;
+const uint ATTRIBUTE_BIND = 7;
 const uint ACC_BIND = 8;
 const uint MAT_BIND = 9;
 const uint DLIGHT_BIND = 10;
@@ -134,6 +204,12 @@ const uint PLIGHT_BIND = 11;
 const uint SKYBOX_BIND = 12;
 const uint ANIMATION_BIND = 13;
 const uint SKIN_BIND = 14;
+const uint COLOR_BIND = 15;
+const uint ANIMATION_ATTRIBUTE_BIND = 16;
+const uint ANIMATED_VERTEX_BIND = 17;
+const uint MORPH_BIND = 18;
+const uint MORPH_DELTA_BIND = 19;
+const uint MORPH_WEIGHT_BIND = 20;
 
 
 const uint RENDER = 0;
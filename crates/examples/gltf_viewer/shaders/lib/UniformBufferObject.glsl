@@ -10,7 +10,8 @@ struct UniformBufferObject
 	float Aperture;
 	float FocusDistance;
 	float fovAngle;
-	float orthographic_fov_dis;
+	float orthographic_xmag;
+	float orthographic_ymag;
 	float HeatmapScale;
 	uint TotalNumberOfSamples;
 
@@ -27,4 +28,5 @@ struct UniformBufferObject
 	bool fully_opaque;
 	float exposure;
 	uint tone_mapping_mode;
+	float frame_aspect;
 };
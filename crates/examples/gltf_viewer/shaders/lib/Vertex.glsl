@@ -0,0 +1,32 @@
+// Vertex streams of the hit shaders, see asset_loader::CompactGeometry
+layout(binding = VERTEX_BIND, set = 0) readonly buffer Positions { float p[]; } positions;
+layout(binding = ATTRIBUTE_BIND, set = 0) readonly buffer Attributes { VertexAttributes a[]; } attributes;
+layout(binding = COLOR_BIND, set = 0) readonly buffer Colors { uint c[]; } colors;
+// 16 bit indices are packed in pairs
+layout(binding = INDEX_BIND, set = 0) readonly buffer Indices { uint i[]; } indices;
+
+uint fetchIndex16(uint i) {
+	const uint word = indices.i[i >> 1];
+	return (i & 1u) == 0u ? word & 0xFFFFu : word >> 16;
+}
+
+// Vertex indices of a triangle, local to the geometry
+uvec3 fetchTriangle(const PrimInfo primInfo, uint primitive) {
+	const uint first = primInfo.i_offset + 3 * primitive;
+	if (primInfo.index_u16 != 0) {
+		return uvec3(fetchIndex16(first), fetchIndex16(first + 1), fetchIndex16(first + 2));
+	}
+	return uvec3(indices.i[first], indices.i[first + 1], indices.i[first + 2]);
+}
+
+Vertex fetchVertex(const PrimInfo primInfo, uint index) {
+	const uint i = primInfo.v_offset + index;
+	const VertexAttributes a = attributes.a[i];
+	Vertex v;
+	v.pos = vec3(positions.p[3 * i], positions.p[3 * i + 1], positions.p[3 * i + 2]);
+	v.normal = decodeOctahedral(a.normal);
+	v.tangent = decodeTangent(a.tangent);
+	v.uv0And1 = vec4(a.uv0, a.uv1);
+	v.color = primInfo.color_offset == NO_COLORS ? vec4(1.) : unpackUnorm4x8(colors.c[primInfo.color_offset + index]);
+	return v;
+}
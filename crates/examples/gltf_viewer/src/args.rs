@@ -1,4 +1,6 @@
+use asset_loader::{LoadOptions, NormalMode};
 use clap::Parser;
+use std::path::PathBuf;
 
 /// Simple program to greet a person
 #[derive(Parser, Debug)]
@@ -7,4 +9,50 @@ pub struct Args {
     /// Path of the glTF file
     #[clap(short, long, value_parser, default_value = "")]
     pub file: String,
+    /// Write the loaded file to this .gltf or .glb
+    #[clap(short, long, value_parser, default_value = "")]
+    pub export: String,
+    /// Keep the units of the file instead of fitting the scene into a 10 unit box
+    #[clap(long)]
+    pub no_normalize: bool,
+    /// The file is Z-up
+    #[clap(long)]
+    pub z_up: bool,
+    /// Regenerate tangents even when the file has them
+    #[clap(long)]
+    pub regenerate_tangents: bool,
+    /// Downscale larger textures to this size
+    #[clap(long, value_parser)]
+    pub max_texture_size: Option<u32>,
+    /// Generate flat instead of smooth normals for meshes without them
+    #[clap(long)]
+    pub flat_normals: bool,
+    /// Degrees between triangles beyond which generated normals keep a hard edge
+    #[clap(long, value_parser, default_value_t = 60.)]
+    pub crease_angle: f32,
+    /// Keep the vertices of the file instead of merging the ones with equal attributes
+    #[clap(long)]
+    pub no_weld: bool,
+    /// Store processed files in this directory and reuse them while the sources are unchanged
+    #[clap(long, value_parser)]
+    pub cache_dir: Option<PathBuf>,
+}
+
+impl Args {
+    pub fn load_options(&self) -> LoadOptions {
+        LoadOptions {
+            normalize: !self.no_normalize,
+            z_up: self.z_up,
+            regenerate_tangents: self.regenerate_tangents,
+            max_texture_size: self.max_texture_size,
+            normals: if self.flat_normals {
+                NormalMode::Flat
+            } else {
+                NormalMode::Smooth
+            },
+            crease_angle: self.crease_angle,
+            weld: !self.no_weld,
+            cache_dir: self.cache_dir.clone(),
+        }
+    }
 }
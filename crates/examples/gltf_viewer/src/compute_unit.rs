@@ -1,5 +1,8 @@
 use crate::desc_sets::DescriptorRes;
-use crate::{ANIMATION_BIND, SKIN_BIND, VERTEX_BIND};
+use crate::{
+    ANIMATED_VERTEX_BIND, ANIMATION_ATTRIBUTE_BIND, ANIMATION_BIND, ATTRIBUTE_BIND, MORPH_BIND,
+    MORPH_DELTA_BIND, MORPH_WEIGHT_BIND, SKIN_BIND, VERTEX_BIND,
+};
 use app::anyhow::Result;
 use app::load_spv;
 use app::vulkan::ash::vk;
@@ -27,20 +30,25 @@ impl ComputeUnit {
         })
     }
 
-    pub fn dispatch(&self, context: &Context, _buffers: &Buffers, vertex_count: u32) -> Result<()> {
+    /// Morph and skin the animated vertices from the rest streams.
+    /// The other vertices were uploaded with the animation buffers and are never touched.
+    pub fn dispatch(&self, context: &Context, buffers: &Buffers) -> Result<()> {
+        let ani = buffers.animation_buffers.as_ref().unwrap();
         let cmd_buffer = context
             .command_pool
             .allocate_command_buffer(vk::CommandBufferLevel::PRIMARY)?;
         cmd_buffer.begin(Some(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT))?;
-        let static_set = &self.descriptor_res.static_set;
-        cmd_buffer.bind_compute_pipeline(&self.pipeline.pipeline);
-        cmd_buffer.bind_descriptor_sets(
-            vk::PipelineBindPoint::COMPUTE,
-            &self.pipeline.pipeline_layout,
-            0,
-            &[static_set],
-        );
-        cmd_buffer.dispatch((vertex_count / DISPATCH_SIZE) + 1, 1, 1);
+        if ani.animated_vertices > 0 {
+            let static_set = &self.descriptor_res.static_set;
+            cmd_buffer.bind_compute_pipeline(&self.pipeline.pipeline);
+            cmd_buffer.bind_descriptor_sets(
+                vk::PipelineBindPoint::COMPUTE,
+                &self.pipeline.pipeline_layout,
+                0,
+                &[static_set],
+            );
+            cmd_buffer.dispatch((ani.animated_vertices / DISPATCH_SIZE) + 1, 1, 1);
+        }
         unsafe {
             context.device.inner.cmd_pipeline_barrier2(
                 cmd_buffer.inner,
@@ -75,25 +83,24 @@ pub fn create_compute_pipeline(context: &Context) -> Result<ComputePipelineRes>
     };
     let stage_flag = vk::ShaderStageFlags::COMPUTE;
     let layout_bindings = [
+        VERTEX_BIND,
+        ATTRIBUTE_BIND,
+        ANIMATION_BIND,
+        ANIMATION_ATTRIBUTE_BIND,
+        SKIN_BIND,
+        ANIMATED_VERTEX_BIND,
+        MORPH_BIND,
+        MORPH_DELTA_BIND,
+        MORPH_WEIGHT_BIND,
+    ]
+    .map(|binding| {
         vk::DescriptorSetLayoutBinding::builder()
-            .binding(VERTEX_BIND)
-            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
-            .descriptor_count(1)
-            .stage_flags(stage_flag)
-            .build(),
-        vk::DescriptorSetLayoutBinding::builder()
-            .binding(ANIMATION_BIND)
-            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
-            .descriptor_count(1)
-            .stage_flags(stage_flag)
-            .build(),
-        vk::DescriptorSetLayoutBinding::builder()
-            .binding(SKIN_BIND)
+            .binding(binding)
             .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
             .descriptor_count(1)
             .stage_flags(stage_flag)
-            .build(),
-    ];
+            .build()
+    });
     let dsl = context.create_descriptor_set_layout(&layout_bindings)?;
     let pipeline_layout = context.create_pipeline_layout(&[&dsl])?;
     let pipeline = ComputePipeline::new(context.device.clone(), &pipeline_layout, info)?;
@@ -117,28 +124,27 @@ pub fn create_descriptor_sets(
 ) -> Result<DescriptorRes> {
     let pool_sizes = [vk::DescriptorPoolSize::builder()
         .ty(vk::DescriptorType::STORAGE_BUFFER)
-        .descriptor_count(3)
+        .descriptor_count(9)
         .build()];
 
     let pool = context.create_descriptor_pool(1, &pool_sizes)?;
 
     let static_set = pool.allocate_set(&pipeline_res.dsl)?;
-    let (skins, ani) = buffers.animation_buffers.as_ref().unwrap();
+    let ani = buffers.animation_buffers.as_ref().unwrap();
+    let storage = |binding, buffer| WriteDescriptorSet {
+        binding,
+        kind: WriteDescriptorSetKind::StorageBuffer { buffer },
+    };
     static_set.update(&[
-        WriteDescriptorSet {
-            binding: VERTEX_BIND,
-            kind: WriteDescriptorSetKind::StorageBuffer {
-                buffer: &buffers.vertex_buffer,
-            },
-        },
-        WriteDescriptorSet {
-            binding: SKIN_BIND,
-            kind: WriteDescriptorSetKind::StorageBuffer { buffer: skins },
-        },
-        WriteDescriptorSet {
-            binding: ANIMATION_BIND,
-            kind: WriteDescriptorSetKind::StorageBuffer { buffer: ani },
-        },
+        storage(VERTEX_BIND, &buffers.vertex_buffer),
+        storage(ATTRIBUTE_BIND, &buffers.attribute_buffer),
+        storage(ANIMATION_BIND, &ani.positions),
+        storage(ANIMATION_ATTRIBUTE_BIND, &ani.attributes),
+        storage(SKIN_BIND, &ani.joints),
+        storage(ANIMATED_VERTEX_BIND, &ani.animated),
+        storage(MORPH_BIND, &ani.morphs),
+        storage(MORPH_DELTA_BIND, &ani.morph_deltas),
+        storage(MORPH_WEIGHT_BIND, &ani.morph_weights),
     ]);
     Ok(DescriptorRes {
         _pool: pool,
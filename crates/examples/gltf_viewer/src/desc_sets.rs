@@ -1,7 +1,7 @@
 use crate::pipeline_res::PipelineRes;
 use crate::{
-    ACC_BIND, AS_BIND, DLIGHT_BIND, GEO_BIND, INDEX_BIND, MAT_BIND, PLIGHT_BIND, STORAGE_BIND,
-    TEXTURE_BIND, UNIFORM_BIND, VERTEX_BIND,
+    ACC_BIND, AS_BIND, ATTRIBUTE_BIND, COLOR_BIND, DLIGHT_BIND, GEO_BIND, INDEX_BIND, MAT_BIND,
+    PLIGHT_BIND, STORAGE_BIND, TEXTURE_BIND, UNIFORM_BIND, VERTEX_BIND,
 };
 use app::anyhow::Result;
 use app::vulkan::ash::vk;
@@ -51,7 +51,7 @@ pub fn create_descriptor_sets(
             .build(),
         vk::DescriptorPoolSize::builder()
             .ty(vk::DescriptorType::STORAGE_BUFFER)
-            .descriptor_count(6)
+            .descriptor_count(8)
             .build(),
         vk::DescriptorPoolSize::builder()
             .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
@@ -69,11 +69,19 @@ pub fn create_descriptor_sets(
         WriteDescriptorSet {
             binding: VERTEX_BIND,
             kind: WriteDescriptorSetKind::StorageBuffer {
-                buffer: if let Some((_, ani)) = &buffers.animation_buffers {
-                    ani
-                } else {
-                    &buffers.vertex_buffer
-                },
+                buffer: buffers.positions(),
+            },
+        },
+        WriteDescriptorSet {
+            binding: ATTRIBUTE_BIND,
+            kind: WriteDescriptorSetKind::StorageBuffer {
+                buffer: buffers.attributes(),
+            },
+        },
+        WriteDescriptorSet {
+            binding: COLOR_BIND,
+            kind: WriteDescriptorSetKind::StorageBuffer {
+                buffer: &buffers.color_buffer,
             },
         },
         WriteDescriptorSet {
@@ -108,19 +116,8 @@ pub fn create_descriptor_sets(
             binding: UNIFORM_BIND,
             kind: WriteDescriptorSetKind::UniformBuffer { buffer: ubo_buffer },
         },
-        WriteDescriptorSet {
-            binding: DLIGHT_BIND,
-            kind: WriteDescriptorSetKind::StorageBuffer {
-                buffer: &buffers.dlights_buffer,
-            },
-        },
-        WriteDescriptorSet {
-            binding: PLIGHT_BIND,
-            kind: WriteDescriptorSetKind::StorageBuffer {
-                buffer: &buffers.plights_buffer,
-            },
-        },
     ]);
+    update_light_desc(&static_set, buffers);
 
     let mut writes = vec![];
     for [_texture_index, image_index, sampler_index] in model.textures.iter() {
@@ -162,3 +159,20 @@ pub fn create_descriptor_sets(
         static_set,
     })
 }
+
+pub fn update_light_desc(static_set: &DescriptorSet, buffers: &Buffers) {
+    static_set.update(&[
+        WriteDescriptorSet {
+            binding: DLIGHT_BIND,
+            kind: WriteDescriptorSetKind::StorageBuffer {
+                buffer: &buffers.dlights_buffer,
+            },
+        },
+        WriteDescriptorSet {
+            binding: PLIGHT_BIND,
+            kind: WriteDescriptorSetKind::StorageBuffer {
+                buffer: &buffers.plights_buffer,
+            },
+        },
+    ]);
+}
@@ -1,7 +1,8 @@
 use crate::gui_state::Scene::DragAndDrop;
 use app::anyhow::Result;
 use asset_loader::light::LightRaw;
-use gui::imgui::{Condition, Ui};
+use asset_loader::{LoadOptions, NormalMode, PlayMode};
+use gui::imgui::{Condition, ProgressBar, TreeNodeFlags, Ui};
 use std::borrow::Cow;
 use std::convert::AsRef;
 use std::time::Duration;
@@ -25,10 +26,19 @@ pub struct Gui {
     pub max_number_of_samples: u32,
     pub scale: f32,
     pub scene: Scene,
+    // Applied to the next load, changing them reloads the scene
+    pub load_options: LoadOptions,
     pub mapping: Mapping,
     pub skybox: Skybox,
-    pub animation: bool,
+    // Names and durations of the animations in the loaded scene
+    pub animations: Vec<(String, f32)>,
+    pub animation_clip: usize,
+    pub animation_playing: bool,
+    pub animation_time: f32,
     pub animation_speed: f32,
+    pub animation_mode: PlayMode,
+    // Seconds to blend into a newly selected clip
+    pub animation_crossfade: f32,
     pub antialiasing: bool,
     pub debug: u32,
     pub sun: LightRaw,
@@ -38,6 +48,29 @@ pub struct Gui {
     pub point_light_radius: f32,
     pub exposure: f32,
     pub selected_tone_map_mode: usize,
+    // KHR_materials_variants of the loaded scene
+    pub variants: Vec<String>,
+    pub material_variant: Option<usize>,
+    // glTF cameras of the loaded scene
+    pub cameras: Vec<String>,
+    pub selected_camera: Option<usize>,
+    // Of the selected glTF camera, the frame is letterboxed to its aspect ratio
+    pub camera_aspect: Option<f32>,
+    // xmag and ymag of the selected orthographic glTF camera
+    pub camera_magnification: Option<[f32; 2]>,
+    // Scenes inside the loaded glTF document
+    pub gltf_scenes: Vec<String>,
+    pub gltf_scene: usize,
+    // Load report sections and timings of the loaded file
+    pub load_report: Vec<(String, Vec<String>)>,
+    pub load_timings: Vec<String>,
+    // Export the loaded doc on the next frame, to `export_path` or next to the source file
+    pub export: bool,
+    pub export_path: String,
+    // Properties of the clicked object
+    pub selection: Vec<(String, String)>,
+    // File being loaded and the part done
+    pub loading: Option<(String, f32)>,
 }
 
 #[derive(IntoStaticStr, AsRefStr, EnumIter, PartialEq, Clone, Debug, Default)]
@@ -286,8 +319,16 @@ impl Gui {
         }
     }
 
+    /// Half width and half height of an orthographic view, None for a perspective one
+    pub fn orthographic(&self) -> Option<[f32; 2]> {
+        self.camera_magnification.or_else(|| {
+            let half = 1. + self.orthographic_fov_dis;
+            (self.orthographic_fov_dis > 0.).then_some([half; 2])
+        })
+    }
+
     pub fn acc(&self) -> bool {
-        self.acc && !self.is_mapping() && !self.animation
+        self.acc && !self.is_mapping() && !self.animation_playing
     }
 
     pub fn get_bounce(&self) -> u32 {
@@ -313,11 +354,17 @@ impl app::Gui for Gui {
             max_number_of_samples: 5000,
             sky: false,
             scene: Default::default(),
+            load_options: Default::default(),
             scale: 1.,
             mapping: Default::default(),
             skybox: Default::default(),
-            animation: false,
+            animations: vec![],
+            animation_clip: 0,
+            animation_playing: false,
+            animation_time: 0.,
             animation_speed: 1.,
+            animation_mode: PlayMode::default(),
+            animation_crossfade: 0.3,
             antialiasing: true,
             debug: 0,
             sun: LightRaw::default(),
@@ -328,6 +375,20 @@ impl app::Gui for Gui {
             orthographic_fov_dis: 0.0,
             exposure: 5.0,
             selected_tone_map_mode: 0,
+            variants: vec![],
+            material_variant: None,
+            cameras: vec![],
+            selected_camera: None,
+            camera_aspect: None,
+            camera_magnification: None,
+            gltf_scenes: vec![],
+            gltf_scene: 0,
+            load_report: vec![],
+            load_timings: vec![],
+            export: false,
+            export_path: String::new(),
+            selection: vec![],
+            loading: None,
         })
     }
 
@@ -387,6 +448,42 @@ impl app::Gui for Gui {
                     }
                     self.scene = selected;
                 }
+                self.build_load_options(ui);
+                if let Some((label, fraction)) = &self.loading {
+                    ProgressBar::new(*fraction).overlay_text(label).build(ui);
+                }
+                if self.gltf_scenes.len() > 1 {
+                    ui.combo("glTF scene", &mut self.gltf_scene, &self.gltf_scenes, |s| {
+                        Cow::Borrowed(s.as_str())
+                    });
+                }
+                if !self.variants.is_empty() {
+                    let mut selected = self.material_variant.map_or(0, |v| v + 1);
+                    ui.combo(
+                        "Material variant",
+                        &mut selected,
+                        &[None]
+                            .into_iter()
+                            .chain(self.variants.iter().map(Some))
+                            .collect::<Vec<_>>(),
+                        |v| Cow::Borrowed(v.map_or("Default", |v| v.as_str())),
+                    );
+                    self.material_variant = selected.checked_sub(1);
+                }
+                if !self.cameras.is_empty() {
+                    let mut selected = self.selected_camera.map_or(0, |c| c + 1);
+                    ui.combo(
+                        "Camera",
+                        &mut selected,
+                        &[None]
+                            .into_iter()
+                            .chain(self.cameras.iter().map(Some))
+                            .collect::<Vec<_>>(),
+                        |c| Cow::Borrowed(c.map_or("Free", |c| c.as_str())),
+                    );
+                    self.selected_camera = selected.checked_sub(1);
+                }
+                self.build_export(ui);
 
                 ui.separator();
                 let _tone_map_mode_changed = ui.combo(
@@ -451,13 +548,6 @@ impl app::Gui for Gui {
                     self.acc = !self.acc;
                 }
 
-                if ui.radio_button_bool("Animation", self.animation) {
-                    self.animation = !self.animation;
-                }
-                if self.animation {
-                    ui.slider("Animation speed", 0.1, 10., &mut self.animation_speed);
-                }
-
                 if ui.radio_button_bool("sky", self.sky) {
                     self.sky = !self.sky;
                 }
@@ -491,5 +581,136 @@ impl app::Gui for Gui {
                 //     .build();
                 // self.sun.update_color([color[0], color[1], color[2], 0.]);
             });
+        self.build_load_report(ui);
+        self.build_animation(ui);
+        self.build_selection(ui);
+    }
+}
+
+impl Gui {
+    fn build_export(&mut self, ui: &Ui) {
+        ui.input_text("Export path", &mut self.export_path)
+            .hint("Next to the source file")
+            .build();
+        if ui.button("Export .glb or .gltf") {
+            self.export = true;
+        }
+    }
+
+    fn build_selection(&mut self, ui: &Ui) {
+        if self.selection.is_empty() {
+            return;
+        }
+        ui.window("Selection")
+            .position([420.0, 540.0], Condition::FirstUseEver)
+            .size([400.0, 300.0], Condition::FirstUseEver)
+            .bg_alpha(0.5)
+            .build(|| {
+                for (label, value) in &self.selection {
+                    ui.text_wrapped(format!("{}: {}", label, value));
+                }
+                if ui.button("Clear") {
+                    self.selection.clear();
+                }
+            });
+    }
+
+    fn build_load_options(&mut self, ui: &Ui) {
+        if !ui.collapsing_header("Load options", TreeNodeFlags::empty()) {
+            return;
+        }
+        let options = &mut self.load_options;
+        ui.checkbox("Normalize size", &mut options.normalize);
+        ui.checkbox("Z-up", &mut options.z_up);
+        ui.checkbox("Regenerate tangents", &mut options.regenerate_tangents);
+        ui.checkbox("Weld vertices", &mut options.weld);
+        let mut sizes = vec![None, Some(512), Some(1024), Some(2048), Some(4096)];
+        // A size given on the command line
+        if !sizes.contains(&options.max_texture_size) {
+            sizes.push(options.max_texture_size);
+        }
+        let mut size = sizes
+            .iter()
+            .position(|&s| s == options.max_texture_size)
+            .unwrap_or(0);
+        ui.combo("Max texture size", &mut size, &sizes, |s| {
+            Cow::Owned(s.map_or("Original".to_string(), |s| s.to_string()))
+        });
+        options.max_texture_size = sizes[size];
+        let modes = [NormalMode::Flat, NormalMode::Smooth];
+        let mut mode = modes
+            .iter()
+            .position(|&m| m == options.normals)
+            .unwrap_or(0);
+        ui.combo("Generated normals", &mut mode, &modes, |m| {
+            Cow::Owned(format!("{m:?}"))
+        });
+        options.normals = modes[mode];
+        if options.normals == NormalMode::Smooth {
+            // Applied on enter, every change reloads the scene
+            ui.input_float("Crease angle", &mut options.crease_angle)
+                .enter_returns_true(true)
+                .build();
+            options.crease_angle = options.crease_angle.clamp(0., 180.);
+        }
+    }
+
+    fn build_animation(&mut self, ui: &Ui) {
+        if self.animations.is_empty() {
+            return;
+        }
+        ui.window("Animation")
+            .position([420.0, 320.0], Condition::FirstUseEver)
+            .size([400.0, 200.0], Condition::FirstUseEver)
+            .bg_alpha(0.5)
+            .build(|| {
+                ui.combo("Clip", &mut self.animation_clip, &self.animations, |a| {
+                    Cow::Borrowed(a.0.as_str())
+                });
+                if ui.button(if self.animation_playing {
+                    "Pause"
+                } else {
+                    "Play"
+                }) {
+                    self.animation_playing = !self.animation_playing;
+                }
+                let duration = self.animations[self.animation_clip].1;
+                ui.slider("Time", 0., duration, &mut self.animation_time);
+                ui.slider("Speed", -4., 4., &mut self.animation_speed);
+                let modes = [PlayMode::Loop, PlayMode::PingPong, PlayMode::Once];
+                let mut mode = modes
+                    .iter()
+                    .position(|&m| m == self.animation_mode)
+                    .unwrap_or(0);
+                ui.combo("Mode", &mut mode, &modes, |m| Cow::Owned(format!("{m:?}")));
+                self.animation_mode = modes[mode];
+                ui.slider("Crossfade", 0., 2., &mut self.animation_crossfade);
+            });
+    }
+
+    fn build_load_report(&mut self, ui: &Ui) {
+        ui.window("Load report")
+            .position([420.0, 0.0], Condition::FirstUseEver)
+            .size([400.0, 300.0], Condition::FirstUseEver)
+            .collapsed(true, Condition::FirstUseEver)
+            .bg_alpha(0.5)
+            .build(|| {
+                let issues: Vec<_> = self
+                    .load_report
+                    .iter()
+                    .filter(|(_, lines)| !lines.is_empty())
+                    .collect();
+                if issues.is_empty() {
+                    ui.text("No issues");
+                }
+                for (title, lines) in issues {
+                    let label = format!("{} ({})", title, lines.len());
+                    if ui.collapsing_header(label, TreeNodeFlags::empty()) {
+                        lines.iter().for_each(|l| ui.text_wrapped(l));
+                    }
+                }
+                ui.separator();
+                self.load_timings.iter().for_each(|t| ui.text(t));
+            });
     }
 }
@@ -1,14 +1,17 @@
 //! Non-blocking model loader
 //!
 //! The loader starts a worker thread that will wait for load messages.
-//! Once a message is received the thread will load the model and send the
-//! loaded model through another channel.
+//! Once a message is received the thread will load the model and send
+//! progress events and the loaded model through another channel.
+//!
+//! The latest request wins: `load` cancels the load in flight, the worker skips
+//! requests that were superseded while queued, and models of older requests are dropped.
 //!
 //! When dropping the loader, a stop message is sent to the thread so it can
 //! stop listening for load events. Then we wait for the thread to terminate.
 //!
 //! Users have to call `load` to load a new model and `get_model` to retrieve
-//! the loaded model.
+//! the loaded model. `status` tells how far the latest request is.
 
 use log::{error, info};
 
@@ -18,41 +21,72 @@ use std::sync::mpsc::{Receiver, Sender};
 use std::thread;
 use std::thread::JoinHandle;
 
-use asset_loader::{load_file, Doc};
+use asset_loader::{load_file_with_progress, Doc, LoadOptions, LoadPhase, LoadProgress};
+
+type RequestId = u64;
 
 enum Message {
-    Load(String),
+    Load(RequestId, String, LoadOptions, LoadProgress),
     Stop,
 }
 
+enum Event {
+    Phase(RequestId, LoadPhase),
+    Loaded(RequestId, Box<Doc>),
+    // Failed or cancelled
+    Stopped(RequestId),
+}
+
 pub struct Loader {
     message_sender: Sender<Message>,
-    model_receiver: Receiver<Doc>,
+    event_sender: Sender<Event>,
+    event_receiver: Receiver<Event>,
     thread_handle: Option<JoinHandle<()>>,
+    latest: RequestId,
+    // Of the latest request, until it is loaded or fails
+    progress: Option<LoadProgress>,
+    status: Option<(String, LoadPhase)>,
 }
 
 impl Loader {
     pub fn new() -> Self {
         let (message_sender, message_receiver) = mpsc::channel();
-        let (model_sender, model_receiver) = mpsc::channel();
+        let (event_sender, event_receiver) = mpsc::channel();
+        let model_sender = event_sender.clone();
         let thread_handle = Some(thread::spawn(move || {
             info!("Starting loader");
+            let mut next = None;
             loop {
-                let message = message_receiver.recv().expect("Failed to receive a path");
+                let message = match next.take() {
+                    Some(message) => message,
+                    None => message_receiver.recv().expect("Failed to receive a path"),
+                };
                 match message {
-                    Message::Load(path) => {
+                    Message::Load(id, path, options, progress) => {
+                        // Only the last queued request is loaded
+                        if let Ok(newer) = message_receiver.try_recv() {
+                            next = Some(newer);
+                            continue;
+                        }
                         info!("Start loading {}", path);
-                        let pre_loaded_model = load_file(&path);
+                        let pre_loaded_model = load_file_with_progress(&path, &options, &progress);
 
-                        match pre_loaded_model {
+                        let event = match pre_loaded_model {
                             Ok(pre_loaded_model) => {
                                 info!("Finish loading {}", path);
-                                model_sender.send(pre_loaded_model).unwrap();
+                                Event::Loaded(id, Box::new(pre_loaded_model))
+                            }
+                            Err(_) if progress.is_cancelled() => {
+                                info!("Cancelled loading {}", path);
+                                Event::Stopped(id)
                             }
                             Err(error) => {
                                 error!("Failed to load {}. Cause: {:?}", path, error);
+                                Event::Stopped(id)
                             }
-                        }
+                        };
+                        // The loader may be dropped while loading
+                        let _ = model_sender.send(event);
                     }
                     Message::Stop => break,
                 }
@@ -62,28 +96,69 @@ impl Loader {
 
         Self {
             message_sender,
-            model_receiver,
+            event_sender,
+            event_receiver,
             thread_handle,
+            latest: 0,
+            progress: None,
+            status: None,
         }
     }
 
-    /// Start loading a new model in the background.
+    /// Start loading a new model in the background, cancelling the previous request.
     ///
     /// Call `get_model` to retrieve the loaded model.
-    pub fn load(&self, path: String) {
+    pub fn load(&mut self, path: String, options: LoadOptions) {
+        if let Some(progress) = self.progress.take() {
+            progress.cancel();
+        }
+        self.latest += 1;
+        let id = self.latest;
+        let sender = self.event_sender.clone();
+        let progress = LoadProgress::new(move |phase| {
+            let _ = sender.send(Event::Phase(id, phase));
+        });
+        self.progress = Some(progress.clone());
+        self.status = Some((path.clone(), LoadPhase::Parse));
         self.message_sender
-            .send(Message::Load(path))
+            .send(Message::Load(id, path, options, progress))
             .expect("Failed to send load message to loader");
     }
 
-    /// Get the last loaded model.
+    /// Get the model of the latest request once it is loaded.
     ///
     /// If no model is ready, then `None` is returned.
-    pub fn get_model(&self) -> Option<Doc> {
-        match self.model_receiver.try_recv() {
-            Ok(pre_loaded_model) => Some(pre_loaded_model),
-            _ => None,
+    pub fn get_model(&mut self) -> Option<Doc> {
+        let mut model = None;
+        while let Ok(event) = self.event_receiver.try_recv() {
+            match event {
+                Event::Phase(id, phase) if id == self.latest => {
+                    if let Some(status) = &mut self.status {
+                        status.1 = phase;
+                    }
+                }
+                Event::Loaded(id, pre_loaded_model) if id == self.latest => {
+                    model = Some(*pre_loaded_model);
+                    self.finish();
+                }
+                Event::Stopped(id) if id == self.latest => self.finish(),
+                // Superseded requests
+                _ => {}
+            }
         }
+        model
+    }
+
+    /// Path and phase of the latest request while it is loading
+    pub fn status(&self) -> Option<(&str, LoadPhase)> {
+        self.status
+            .as_ref()
+            .map(|(path, phase)| (path.as_str(), *phase))
+    }
+
+    fn finish(&mut self) {
+        self.progress = None;
+        self.status = None;
     }
 }
 
@@ -98,6 +173,9 @@ impl Loader {
 
 impl Drop for Loader {
     fn drop(&mut self) {
+        if let Some(progress) = self.progress.take() {
+            progress.cancel();
+        }
         self.message_sender
             .send(Message::Stop)
             .expect("Failed to send stop message to loader thread");
@@ -1,5 +1,7 @@
 use app::anyhow::Result;
 use app::camera::Camera;
+use app::na::Vector4;
+use app::types::Mat4;
 use std::default::Default;
 
 use app::vulkan::ash::vk::{self};
@@ -7,9 +9,9 @@ use app::vulkan::gpu_allocator::MemoryLocation;
 use app::{vulkan::*, BaseApp};
 use app::{App, FrameStats};
 use std::mem::size_of;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use log::info;
+use log::{error, info};
 use std::time::Instant;
 
 mod args;
@@ -25,9 +27,10 @@ use crate::compute_unit::ComputeUnit;
 use crate::gui_state::{Scene, Skybox};
 use crate::loader::Loader;
 use asset_loader::acceleration_structures::{create_as, create_top_as, TopAS};
+use asset_loader::camera::{CameraView, Projection};
 use asset_loader::globals::{create_global, Buffers, SkyboxResource, VkGlobal};
 use asset_loader::light::LightRaw;
-use asset_loader::{load_file, Doc};
+use asset_loader::{load_file, Bvh, Doc, LoadOptions, Ray};
 use desc_sets::*;
 use gui_state::Gui;
 use pipeline_res::*;
@@ -44,6 +47,7 @@ const VERTEX_BIND: u32 = 3;
 const INDEX_BIND: u32 = 4;
 const GEO_BIND: u32 = 5;
 const TEXTURE_BIND: u32 = 6;
+const ATTRIBUTE_BIND: u32 = 7;
 const ACC_BIND: u32 = 8;
 const MAT_BIND: u32 = 9;
 const DLIGHT_BIND: u32 = 10;
@@ -51,6 +55,12 @@ const PLIGHT_BIND: u32 = 11;
 const SKYBOX_BIND: u32 = 12;
 const ANIMATION_BIND: u32 = 13;
 const SKIN_BIND: u32 = 14;
+const COLOR_BIND: u32 = 15;
+const ANIMATION_ATTRIBUTE_BIND: u32 = 16;
+const ANIMATED_VERTEX_BIND: u32 = 17;
+const MORPH_BIND: u32 = 18;
+const MORPH_DELTA_BIND: u32 = 19;
+const MORPH_WEIGHT_BIND: u32 = 20;
 const ENABLE_RAYTRACING: bool = true;
 
 fn main() -> Result<()> {
@@ -68,6 +78,10 @@ struct GltfViewerInner {
     buffers: Buffers,
     globals: VkGlobal,
     compute_unit: Option<ComputeUnit>,
+    // Whether the GUI shows the variants, cameras and load report of this doc
+    gui_synced: bool,
+    // CPU picking structure, built on the first click after a change
+    bvh: Option<Bvh>,
 }
 
 impl GltfViewerInner {
@@ -84,7 +98,7 @@ impl GltfViewerInner {
         let buffers = Buffers::new(context, &doc.geo_builder, &globals)?;
         let compute_unit = if buffers.animation_buffers.is_some() {
             let compute_unit = ComputeUnit::new(context, &buffers)?;
-            compute_unit.dispatch(context, &buffers, doc.geo_builder.vertices.len() as u32)?;
+            compute_unit.dispatch(context, &buffers)?;
             Some(compute_unit)
         } else {
             None
@@ -124,6 +138,8 @@ impl GltfViewerInner {
             buffers,
             globals,
             compute_unit,
+            gui_synced: false,
+            bvh: None,
         })
     }
 }
@@ -134,7 +150,6 @@ struct GltfViewer {
     prev_gui_state: Option<Gui>,
     old_camera: Option<Camera>,
 
-    clock: Instant,
     last_update: Instant,
     loader: Loader,
     inner: Vec<GltfViewerInner>,
@@ -142,8 +157,17 @@ struct GltfViewer {
 }
 
 impl GltfViewer {
-    fn new_with_scene(base: &BaseApp<Self>, scene: Scene, loader: Loader) -> Result<Self> {
-        let doc = load_file(scene.path())?;
+    fn new_with_scene(
+        base: &BaseApp<Self>,
+        scene: Scene,
+        options: &LoadOptions,
+        loader: Loader,
+        export: &str,
+    ) -> Result<Self> {
+        let doc = load_file(scene.path(), options)?;
+        if !export.is_empty() {
+            doc.export(export)?;
+        }
         Self::new_with_doc(base, doc, loader)
     }
 
@@ -163,7 +187,6 @@ impl GltfViewer {
             total_number_of_samples: 0,
             old_camera: None,
             prev_gui_state: None,
-            clock: Instant::now(),
             last_update: Instant::now(),
             loader,
             skybox,
@@ -178,12 +201,13 @@ impl App for GltfViewer {
     fn new(base: &BaseApp<Self>) -> Result<Self> {
         use clap::Parser;
         let args = Args::parse();
+        let options = args.load_options();
         let scene = if args.file.is_empty() {
             Default::default()
         } else {
             Scene::DragAndDrop(args.file)
         };
-        Self::new_with_scene(base, scene, Loader::new())
+        Self::new_with_scene(base, scene, &options, Loader::new(), &args.export)
     }
 
     fn update(
@@ -194,15 +218,8 @@ impl App for GltfViewer {
         frame_stats: &FrameStats,
     ) -> Result<()> {
         self.state_change(base, gui)?;
-        let view = base.camera.view_matrix()
-            * if gui.scale > 0. {
-                gui.scale
-            } else {
-                1. / (gui.scale.abs() + 1.)
-            };
+        let (view, proj) = view_projection(&base.camera, gui);
         let inverted_view = view.try_inverse().expect("Should be invertible");
-
-        let proj = base.camera.projection_matrix();
         let inverted_proj = proj.try_inverse().expect("Should be invertible");
         let number_of_samples =
             gui.get_number_of_samples(self.total_number_of_samples, frame_stats.frame_time);
@@ -212,6 +229,7 @@ impl App for GltfViewer {
         }
         self.total_number_of_samples += number_of_samples;
 
+        let orthographic = gui.orthographic();
         let ubo = UniformBufferObject {
             model_view: view,
             projection: proj,
@@ -220,7 +238,8 @@ impl App for GltfViewer {
             aperture: gui.aperture,
             focus_distance: gui.focus_distance,
             fov_angle: 1.0,
-            orthographic_fov_dis: gui.orthographic_fov_dis,
+            orthographic_xmag: orthographic.map_or(0., |[x, _]| x),
+            orthographic_ymag: orthographic.map_or(0., |[_, y]| y),
             heatmap_scale: gui.map_scale,
             total_number_of_samples: self.total_number_of_samples,
             number_of_samples,
@@ -234,6 +253,7 @@ impl App for GltfViewer {
             fully_opaque: self.get_inner_ref().doc.geo_builder.fully_opaque().into(),
             exposure: gui.exposure,
             tone_mapping_mode: gui.selected_tone_map_mode as _,
+            frame_aspect: gui.camera_aspect.unwrap_or(0.),
         };
 
         self.ubo_buffer.copy_data_to_buffer(&[ubo])?;
@@ -307,7 +327,23 @@ impl App for GltfViewer {
     fn drag_and_drop(&mut self, path: PathBuf, gui: &mut Gui) {
         let path = path.into_os_string().into_string().unwrap_or("".to_owned());
         gui.scene = Scene::DragAndDrop(path.clone());
-        self.loader.load(path);
+        self.loader.load(path, gui.load_options.clone());
+    }
+
+    fn on_click(&mut self, base: &mut BaseApp<Self>, position: [f32; 2], gui: &mut Gui) {
+        let Some(ray) = pick_ray(&base.camera, gui, position) else {
+            return;
+        };
+        let inner = self.get_inner_mut();
+        let bvh = inner.bvh.get_or_insert_with(|| inner.doc.build_bvh());
+        gui.selection = bvh.intersect(&ray, f32::MAX).map_or(vec![], |hit| {
+            inner
+                .doc
+                .hit_properties(&hit)
+                .into_iter()
+                .map(|(label, value)| (label.to_string(), value))
+                .collect()
+        });
     }
 
     fn state_change(
@@ -326,6 +362,60 @@ impl App for GltfViewer {
             )?);
             self.reset_samples();
         }
+        // The old scene keeps rendering while loading
+        let loading = self.loader.status().map(|(path, phase)| {
+            let name = Path::new(path).file_name().unwrap_or_default();
+            let label = format!("{} ({:?})", name.to_string_lossy(), phase);
+            (label, phase.fraction())
+        });
+        if gui_state.loading != loading {
+            if let Some(prev) = &mut self.prev_gui_state {
+                prev.loading = loading.clone();
+            }
+            gui_state.loading = loading;
+        }
+        if !self.get_inner_ref().gui_synced {
+            let inner = self.get_inner_mut();
+            gui_state.variants = inner.doc.variants.clone();
+            gui_state.material_variant = inner.doc.material_variant();
+            gui_state.cameras = inner
+                .doc
+                .get_cameras()
+                .into_iter()
+                .map(|c| c.name)
+                .collect();
+            gui_state.selected_camera = None;
+            gui_state.camera_aspect = None;
+            gui_state.camera_magnification = None;
+            gui_state.gltf_scenes = inner.doc.scene_names();
+            gui_state.gltf_scene = inner.doc.current_scene();
+            gui_state.load_report = inner
+                .doc
+                .report
+                .sections()
+                .into_iter()
+                .map(|(title, lines)| (title.to_string(), lines))
+                .collect();
+            gui_state.load_timings = inner.doc.report.timing_lines();
+            gui_state.selection = vec![];
+            gui_state.animations = inner
+                .doc
+                .animation_names()
+                .into_iter()
+                .zip(&inner.doc.animations)
+                .map(|(name, a)| (name, a.duration()))
+                .collect();
+            // The clip fading in, or playing alone
+            let clip = inner.doc.player.clips.last();
+            gui_state.animation_clip = clip.map_or(0, |c| c.animation);
+            gui_state.animation_time = clip.map_or(0., |c| c.time);
+            // Options given on the command line, without reloading
+            gui_state.load_options = inner.doc.options().clone();
+            inner.gui_synced = true;
+            if let Some(prev) = &mut self.prev_gui_state {
+                prev.load_options = gui_state.load_options.clone();
+            }
+        }
         if self.old_camera.is_none() {
             self.old_camera = Some(base.camera);
         }
@@ -338,9 +428,33 @@ impl App for GltfViewer {
             self.reset_samples();
         }
 
+        if gui_state.export {
+            gui_state.export = false;
+            // With the selected variant baked in
+            let doc = &self.get_inner_ref().doc;
+            let path = if gui_state.export_path.is_empty() {
+                let source = doc.source();
+                let stem = source
+                    .file_stem()
+                    .map_or("scene".into(), |s| s.to_string_lossy().to_string());
+                source.with_file_name(format!("{}_export.glb", stem))
+            } else {
+                PathBuf::from(&gui_state.export_path)
+            };
+            match doc.export(&path) {
+                Ok(()) => info!("Exported {}", path.display()),
+                Err(e) => error!("Failed to export {}: {}", path.display(), e),
+            }
+        }
+
+        // Animations are posed without advancing while paused
+        let mut scrubbed = false;
         if let Some(old_state) = self.prev_gui_state.clone().filter(|x| x != gui_state) {
-            if old_state.scene != gui_state.scene {
-                self.loader.load(gui_state.scene.path());
+            if old_state.scene != gui_state.scene
+                || old_state.load_options != gui_state.load_options
+            {
+                self.loader
+                    .load(gui_state.scene.path(), gui_state.load_options.clone());
                 // *self = Self::new_with_scene(base, gui_state.scene, gui_state.skybox, self.loader.clone()).unwrap();
             }
             if old_state.skybox != gui_state.skybox {
@@ -350,6 +464,58 @@ impl App for GltfViewer {
             }
             self.prev_gui_state = Some(gui_state.clone());
             self.reset_samples();
+            if old_state.material_variant != gui_state.material_variant
+                && self.get_inner_ref().doc.material_variant() != gui_state.material_variant
+            {
+                base.wait_for_gpu()?;
+                let inner = self.get_inner_mut();
+                inner.doc.set_material_variant(gui_state.material_variant);
+                inner.bvh = None;
+                inner.buffers.update_prim_info(&inner.doc)?;
+                // Opacity flags of the geometries may have changed
+                let (blas, tlas) = create_as(
+                    &base.context,
+                    &inner.doc,
+                    &inner.buffers,
+                    vk::BuildAccelerationStructureFlagsKHR::empty(),
+                )?;
+                inner._bottom_as = blas;
+                self.update_tlas(tlas);
+            }
+            if old_state.gltf_scene != gui_state.gltf_scene
+                && self.get_inner_ref().doc.current_scene() != gui_state.gltf_scene
+            {
+                base.wait_for_gpu()?;
+                self.switch_scene(&base.context, gui_state.gltf_scene)?;
+                // Cameras differ between scenes
+                self.get_inner_mut().gui_synced = false;
+            }
+            if !self.get_inner_ref().doc.static_scene() {
+                let player = &mut self.get_inner_mut().doc.player;
+                if old_state.animation_clip != gui_state.animation_clip {
+                    player.crossfade(gui_state.animation_clip, gui_state.animation_crossfade);
+                    scrubbed = true;
+                } else if old_state.animation_time != gui_state.animation_time {
+                    if let Some(clip) = player.clip_mut(gui_state.animation_clip) {
+                        clip.time = gui_state.animation_time;
+                    }
+                    scrubbed = true;
+                }
+                if old_state.animation_playing != gui_state.animation_playing {
+                    self.last_update = Instant::now();
+                }
+            }
+            if old_state.selected_camera != gui_state.selected_camera {
+                let view = gui_state
+                    .selected_camera
+                    .and_then(|c| self.get_inner_ref().doc.get_cameras().into_iter().nth(c));
+                if let Some(view) = view {
+                    set_camera(&mut base.camera, &view, gui_state);
+                } else {
+                    gui_state.camera_aspect = None;
+                    gui_state.camera_magnification = None;
+                }
+            }
             if old_state.sun != gui_state.sun {
                 let inner = self.get_inner_mut();
                 inner.globals.d_lights[0] = gui_state.sun;
@@ -374,16 +540,40 @@ impl App for GltfViewer {
             }
         }
 
-        if !self.get_inner_ref().doc.static_scene() && gui_state.animation && self.need_update() {
+        if !self.get_inner_ref().doc.static_scene()
+            && (scrubbed || gui_state.animation_playing && self.need_update())
+        {
+            let dt = if gui_state.animation_playing {
+                self.last_update.elapsed().as_secs_f32()
+            } else {
+                0.
+            };
             self.last_update = Instant::now();
-            let t = self.clock.elapsed().as_secs_f32() * gui_state.animation_speed;
-            self.get_inner_mut().doc.animate(t);
+            let doc = &mut self.get_inner_mut().doc;
+            if let Some(clip) = doc.player.clip_mut(gui_state.animation_clip) {
+                clip.speed = gui_state.animation_speed;
+                clip.mode = gui_state.animation_mode;
+            }
+            doc.update_animations(dt);
+            self.get_inner_mut().bvh = None;
+            let doc = &self.get_inner_ref().doc;
+            // The time slider follows the selected clip
+            let time = doc
+                .player
+                .clip(gui_state.animation_clip)
+                .map_or(0., |c| c.time);
+            gui_state.animation_time = time;
+            if let Some(prev) = &mut self.prev_gui_state {
+                prev.animation_time = time;
+            }
             let mut blas_opt = None;
             let tlas = {
                 let inner = self.get_inner_ref();
-                if let Some((skin, _ani)) = &inner.buffers.animation_buffers {
+                if let Some(ani) = &inner.buffers.animation_buffers {
                     let new_skin = inner.doc.get_skins();
-                    skin.copy_data_to_buffer(&new_skin)?;
+                    ani.joints.copy_data_to_buffer(&new_skin)?;
+                    ani.morph_weights
+                        .copy_data_to_buffer(&inner.doc.get_morph_weights())?;
                     self.compute(&base.context)?;
                     let (blas, tlas) = create_as(
                         &base.context,
@@ -413,6 +603,24 @@ impl App for GltfViewer {
 }
 
 impl GltfViewer {
+    fn switch_scene(&mut self, context: &Context, scene: usize) -> Result<()> {
+        let inner = self.get_inner_mut();
+        inner.doc.set_scene(scene);
+        inner.bvh = None;
+        [inner.globals.d_lights, inner.globals.p_lights] = inner.doc.get_lights_raw();
+        inner.buffers.update_lights(context, &inner.globals)?;
+        update_light_desc(&inner.descriptor_res.static_set, &inner.buffers);
+        let tlas = create_top_as(
+            context,
+            &inner.doc,
+            &inner._bottom_as,
+            vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE,
+            None,
+        )?;
+        self.update_tlas(tlas);
+        Ok(())
+    }
+
     fn get_inner_mut(&mut self) -> &mut GltfViewerInner {
         &mut self.inner[0]
     }
@@ -446,9 +654,102 @@ impl GltfViewer {
     fn compute(&self, context: &Context) -> Result<()> {
         let inner = self.get_inner_ref();
         if let Some(compute) = &inner.compute_unit {
-            let vertex_count = inner.doc.geo_builder.vertices.len() as u32;
-            compute.dispatch(context, &inner.buffers, vertex_count)?;
+            compute.dispatch(context, &inner.buffers)?;
         }
         Ok(())
     }
 }
+
+/// View and projection matrices as used by the ray generation shader.
+/// The projection has the aspect ratio of the selected glTF camera, the window keeps its own.
+fn view_projection(camera: &Camera, gui: &Gui) -> (Mat4, Mat4) {
+    let scale = if gui.scale > 0. {
+        gui.scale
+    } else {
+        1. / (gui.scale.abs() + 1.)
+    };
+    let frame = Camera {
+        aspect_ratio: gui.camera_aspect.unwrap_or(camera.aspect_ratio),
+        ..*camera
+    };
+    (camera.view_matrix() * scale, frame.projection_matrix())
+}
+
+/// Window uv in [-1, 1] to the uv of the letterboxed frame, None in the bars
+fn frame_uv(uv: [f32; 2], window_aspect: f32, frame_aspect: Option<f32>) -> Option<[f32; 2]> {
+    let Some(frame_aspect) = frame_aspect else {
+        return Some(uv);
+    };
+    let [x, y] = uv;
+    let uv = if window_aspect > frame_aspect {
+        [x * window_aspect / frame_aspect, y]
+    } else {
+        [x, y * frame_aspect / window_aspect]
+    };
+    uv.iter().all(|c| c.abs() <= 1.).then_some(uv)
+}
+
+/// Camera ray through a window position, without depth of field
+fn pick_ray(camera: &Camera, gui: &Gui, [x, y]: [f32; 2]) -> Option<Ray> {
+    let (view, proj) = view_projection(camera, gui);
+    let inverted_view = view.try_inverse()?;
+    let uv = frame_uv(
+        [x * 2. - 1., y * 2. - 1.],
+        camera.aspect_ratio,
+        gui.camera_aspect,
+    )?;
+    let (origin, direction) = if let Some([xmag, ymag]) = gui.orthographic() {
+        (
+            Vector4::new(uv[0] * xmag, -uv[1] * ymag, 0., 1.),
+            Vector4::new(0., 0., -1., 0.),
+        )
+    } else {
+        let target = proj.try_inverse()? * Vector4::new(uv[0], uv[1], 1., 1.);
+        (
+            Vector4::new(0., 0., 0., 1.),
+            target.xyz().normalize().push(0.),
+        )
+    };
+    let [origin, direction] = [origin, direction].map(|v| {
+        let v = inverted_view * v;
+        glam::Vec3::new(v.x, v.y, v.z)
+    });
+    Some(Ray {
+        origin,
+        direction: direction.normalize(),
+    })
+}
+
+/// Move the viewer camera to a glTF camera.
+/// The window keeps its aspect ratio, the frame is letterboxed to the one of the camera.
+fn set_camera(camera: &mut Camera, view: &CameraView, gui: &mut Gui) {
+    let [x, y, z] = view.position.to_array();
+    camera.position = app::types::Point::new(x, y, z);
+    let [x, y, z] = view.direction.to_array();
+    camera.direction = app::types::Vec3::new(x, y, z);
+    match view.projection {
+        Projection::Perspective {
+            yfov,
+            aspect_ratio,
+            znear,
+            zfar,
+        } => {
+            camera.fov = yfov.to_degrees();
+            camera.z_near = znear;
+            camera.z_far = zfar.unwrap_or(camera.z_far.max(znear * 1000.));
+            gui.camera_aspect = aspect_ratio;
+            gui.camera_magnification = None;
+        }
+        Projection::Orthographic {
+            xmag,
+            ymag,
+            znear,
+            zfar,
+        } => {
+            camera.z_near = znear;
+            camera.z_far = zfar;
+            gui.camera_aspect = Some(xmag / ymag);
+            gui.camera_magnification = Some([xmag, ymag]);
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use crate::{
-    ACC_BIND, AS_BIND, DLIGHT_BIND, GEO_BIND, INDEX_BIND, MAT_BIND, PLIGHT_BIND, SKYBOX_BIND,
-    STORAGE_BIND, TEXTURE_BIND, UNIFORM_BIND, VERTEX_BIND,
+    ACC_BIND, AS_BIND, ATTRIBUTE_BIND, COLOR_BIND, DLIGHT_BIND, GEO_BIND, INDEX_BIND, MAT_BIND,
+    PLIGHT_BIND, SKYBOX_BIND, STORAGE_BIND, TEXTURE_BIND, UNIFORM_BIND, VERTEX_BIND,
 };
 use app::anyhow::Result;
 use app::load_spv;
@@ -43,13 +43,25 @@ pub fn create_pipeline(
                     | vk::ShaderStageFlags::MISS_KHR,
             )
             .build(),
-        // Vertex buffer
+        // Vertex streams
         vk::DescriptorSetLayoutBinding::builder()
             .binding(VERTEX_BIND)
             .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
             .descriptor_count(1)
             .stage_flags(primary_hit_group_flags)
             .build(),
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(ATTRIBUTE_BIND)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .descriptor_count(1)
+            .stage_flags(primary_hit_group_flags)
+            .build(),
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(COLOR_BIND)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .descriptor_count(1)
+            .stage_flags(primary_hit_group_flags)
+            .build(),
         //Index buffer
         vk::DescriptorSetLayoutBinding::builder()
             .binding(INDEX_BIND)
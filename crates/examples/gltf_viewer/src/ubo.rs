@@ -11,7 +11,9 @@ pub struct UniformBufferObject {
     pub(crate) aperture: f32,
     pub(crate) focus_distance: f32,
     pub fov_angle: f32,
-    pub orthographic_fov_dis: f32,
+    // Zero for a perspective view
+    pub orthographic_xmag: f32,
+    pub orthographic_ymag: f32,
     pub(crate) heatmap_scale: f32,
     pub(crate) total_number_of_samples: u32,
 
@@ -28,4 +30,6 @@ pub struct UniformBufferObject {
     pub fully_opaque: u32,
     pub exposure: f32,
     pub tone_mapping_mode: u32,
+    // Letterboxed to this aspect ratio, zero fills the window
+    pub frame_aspect: f32,
 }
@@ -97,6 +97,8 @@ pub trait App: Sized {
         Ok(())
     }
     fn drag_and_drop(&mut self, path: PathBuf, gui: &mut Self::Gui);
+    /// Left click outside the GUI, in window coordinates from 0 to 1 with y down
+    fn on_click(&mut self, _base: &mut BaseApp<Self>, _position: [f32; 2], _gui: &mut Self::Gui) {}
 }
 
 pub trait Gui: Sized + Clone {
@@ -153,6 +155,7 @@ pub fn run<A: App + 'static>(
     let mut is_swapchain_dirty = false;
     let mut last_frame = Instant::now();
     let mut frame_stats = FrameStats::default();
+    let mut cursor_position = [0.; 2];
 
     event_loop.run(move |event, _, control_flow| {
         *control_flow = ControlFlow::Poll;
@@ -225,6 +228,10 @@ pub fn run<A: App + 'static>(
                 ..
             } => app.drag_and_drop(path, &mut ui),
             // Mouse
+            Event::WindowEvent {
+                event: WindowEvent::CursorMoved { position, .. },
+                ..
+            } => cursor_position = [position.x as f32, position.y as f32],
             Event::WindowEvent {
                 event: WindowEvent::MouseInput { state, button, .. },
                 ..
@@ -236,6 +243,17 @@ pub fn run<A: App + 'static>(
                         window.set_cursor_visible(true);
                     }
                 }
+                if button == MouseButton::Left
+                    && state == ElementState::Pressed
+                    && !gui_context.imgui.io().want_capture_mouse
+                {
+                    let size = window.inner_size();
+                    let position = [
+                        cursor_position[0] / size.width.max(1) as f32,
+                        cursor_position[1] / size.height.max(1) as f32,
+                    ];
+                    app.on_click(&mut base_app, position, &mut ui);
+                }
             }
             // Exit app on request to close window
             Event::WindowEvent {
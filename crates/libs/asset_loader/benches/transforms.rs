@@ -0,0 +1,84 @@
+//! Animation update cost on a synthetic deep hierarchy: `cargo bench -p asset_loader`
+use asset_loader::{load_file, LoadOptions};
+use glam::{Quat, Vec3};
+use serde_json::json;
+use std::path::PathBuf;
+use std::time::Instant;
+
+const CHAINS: usize = 16;
+const DEPTH: usize = 128;
+const ANGLE: f32 = 0.1;
+const ITERATIONS: u32 = 1000;
+
+/// Chains of nodes under one root, every node rotated by the same channel sampler
+fn write_hierarchy() -> PathBuf {
+    let floats: Vec<f32> = [0., 1.]
+        .into_iter()
+        .chain(Quat::IDENTITY.to_array())
+        .chain(Quat::from_rotation_z(ANGLE).to_array())
+        .collect();
+    let bytes: Vec<u8> = floats.iter().flat_map(|f| f.to_le_bytes()).collect();
+    let uri = format!(
+        "data:application/octet-stream;base64,{}",
+        base64::encode(&bytes)
+    );
+
+    let node = |n: usize| n + 1;
+    let mut nodes =
+        vec![json!({ "children": (0..CHAINS).map(|c| node(c * DEPTH)).collect::<Vec<_>>() })];
+    for c in 0..CHAINS {
+        for d in 0..DEPTH {
+            let mut n = json!({ "translation": [0., 1., 0.] });
+            if d + 1 < DEPTH {
+                n["children"] = json!([node(c * DEPTH + d + 1)]);
+            }
+            nodes.push(n);
+        }
+    }
+    let channels: Vec<_> = (1..nodes.len())
+        .map(|n| json!({"sampler": 0, "target": {"node": n, "path": "rotation"}}))
+        .collect();
+    let root = json!({
+        "asset": {"version": "2.0"},
+        "scene": 0,
+        "scenes": [{"nodes": [0]}],
+        "nodes": nodes,
+        "animations": [{
+            "channels": channels,
+            "samplers": [{"input": 0, "output": 1, "interpolation": "LINEAR"}],
+        }],
+        "buffers": [{"byteLength": bytes.len(), "uri": uri}],
+        "bufferViews": [
+            {"buffer": 0, "byteOffset": 0, "byteLength": 8},
+            {"buffer": 0, "byteOffset": 8, "byteLength": 32},
+        ],
+        "accessors": [
+            {"bufferView": 0, "componentType": 5126, "count": 2, "type": "SCALAR", "min": [0.], "max": [1.]},
+            {"bufferView": 1, "componentType": 5126, "count": 2, "type": "VEC4"},
+        ],
+    });
+    let path = std::env::temp_dir().join("rustracer_transform_bench.gltf");
+    std::fs::write(&path, root.to_string()).unwrap();
+    path
+}
+
+fn main() {
+    let mut doc = load_file(write_hierarchy(), &LoadOptions::default()).unwrap();
+
+    // Half way, every level adds half the angle
+    doc.update_animations(0.5);
+    let leaf = doc.nodes[DEPTH].get_world_transform();
+    let expected = Quat::from_rotation_z(DEPTH as f32 * ANGLE / 2.) * Vec3::X;
+    assert!((leaf.transform_vector3(Vec3::X) - expected).length() < 1e-3);
+
+    let now = Instant::now();
+    for _ in 0..ITERATIONS {
+        doc.update_animations(1. / 60.);
+    }
+    println!(
+        "{} nodes, depth {}: {:?} per animation update",
+        doc.nodes.len(),
+        DEPTH,
+        now.elapsed() / ITERATIONS
+    );
+}
@@ -1,9 +1,10 @@
 use glam::{Mat4, Vec3, Vec4};
 use gltf::mesh::Bounds;
+use serde::{Deserialize, Serialize};
 use std::ops::Mul;
 
 /// Axis aligned bounding box.
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub struct Aabb {
     min: Vec3,
     max: Vec3,
@@ -62,6 +63,32 @@ impl Aabb {
         self.min + (self.max - self.min) / two
     }
 
+    pub fn min(&self) -> Vec3 {
+        self.min
+    }
+
+    pub fn max(&self) -> Vec3 {
+        self.max
+    }
+
+    /// Smallest AABB around the points, or an inverted one without points
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Self {
+        points.into_iter().fold(
+            Aabb::new(Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+            |aabb, p| Aabb::new(aabb.min.min(p), aabb.max.max(p)),
+        )
+    }
+
+    /// Distance along the ray where it enters the AABB, if before `t_max`.
+    /// `inv_direction` is the component-wise inverse of the ray direction.
+    pub fn ray_distance(&self, origin: Vec3, inv_direction: Vec3, t_max: f32) -> Option<f32> {
+        let t0 = (self.min - origin) * inv_direction;
+        let t1 = (self.max - origin) * inv_direction;
+        let near = t0.min(t1).max_element().max(0.);
+        let far = t0.max(t1).min_element().min(t_max);
+        (near <= far).then_some(near)
+    }
+
     pub fn get_transform(&self) -> Mat4 {
         let translation = Mat4::from_translation(-self.get_center());
         let scale = Mat4::from_scale(Vec3::splat(10. / self.get_larger_side_size()));
@@ -1,4 +1,5 @@
-use crate::geometry::{GeoBuilder, Vertex};
+use crate::compact::IndexLayout;
+use crate::geometry::GeoBuilder;
 use crate::globals::Buffers;
 
 use crate::scene_graph::{Doc, Node};
@@ -18,17 +19,18 @@ fn primitive_to_vk_geometry(
     _context: &Context,
     buffers: &Buffers,
     geo_builder: &GeoBuilder,
+    index_layout: IndexLayout,
     geo_id: u32,
 ) -> BlasInput {
-    let vertex_buffer = if let Some(ani) = &buffers.animation_buffers {
-        &ani.1
-    } else {
-        &buffers.vertex_buffer
-    };
-    let vertex_buffer_addr = vertex_buffer.get_device_address();
+    let vertex_buffer_addr = buffers.positions().get_device_address();
     let index_buffer_addr = buffers.index_buffer.get_device_address();
     let [v_len, i_len] = geo_builder.len[geo_id as usize];
-    let [v_offset, i_offset, _mat] = geo_builder.offsets[geo_id as usize];
+    let [v_offset, _, _mat] = geo_builder.offsets[geo_id as usize];
+    let (index_type, index_size) = if index_layout.u16 {
+        (vk::IndexType::UINT16, size_of::<u16>())
+    } else {
+        (vk::IndexType::UINT32, size_of::<u32>())
+    };
     let is_opaque = geo_builder.is_opaque(geo_id);
     assert_eq!(i_len % 3, 0);
     let primitive_count = (i_len / 3) as u32;
@@ -37,9 +39,9 @@ fn primitive_to_vk_geometry(
         .vertex_data(vk::DeviceOrHostAddressConstKHR {
             device_address: vertex_buffer_addr,
         })
-        .vertex_stride(size_of::<Vertex>() as _)
+        .vertex_stride(size_of::<[f32; 3]>() as _)
         .max_vertex(v_len as _)
-        .index_type(vk::IndexType::UINT32)
+        .index_type(index_type)
         .index_data(vk::DeviceOrHostAddressConstKHR {
             device_address: index_buffer_addr,
         })
@@ -63,7 +65,7 @@ fn primitive_to_vk_geometry(
     let build_range_info = vk::AccelerationStructureBuildRangeInfoKHR::builder()
         .first_vertex(v_offset)
         .primitive_count(primitive_count)
-        .primitive_offset(i_offset * size_of::<u32>() as u32)
+        .primitive_offset(index_layout.offset * index_size as u32)
         // .transform_offset((node_index * size_of::<vk::TransformMatrixKHR>()) as u32)
         .build();
     BlasInput {
@@ -82,11 +84,16 @@ pub fn create_as(
     buffers: &Buffers,
     flags: vk::BuildAccelerationStructureFlagsKHR,
 ) -> Result<(Vec<AccelerationStructure>, TopAS)> {
+    let (index_layout, _) = doc.geo_builder.index_layout();
     let mut blas_inputs: Vec<_> = doc
         .meshes
         .iter()
         .flat_map(|m| m.primitives.iter())
-        .map(|p| primitive_to_vk_geometry(context, buffers, &doc.geo_builder, p.geometry_id))
+        .map(|p| {
+            let geo_id = p.geometry_id;
+            let layout = index_layout[geo_id as usize];
+            primitive_to_vk_geometry(context, buffers, &doc.geo_builder, layout, geo_id)
+        })
         .collect();
     blas_inputs.sort_by_key(|b| b.geo_id);
     let cmd_buffer = context
@@ -142,31 +149,35 @@ pub fn create_top_as(
 ) -> Result<TopAS> {
     let mut ins = vec![];
     let mut f = |node: &Node| {
-        // Row major.
-        let transform = if node.skin.is_none() {
-            node.get_world_transform().transpose().to_cols_array()
-        } else {
-            Mat4::IDENTITY.to_cols_array()
-        };
-        let mut matrix = [0.; 12];
-        matrix.copy_from_slice(&transform[..12]);
         if let Some(mesh) = node.mesh.map(|m| &doc.meshes[m]) {
-            let transform_matrix = vk::TransformMatrixKHR { matrix };
-            let instances = mesh.primitives.iter().map(|p| {
-                let geo_id = p.geometry_id;
-                vk::AccelerationStructureInstanceKHR {
-                    transform: transform_matrix,
-                    instance_custom_index_and_mask: Packed24_8::new(geo_id, 0xFF),
-                    instance_shader_binding_table_record_offset_and_flags: Packed24_8::new(
-                        0,
-                        vk::GeometryInstanceFlagsKHR::TRIANGLE_FACING_CULL_DISABLE.as_raw() as _,
-                    ),
-                    acceleration_structure_reference: vk::AccelerationStructureReferenceKHR {
-                        device_handle: blases[geo_id as usize].address,
-                    },
-                }
-            });
-            ins.extend(instances);
+            let transforms = if node.skin.is_none() {
+                node.get_instance_transforms()
+            } else {
+                vec![Mat4::IDENTITY]
+            };
+            for transform in transforms {
+                // Row major.
+                let transform = transform.transpose().to_cols_array();
+                let mut matrix = [0.; 12];
+                matrix.copy_from_slice(&transform[..12]);
+                let transform_matrix = vk::TransformMatrixKHR { matrix };
+                let instances = mesh.primitives.iter().map(|p| {
+                    let geo_id = p.geometry_id;
+                    vk::AccelerationStructureInstanceKHR {
+                        transform: transform_matrix,
+                        instance_custom_index_and_mask: Packed24_8::new(geo_id, 0xFF),
+                        instance_shader_binding_table_record_offset_and_flags: Packed24_8::new(
+                            0,
+                            vk::GeometryInstanceFlagsKHR::TRIANGLE_FACING_CULL_DISABLE.as_raw()
+                                as _,
+                        ),
+                        acceleration_structure_reference: vk::AccelerationStructureReferenceKHR {
+                            device_handle: blases[geo_id as usize].address,
+                        },
+                    }
+                });
+                ins.extend(instances);
+            }
         }
     };
     doc.traverse_root_nodes(&mut f);
@@ -1,25 +1,34 @@
+use crate::export::{named, GltfBuffer};
 use crate::geometry::GeoBuilder;
 use crate::{get_name, Name, NodeID};
-use glam::{Quat, Vec3};
+use glam::{Quat, Vec3, Vec4};
 use gltf::animation::util::ReadOutputs;
 use gltf::animation::{Channel, Interpolation, Sampler};
+use gltf::scene::Transform;
 use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::ops::{Add, Mul};
 
 type Float3 = [f32; 3];
 
+#[derive(Serialize, Deserialize)]
 pub struct Animation {
     pub index: usize,
     name: Name,
     pub channels: Vec<AnimationChannel>,
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct AnimationChannel {
     pub target: NodeID,
     property: Property,
     input: Vec<f32>,
+    #[serde(with = "crate::cache::InterpolationDef")]
     interpolation: Interpolation,
 }
 
+#[derive(Serialize, Deserialize)]
 enum Property {
     Translation(Vec<Float3>),
     Rotation(Vec<[f32; 4]>),
@@ -37,12 +46,12 @@ impl Property {
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone)]
 pub enum PropertyOutput {
     Translation(Float3),
     Rotation([f32; 4]),
     Scale(Float3),
-    Morph,
+    Morph(Vec<f32>),
 }
 
 impl AnimationChannel {
@@ -65,139 +74,136 @@ impl AnimationChannel {
             }
         };
         let sampler = channel.sampler();
-        assert_eq!(input_len, property.len());
+        let interpolation = sampler.interpolation();
+        // Cubic spline keyframes hold an in-tangent, a value and an out-tangent.
+        // Morph weights are already grouped by keyframe.
+        let cubic = matches!(interpolation, Interpolation::CubicSpline);
+        let outputs_per_key = if cubic && !matches!(property, Property::Morph(_)) {
+            3
+        } else {
+            1
+        };
+        assert_eq!(input_len * outputs_per_key, property.len());
         Self {
             target: target_node,
             property,
             input,
-            interpolation: sampler.interpolation(),
+            interpolation,
         }
     }
 
-    pub fn get_transform(&self, t: f32) -> PropertyOutput {
-        let min = self.input[0];
+    pub(crate) fn animates_weights(&self) -> bool {
+        matches!(self.property, Property::Morph(_))
+    }
+
+    /// Keyframes around `t` and the factor between them.
+    /// Time is clamped to the input range, clips loop in `AnimationPlayer`.
+    fn keyframes(&self, t: f32) -> (usize, usize, f32) {
         let len = self.input.len();
+        let min = self.input[0];
         let max = self.input[len - 1];
-        let interval = max - min;
-        let t = if t > min {
-            (t - min) % interval + min
+        if len == 1 || max <= min {
+            return (0, 0, 0.);
+        }
+        let t = t.clamp(min, max);
+        // First keyframe after t
+        let e = self.input.partition_point(|&k| k <= t).clamp(1, len - 1);
+        let s = e - 1;
+        let interval = self.input[e] - self.input[s];
+        let factor = if interval > 0. {
+            (t - self.input[s]) / interval
         } else {
-            t
+            0.
         };
-        let mut s = 0;
-        let mut e = 0;
-        for (i, &d) in self.input[..len - 1].iter().enumerate() {
-            if t >= d && t <= self.input[i + 1] {
-                s = i;
-                e = s + 1;
-            }
-        }
+        (s, e, factor)
+    }
 
-        let s3 = s * 3;
-        let prev_time = self.input[s];
-        let next_time = self.input[e];
-        let factor = (t - prev_time) / (next_time - prev_time);
+    pub fn get_transform(&self, t: f32) -> PropertyOutput {
+        let (s, e, factor) = self.keyframes(t);
+        let interval = self.input[e] - self.input[s];
         let interpolation = self.interpolation;
         match &self.property {
-            Property::Translation(t) => {
-                let res = match interpolation {
-                    Interpolation::Linear => interpolate_lerp3(t[s], t[e], factor),
-                    Interpolation::Step => t[s],
-                    Interpolation::CubicSpline => cubic_spline(
-                        [t[s3], t[s3 + 1], t[s3 + 2]],
-                        prev_time,
-                        [t[s3 + 3], t[s3 + 4], t[s3 + 5]],
-                        next_time,
-                        factor,
-                    ),
-                };
-                PropertyOutput::Translation(res)
+            Property::Translation(t) => PropertyOutput::Translation(interpolate3(
+                t,
+                [s, e],
+                factor,
+                interval,
+                interpolation,
+            )),
+            Property::Scale(t) => {
+                PropertyOutput::Scale(interpolate3(t, [s, e], factor, interval, interpolation))
             }
             Property::Rotation(r) => {
-                let l = Quat::from_array(r[s]);
-                let right = Quat::from_array(r[e]);
+                let q = |i: usize| Quat::from_array(r[i]);
                 let res = match interpolation {
-                    Interpolation::Linear | Interpolation::CubicSpline => {
-                        l.slerp(right, factor).to_array()
+                    Interpolation::Step => q(s),
+                    Interpolation::Linear => q(s).slerp(q(e), factor),
+                    Interpolation::CubicSpline => {
+                        // Hermite on the components, then normalized as the spec requires
+                        let v = |k: usize| Vec4::from_array(r[k]);
+                        let key = |i: usize| [v(3 * i), v(3 * i + 1), v(3 * i + 2)];
+                        Quat::from_vec4(cubic_spline(key(s), key(e), interval, factor)).normalize()
                     }
-                    Interpolation::Step => r[s],
                 };
-                PropertyOutput::Rotation(res)
+                PropertyOutput::Rotation(res.to_array())
             }
-            Property::Scale(t) => {
+            Property::Morph(w) => {
                 let res = match interpolation {
-                    Interpolation::Linear => interpolate_lerp3(t[s], t[e], factor),
-                    Interpolation::Step => t[s],
-                    Interpolation::CubicSpline => cubic_spline(
-                        [t[s3], t[s3 + 1], t[s3 + 2]],
-                        prev_time,
-                        [t[s3 + 3], t[s3 + 4], t[s3 + 5]],
-                        next_time,
-                        factor,
-                    ),
+                    Interpolation::Linear => w[s]
+                        .iter()
+                        .zip(&w[e])
+                        .map(|(&l, &r)| l + (r - l) * factor)
+                        .collect(),
+                    Interpolation::Step => w[s].clone(),
+                    Interpolation::CubicSpline => {
+                        // Each keyframe holds in-tangents, values and out-tangents
+                        let n = w[s].len() / 3;
+                        let key = |k: usize, i: usize| [w[k][i], w[k][n + i], w[k][2 * n + i]];
+                        (0..n)
+                            .map(|i| cubic_spline(key(s, i), key(e, i), interval, factor))
+                            .collect()
+                    }
                 };
-                PropertyOutput::Scale(res)
-            }
-            Property::Morph(_) => {
-                warn!("Morph unimplemented. Ignore.");
-                PropertyOutput::Scale([1.; 3])
+                PropertyOutput::Morph(res)
             }
         }
     }
 }
 
-fn interpolate_lerp3(s: Float3, e: Float3, factor: f32) -> Float3 {
-    let start = Vec3::from_array(s);
-    let end = Vec3::from_array(e);
-    start.lerp(end, factor).to_array()
+fn interpolate3(
+    values: &[Float3],
+    [s, e]: [usize; 2],
+    factor: f32,
+    interval: f32,
+    interpolation: Interpolation,
+) -> Float3 {
+    let v = |i: usize| Vec3::from_array(values[i]);
+    let key = |i: usize| [v(3 * i), v(3 * i + 1), v(3 * i + 2)];
+    match interpolation {
+        Interpolation::Step => v(s),
+        Interpolation::Linear => v(s).lerp(v(e), factor),
+        Interpolation::CubicSpline => cubic_spline(key(s), key(e), interval, factor),
+    }
+    .to_array()
 }
 
-// Stole from Ben
-fn cubic_spline(
-    source: [Float3; 3],
-    source_time: f32,
-    target: [Float3; 3],
-    target_time: f32,
-    amount: f32,
-) -> Float3 {
-    let source = source.map(Vec3::from_array);
-    let target = target.map(Vec3::from_array);
-    let t = amount;
+/// Cubic Hermite spline from the glTF spec.
+/// Keyframes are [in-tangent, value, out-tangent], tangents are scaled by the interval.
+fn cubic_spline<T>(source: [T; 3], target: [T; 3], interval: f32, t: f32) -> T
+where
+    T: Copy + Add<Output = T> + Mul<f32, Output = T>,
+{
+    let t2 = t * t;
+    let t3 = t2 * t;
     let p0 = source[1];
-    let m0 = (target_time - source_time) * source[2];
+    let m0 = source[2] * interval;
     let p1 = target[1];
-    let m1 = (target_time - source_time) * target[0];
-
-    let res = (2.0 * t * t * t - 3.0 * t * t + 1.0) * p0
-        + (t * t * t - 2.0 * t * t + t) * m0
-        + (-2.0 * t * t * t + 3.0 * t * t) * p1
-        + (t * t * t - t * t) * m1;
-    res.to_array()
-}
-
-// https://web.mit.edu/2.998/www/QuaternionReport1.pdf
-// Todo: implement squad
-// fn cubic_spline4(
-//     source: [Float4; 3],
-//     source_time: f32,
-//     target: [Float4; 3],
-//     target_time: f32,
-//     amount: f32,
-// ) -> Float4 {
-//     let source = source.map(|i| Quat::from_array(i));
-//     let target = target.map(|i| Quat::from_array(i));
-//     let t = amount;
-//     let p0 = source[1];
-//     let m0 = (target_time - source_time) * source[2];
-//     let p1 = target[1];
-//     let m1 = (target_time - source_time) * target[0];
-//
-//     let res = (2.0 * t * t * t - 3.0 * t * t + 1.0) * p0
-//         + (t * t * t - 2.0 * t * t + t) * m0
-//         + (-2.0 * t * t * t + 3.0 * t * t) * p1
-//         + (t * t * t - t * t) * m1;
-//     res.to_array()
-// }
+    let m1 = target[0] * interval;
+    p0 * (2. * t3 - 3. * t2 + 1.)
+        + m0 * (t3 - 2. * t2 + t)
+        + p1 * (-2. * t3 + 3. * t2)
+        + m1 * (t3 - t2)
+}
 
 struct AnimationSampler {}
 
@@ -223,4 +229,435 @@ impl Animation {
             channels,
         }
     }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// End of the last keyframe in seconds
+    pub fn duration(&self) -> f32 {
+        self.channels
+            .iter()
+            .filter_map(|c| c.input.last())
+            .fold(0., |d, &t| d.max(t))
+    }
+
+    /// One sampler per channel
+    pub(crate) fn to_json(&self, buffer: &mut GltfBuffer) -> Value {
+        let (samplers, channels): (Vec<_>, Vec<_>) = self
+            .channels
+            .iter()
+            .enumerate()
+            .map(|(i, c)| {
+                let input = buffer.push_floats(&c.input, "SCALAR", true);
+                let (path, output) = match &c.property {
+                    Property::Translation(t) => (
+                        "translation",
+                        buffer.push_floats(&t.concat(), "VEC3", false),
+                    ),
+                    Property::Rotation(r) => {
+                        ("rotation", buffer.push_floats(&r.concat(), "VEC4", false))
+                    }
+                    Property::Scale(s) => ("scale", buffer.push_floats(&s.concat(), "VEC3", false)),
+                    Property::Morph(w) => {
+                        ("weights", buffer.push_floats(&w.concat(), "SCALAR", false))
+                    }
+                };
+                let interpolation = match c.interpolation {
+                    Interpolation::Linear => "LINEAR",
+                    Interpolation::Step => "STEP",
+                    Interpolation::CubicSpline => "CUBICSPLINE",
+                };
+                (
+                    json!({"input": input, "output": output, "interpolation": interpolation}),
+                    json!({"sampler": i, "target": {"node": c.target, "path": path}}),
+                )
+            })
+            .unzip();
+        named(
+            json!({"channels": channels, "samplers": samplers}),
+            &self.name,
+        )
+    }
+}
+
+/// Weighted sum of the animated properties of one node
+#[derive(Default)]
+pub(crate) struct Blend {
+    translation: (Vec3, f32),
+    rotation: (Vec4, f32),
+    scale: (Vec3, f32),
+    weights: (Vec<f32>, f32),
+}
+
+impl Blend {
+    pub(crate) fn add(&mut self, output: PropertyOutput, weight: f32) {
+        match output {
+            PropertyOutput::Translation(t) => {
+                self.translation.0 += Vec3::from(t) * weight;
+                self.translation.1 += weight;
+            }
+            PropertyOutput::Rotation(r) => {
+                // q and -q are the same rotation, keep them in one hemisphere
+                let q = Vec4::from(r);
+                let q = if self.rotation.0.dot(q) < 0. { -q } else { q };
+                self.rotation.0 += q * weight;
+                self.rotation.1 += weight;
+            }
+            PropertyOutput::Scale(s) => {
+                self.scale.0 += Vec3::from(s) * weight;
+                self.scale.1 += weight;
+            }
+            PropertyOutput::Morph(w) => {
+                let sum = &mut self.weights.0;
+                sum.resize(sum.len().max(w.len()), 0.);
+                sum.iter_mut().zip(w).for_each(|(s, w)| *s += w * weight);
+                self.weights.1 += weight;
+            }
+        }
+    }
+
+    /// Blended pose, filled up with the rest pose when the weights sum below one
+    pub(crate) fn finish(self, rest: Transform, rest_weights: &[f32]) -> (Transform, Vec<f32>) {
+        fn mix<T: Add<Output = T> + Mul<f32, Output = T>>((sum, total): (T, f32), rest: T) -> T {
+            let normalized = total.max(1.);
+            (sum + rest * (normalized - total)) * (1. / normalized)
+        }
+        let (t, r, s) = rest.decomposed();
+        let r = Vec4::from(r);
+        let r = if self.rotation.0.dot(r) < 0. { -r } else { r };
+        let (sum, total) = self.weights;
+        let weights = if total > 0. {
+            let rest = |i: usize| rest_weights.get(i).copied().unwrap_or_default();
+            (0..sum.len().max(rest_weights.len()))
+                .map(|i| mix((sum.get(i).copied().unwrap_or_default(), total), rest(i)))
+                .collect()
+        } else {
+            rest_weights.to_vec()
+        };
+        let transform = Transform::Decomposed {
+            translation: mix(self.translation, Vec3::from(t)).to_array(),
+            rotation: mix(self.rotation, r).normalize().to_array(),
+            scale: mix(self.scale, Vec3::from(s)).to_array(),
+        };
+        (transform, weights)
+    }
+}
+
+/// How a clip continues after its last keyframe
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PlayMode {
+    #[default]
+    Loop,
+    PingPong,
+    Once,
+}
+
+/// An animation selected in the player
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Clip {
+    pub animation: usize,
+    // Seconds from the start of the animation
+    pub time: f32,
+    pub speed: f32,
+    pub mode: PlayMode,
+    // Blend weight, clips are normalized when the sum is above one
+    pub weight: f32,
+    // Weight change per second while crossfading
+    fade: f32,
+    // Playing backwards in ping-pong mode
+    reversed: bool,
+}
+
+impl Clip {
+    fn new(animation: usize, weight: f32) -> Self {
+        Self {
+            animation,
+            time: 0.,
+            speed: 1.,
+            mode: PlayMode::default(),
+            weight,
+            fade: 0.,
+            reversed: false,
+        }
+    }
+
+    fn advance(&mut self, dt: f32, duration: f32) {
+        if self.fade != 0. {
+            self.weight = (self.weight + self.fade * dt).clamp(0., 1.);
+            if self.weight == 1. {
+                self.fade = 0.;
+            }
+        }
+        if duration <= 0. {
+            return;
+        }
+        let step = dt * self.speed;
+        match self.mode {
+            PlayMode::Loop => self.time = (self.time + step).rem_euclid(duration),
+            PlayMode::Once => self.time = (self.time + step).clamp(0., duration),
+            PlayMode::PingPong => {
+                // Position on the unfolded forward and backward pass
+                let pass = if self.reversed {
+                    2. * duration - self.time
+                } else {
+                    self.time
+                };
+                let pass = (pass + step).rem_euclid(2. * duration);
+                self.reversed = pass > duration;
+                self.time = if self.reversed {
+                    2. * duration - pass
+                } else {
+                    pass
+                };
+            }
+        }
+    }
+}
+
+/// Selects, times and blends the animations of a `Doc`.
+/// Only the clips in the player are evaluated.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AnimationPlayer {
+    pub clips: Vec<Clip>,
+    durations: Vec<f32>,
+}
+
+impl AnimationPlayer {
+    pub(crate) fn new(animations: &[Animation]) -> Self {
+        Self {
+            clips: vec![],
+            durations: animations.iter().map(Animation::duration).collect(),
+        }
+    }
+
+    /// Play only `animation`, from its start
+    pub fn play(&mut self, animation: usize) -> Option<&mut Clip> {
+        self.clips.clear();
+        self.add(animation, 1.)
+    }
+
+    /// Blend `animation` with the current clips, or change its weight if it is playing
+    pub fn add(&mut self, animation: usize, weight: f32) -> Option<&mut Clip> {
+        if animation >= self.durations.len() {
+            warn!("Animation {} out of range", animation);
+            return None;
+        }
+        let i = match self.clips.iter().position(|c| c.animation == animation) {
+            Some(i) => i,
+            None => {
+                self.clips.push(Clip::new(animation, 0.));
+                self.clips.len() - 1
+            }
+        };
+        let clip = &mut self.clips[i];
+        clip.weight = weight;
+        clip.fade = 0.;
+        Some(clip)
+    }
+
+    pub fn stop(&mut self, animation: usize) {
+        self.clips.retain(|c| c.animation != animation);
+    }
+
+    pub fn clip(&self, animation: usize) -> Option<&Clip> {
+        self.clips.iter().find(|c| c.animation == animation)
+    }
+
+    pub fn clip_mut(&mut self, animation: usize) -> Option<&mut Clip> {
+        self.clips.iter_mut().find(|c| c.animation == animation)
+    }
+
+    /// Fade `animation` in and every other clip out over `duration` seconds
+    pub fn crossfade(&mut self, animation: usize, duration: f32) -> Option<&mut Clip> {
+        if duration <= 0. {
+            return self.play(animation);
+        }
+        let weight = self.clip(animation).map_or(0., |c| c.weight);
+        for clip in &mut self.clips {
+            clip.fade = -clip.weight / duration;
+        }
+        let clip = self.add(animation, weight)?;
+        clip.fade = (1. - weight) / duration;
+        Some(clip)
+    }
+
+    /// Move clips and fades forward by `dt` seconds, dropping clips that faded out
+    pub fn advance(&mut self, dt: f32) {
+        let durations = &self.durations;
+        self.clips
+            .iter_mut()
+            .for_each(|c| c.advance(dt, durations[c.animation]));
+        self.clips.retain(|c| c.weight > 0. || c.fade == 0.);
+    }
+
+    pub fn duration(&self, animation: usize) -> f32 {
+        self.durations.get(animation).copied().unwrap_or_default()
+    }
+}
+
+#[test]
+fn test_keyframes() {
+    let channel = AnimationChannel {
+        target: 0,
+        property: Property::Scale(vec![[1.; 3]; 4]),
+        input: vec![0., 1., 2., 4.],
+        interpolation: Interpolation::Linear,
+    };
+    assert_eq!(channel.keyframes(0.5), (0, 1, 0.5));
+    assert_eq!(channel.keyframes(1.), (1, 2, 0.));
+    assert_eq!(channel.keyframes(3.), (2, 3, 0.5));
+    // Holds the first and last keyframes outside the input range
+    assert_eq!(channel.keyframes(4.5), (2, 3, 1.));
+    assert_eq!(channel.keyframes(-1.), (0, 1, 0.));
+}
+
+// Keyframes laid out like the CubicSpline samples of the InterpolationTest model
+#[test]
+fn test_cubic_spline() {
+    use std::f32::consts::FRAC_PI_2;
+    let y = |angle: f32| Quat::from_rotation_y(angle).to_array();
+    let zero = [0.; 4];
+    let rotation = AnimationChannel {
+        target: 0,
+        property: Property::Rotation(vec![zero, y(0.), zero, zero, y(FRAC_PI_2), zero]),
+        input: vec![0., 1.],
+        interpolation: Interpolation::CubicSpline,
+    };
+    let angle = |t: f32| match rotation.get_transform(t) {
+        PropertyOutput::Rotation(r) => {
+            let (axis, angle) = Quat::from_array(r).to_axis_angle();
+            assert!((Quat::from_array(r).length() - 1.).abs() < 1e-5);
+            assert!(angle < 1e-5 || (axis - Vec3::Y).length() < 1e-5);
+            angle
+        }
+        _ => unreachable!(),
+    };
+    // Values at keyframes, not the tangents
+    assert!(angle(0.).abs() < 1e-5);
+    assert!((angle(0.999_999) - FRAC_PI_2).abs() < 1e-3);
+    // Zero tangents are symmetric around the middle
+    assert!((angle(0.5) - FRAC_PI_2 / 2.).abs() < 1e-5);
+    assert!(angle(0.25) < FRAC_PI_2 / 4.);
+
+    // Tangents of a straight line at constant speed
+    let speed = [2., 0., 0.];
+    let translation = AnimationChannel {
+        target: 0,
+        property: Property::Translation(vec![speed, [0.; 3], speed, speed, [4., 0., 0.], speed]),
+        input: vec![1., 3.],
+        interpolation: Interpolation::CubicSpline,
+    };
+    for (t, x) in [(1., 0.), (1.5, 1.), (2., 2.), (2.5, 3.)] {
+        match translation.get_transform(t) {
+            PropertyOutput::Translation(v) => assert!((v[0] - x).abs() < 1e-5, "{} {:?}", t, v),
+            _ => unreachable!(),
+        }
+    }
+}
+
+// The InterpolationTest sample model, found like the other models in the sample model paths.
+// Samples are checked against the spec formula on the accessors read by gltf.
+#[test]
+#[ignore = "needs the InterpolationTest sample model"]
+fn test_interpolation_test_model() {
+    use gltf::animation::Property as Path;
+    let doc = crate::load_file("InterpolationTest", &Default::default()).unwrap();
+    let (gltf, buffers, _) = gltf::import(doc.source()).unwrap();
+    let close = |a: &[f32], b: &[f32]| a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-4);
+
+    let mut checked = 0;
+    for name in ["CubicSpline Rotation", "CubicSpline Translation"] {
+        let expected = gltf.animations().find(|a| a.name() == Some(name)).unwrap();
+        let animation = &doc.animations[expected.index()];
+        for (channel, expected) in animation.channels.iter().zip(expected.channels()) {
+            assert_eq!(channel.target, expected.target().node().index());
+            let reader = expected.reader(|b| Some(&buffers[b.index()]));
+            let input: Vec<f32> = reader.read_inputs().unwrap().collect();
+            let output: Vec<Vec<f32>> = match reader.read_outputs().unwrap() {
+                ReadOutputs::Translations(t) => t.map(|v| v.to_vec()).collect(),
+                ReadOutputs::Rotations(r) => r.into_f32().map(|v| v.to_vec()).collect(),
+                _ => unreachable!(),
+            };
+            assert!(matches!(
+                expected.sampler().interpolation(),
+                Interpolation::CubicSpline
+            ));
+            let sample = |t: f32| match (channel.get_transform(t), expected.target().property()) {
+                (PropertyOutput::Translation(v), Path::Translation) => v.to_vec(),
+                (PropertyOutput::Rotation(v), Path::Rotation) => v.to_vec(),
+                _ => unreachable!(),
+            };
+            for k in 0..input.len() - 1 {
+                // Values at the keyframes, not the tangents
+                assert!(close(&sample(input[k]), &output[3 * k + 1]), "{}", name);
+                let interval = input[k + 1] - input[k];
+                for s in [0.25, 0.5, 0.75] {
+                    let (s2, s3) = (s * s, s * s * s);
+                    let mut spline: Vec<f32> = (0..output[0].len())
+                        .map(|i| {
+                            (2. * s3 - 3. * s2 + 1.) * output[3 * k + 1][i]
+                                + (s3 - 2. * s2 + s) * interval * output[3 * k + 2][i]
+                                + (-2. * s3 + 3. * s2) * output[3 * k + 4][i]
+                                + (s3 - s2) * interval * output[3 * k + 3][i]
+                        })
+                        .collect();
+                    if spline.len() == 4 {
+                        let length = spline.iter().map(|x| x * x).sum::<f32>().sqrt();
+                        spline.iter_mut().for_each(|x| *x /= length);
+                    }
+                    let t = input[k] + s * interval;
+                    assert!(close(&sample(t), &spline), "{} at {}", name, t);
+                }
+            }
+            let last = input.len() - 1;
+            assert!(close(&sample(input[last]), &output[3 * last + 1]));
+            checked += 1;
+        }
+    }
+    assert_eq!(checked, 2);
+}
+
+#[test]
+fn test_animation_player() {
+    let mut player = AnimationPlayer {
+        clips: vec![],
+        durations: vec![2., 1.],
+    };
+    assert!(player.play(2).is_none());
+    let clip = player.play(0).unwrap();
+    clip.mode = PlayMode::PingPong;
+    player.advance(2.5);
+    assert_eq!(player.clips[0].time, 1.5);
+    player.advance(2.);
+    assert_eq!(player.clips[0].time, 0.5);
+    player.clips[0].mode = PlayMode::Once;
+    player.advance(-1.);
+    assert_eq!(player.clips[0].time, 0.);
+
+    player.crossfade(1, 0.5).unwrap().mode = PlayMode::Loop;
+    player.advance(0.25);
+    let weights: Vec<_> = player.clips.iter().map(|c| c.weight).collect();
+    assert_eq!(weights, [0.5, 0.5]);
+    assert_eq!(player.clips[1].time, 0.25);
+    player.advance(1.);
+    assert_eq!(player.clips.len(), 1);
+    assert_eq!(player.clip(1).map(|c| (c.weight, c.time)), Some((1., 0.25)));
+}
+
+#[test]
+fn test_blend() {
+    let rest = Transform::Decomposed {
+        translation: [0.; 3],
+        rotation: [0., 0., 0., 1.],
+        scale: [1.; 3],
+    };
+    let mut blend = Blend::default();
+    blend.add(PropertyOutput::Translation([2., 0., 0.]), 0.5);
+    blend.add(PropertyOutput::Morph(vec![1.]), 0.5);
+    blend.add(PropertyOutput::Morph(vec![0., 1.]), 1.);
+    let (transform, weights) = blend.finish(rest, &[]);
+    let (t, r, s) = transform.decomposed();
+    assert_eq!((t, r, s), ([1., 0., 0.], [0., 0., 0., 1.], [1.; 3]));
+    assert_eq!(weights, [1. / 3., 2. / 3.]);
 }
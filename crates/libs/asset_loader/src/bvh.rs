@@ -0,0 +1,334 @@
+use crate::aabb::Aabb;
+use crate::{MeshID, NodeID};
+use glam::Vec3;
+
+const LEAF_SIZE: usize = 4;
+
+/// Ray in world space
+#[derive(Debug, Clone, Copy)]
+pub struct Ray {
+    pub origin: Vec3,
+    pub direction: Vec3,
+}
+
+/// Closest intersection of a ray with the scene
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hit {
+    pub node: NodeID,
+    // EXT_mesh_gpu_instancing instance, 0 without instancing
+    pub instance: usize,
+    pub mesh: MeshID,
+    pub primitive: usize,
+    pub geometry_id: u32,
+    // Material in use, with the selected variant
+    pub material: usize,
+    // Triangle inside the primitive
+    pub triangle: usize,
+    // Weights of the second and third vertex, as in the hit shaders
+    pub barycentrics: [f32; 2],
+    pub distance: f32,
+    pub position: Vec3,
+}
+
+/// Where a triangle comes from, shared by the triangles of one primitive instance
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Owner {
+    pub(crate) node: NodeID,
+    pub(crate) instance: usize,
+    pub(crate) mesh: MeshID,
+    pub(crate) primitive: usize,
+    pub(crate) geometry_id: u32,
+    pub(crate) material: usize,
+}
+
+struct Triangle {
+    vertices: [Vec3; 3],
+    owner: u32,
+    index: u32,
+}
+
+impl Triangle {
+    fn centroid(&self) -> Vec3 {
+        (self.vertices[0] + self.vertices[1] + self.vertices[2]) / 3.
+    }
+
+    /// Möller-Trumbore, returns the distance and barycentrics
+    fn intersect(&self, ray: &Ray, t_max: f32) -> Option<(f32, [f32; 2])> {
+        let [a, b, c] = self.vertices;
+        let e1 = b - a;
+        let e2 = c - a;
+        let p = ray.direction.cross(e2);
+        let det = e1.dot(p);
+        // Relative to the triangle size, so small triangles are still hit
+        if !det.is_finite() || det.abs() <= f32::EPSILON * e1.length() * p.length() {
+            return None;
+        }
+        let inv_det = 1. / det;
+        let s = ray.origin - a;
+        let u = s.dot(p) * inv_det;
+        if !(0. ..=1.).contains(&u) {
+            return None;
+        }
+        let q = s.cross(e1);
+        let v = ray.direction.dot(q) * inv_det;
+        if v < 0. || u + v > 1. {
+            return None;
+        }
+        let t = e2.dot(q) * inv_det;
+        (t > 0. && t < t_max).then_some((t, [u, v]))
+    }
+}
+
+// Leaves hold `count` triangles from `start`, inner nodes have children at `start` and `start + 1`
+struct BvhNode {
+    bounds: Aabb,
+    start: u32,
+    count: u32,
+}
+
+/// Bounding volume hierarchy over world-space triangles, for picking on the CPU.
+/// Built with `Doc::build_bvh`.
+#[derive(Default)]
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    triangles: Vec<Triangle>,
+    owners: Vec<Owner>,
+}
+
+impl Bvh {
+    /// `triangles` are world-space vertices with an index into `owners`
+    pub(crate) fn new(owners: Vec<Owner>, triangles: Vec<([Vec3; 3], u32, u32)>) -> Self {
+        let mut bvh = Self {
+            nodes: vec![],
+            triangles: triangles
+                .into_iter()
+                .map(|(vertices, owner, index)| Triangle {
+                    vertices,
+                    owner,
+                    index,
+                })
+                .collect(),
+            owners,
+        };
+        if !bvh.triangles.is_empty() {
+            bvh.nodes.push(BvhNode {
+                bounds: Aabb::from_points([]),
+                start: 0,
+                count: bvh.triangles.len() as _,
+            });
+            bvh.split(0);
+        }
+        bvh
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.triangles.len()
+    }
+
+    fn split(&mut self, node: usize) {
+        let BvhNode { start, count, .. } = self.nodes[node];
+        let range = start as usize..(start + count) as usize;
+        let triangles = &mut self.triangles[range];
+        self.nodes[node].bounds = Aabb::from_points(triangles.iter().flat_map(|t| t.vertices));
+        if triangles.len() <= LEAF_SIZE {
+            return;
+        }
+        // Median split along the longest axis of the centroids
+        let centroids = Aabb::from_points(triangles.iter().map(Triangle::centroid));
+        let extent = centroids.max() - centroids.min();
+        let axis = if extent.x >= extent.y && extent.x >= extent.z {
+            0
+        } else if extent.y >= extent.z {
+            1
+        } else {
+            2
+        };
+        let mid = triangles.len() / 2;
+        triangles.select_nth_unstable_by(mid, |a, b| {
+            a.centroid()[axis].total_cmp(&b.centroid()[axis])
+        });
+
+        let left = self.nodes.len();
+        for (start, count) in [
+            (start, mid as u32),
+            (start + mid as u32, count - mid as u32),
+        ] {
+            self.nodes.push(BvhNode {
+                bounds: Aabb::from_points([]),
+                start,
+                count,
+            });
+        }
+        self.nodes[node].start = left as _;
+        self.nodes[node].count = 0;
+        self.split(left);
+        self.split(left + 1);
+    }
+
+    /// Closest triangle hit by the ray, closer than `t_max`
+    pub fn intersect(&self, ray: &Ray, t_max: f32) -> Option<Hit> {
+        let inv_direction = ray.direction.recip();
+        let mut closest: Option<(f32, [f32; 2], &Triangle)> = None;
+        let limit = |closest: Option<(f32, _, _)>| closest.map_or(t_max, |(t, ..)| t);
+        let mut stack = vec![];
+        if let Some(root) = self.nodes.first() {
+            let t = root.bounds.ray_distance(ray.origin, inv_direction, t_max);
+            stack.extend(t.map(|t| (t, 0)));
+        }
+        while let Some((t_enter, node)) = stack.pop() {
+            // A closer hit may have been found since the node was pushed
+            if t_enter > limit(closest) {
+                continue;
+            }
+            let node = &self.nodes[node];
+            if node.count > 0 {
+                let start = node.start as usize;
+                for triangle in &self.triangles[start..start + node.count as usize] {
+                    if let Some((t, bary)) = triangle.intersect(ray, limit(closest)) {
+                        closest = Some((t, bary, triangle));
+                    }
+                }
+                continue;
+            }
+            let t_max = limit(closest);
+            // Visit the nearer child first
+            let mut children: Vec<_> = [node.start as usize, node.start as usize + 1]
+                .into_iter()
+                .filter_map(|c| {
+                    let t = self.nodes[c]
+                        .bounds
+                        .ray_distance(ray.origin, inv_direction, t_max)?;
+                    Some((t, c))
+                })
+                .collect();
+            children.sort_by(|a, b| b.0.total_cmp(&a.0));
+            stack.extend(children);
+        }
+        closest.map(|(distance, barycentrics, triangle)| {
+            let owner = self.owners[triangle.owner as usize];
+            Hit {
+                node: owner.node,
+                instance: owner.instance,
+                mesh: owner.mesh,
+                primitive: owner.primitive,
+                geometry_id: owner.geometry_id,
+                material: owner.material,
+                triangle: triangle.index as _,
+                barycentrics,
+                distance,
+                position: ray.origin + ray.direction * distance,
+            }
+        })
+    }
+}
+
+#[test]
+fn test_pick() {
+    use crate::load_file;
+    // Two unit quads, one behind the other
+    let dir = crate::TestDir::new(&[(
+        "quads.obj",
+        "o front\nv 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3 4\n\
+         o back\nv 0 0 -1\nv 1 0 -1\nv 1 1 -1\nv 0 1 -1\nf 5 6 7 8\n",
+    )]);
+    let doc = load_file(dir.path("quads.obj"), &Default::default()).unwrap();
+    let bvh = doc.build_bvh();
+    assert_eq!(bvh.triangle_count(), 4);
+
+    // The scene is scaled to 10 units around the origin
+    let ray = |origin: [f32; 3], direction: Vec3| Ray {
+        origin: Vec3::from(origin),
+        direction,
+    };
+    let front = bvh
+        .intersect(&ray([1., 2., 50.], -Vec3::Z), f32::MAX)
+        .unwrap();
+    assert_eq!((front.node, front.mesh), (0, 0));
+    assert!((front.distance - 45.).abs() < 1e-4);
+    assert!((front.position - Vec3::new(1., 2., 5.)).length() < 1e-4);
+    let back = bvh
+        .intersect(&ray([1., 2., -50.], Vec3::Z), f32::MAX)
+        .unwrap();
+    assert_eq!(back.mesh, 1);
+    assert!(bvh.intersect(&ray([1., 2., 50.], -Vec3::Z), 40.).is_none());
+    assert!(bvh
+        .intersect(&ray([20., 2., 50.], -Vec3::Z), f32::MAX)
+        .is_none());
+    assert_eq!(
+        doc.hit_properties(&front)[1],
+        ("Mesh", "0 (front)".to_string())
+    );
+}
+
+#[test]
+fn test_small_triangle() {
+    // A millimeter sized triangle, det is far below f32::EPSILON
+    let triangle = Triangle {
+        vertices: [Vec3::ZERO, Vec3::X * 1e-3, Vec3::Y * 1e-3],
+        owner: 0,
+        index: 0,
+    };
+    let ray = Ray {
+        origin: Vec3::new(2e-4, 2e-4, 1.),
+        direction: -Vec3::Z,
+    };
+    let (t, _) = triangle.intersect(&ray, f32::MAX).unwrap();
+    assert!((t - 1.).abs() < 1e-6);
+    // Parallel to the triangle plane
+    let ray = Ray {
+        origin: Vec3::new(-1., 2e-4, 0.),
+        direction: Vec3::X,
+    };
+    assert!(triangle.intersect(&ray, f32::MAX).is_none());
+}
+
+#[test]
+fn test_pick_skinned() {
+    use crate::load_file;
+    use serde_json::json;
+    let triangle = [0., 0., 0., 1., 0., 0., 0., 1., 0.];
+    let weights = [1., 0., 0., 0.].repeat(3);
+    let ibm = glam::Mat4::IDENTITY.to_cols_array();
+    let floats: Vec<f32> = [&triangle[..], &weights, &ibm].concat();
+    let mut bytes: Vec<u8> = floats.iter().flat_map(|f| f.to_le_bytes()).collect();
+    // JOINTS_0, every vertex on the first joint
+    bytes.extend([0; 12]);
+    let gltf = json!({
+        "asset": {"version": "2.0"},
+        "buffers": [{"byteLength": 160, "uri": format!(
+            "data:application/octet-stream;base64,{}", base64::encode(bytes))}],
+        "bufferViews": [{"buffer": 0, "byteLength": 160}],
+        "accessors": [
+            {"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                "min": [0, 0, 0], "max": [1, 1, 0]},
+            {"bufferView": 0, "byteOffset": 36, "componentType": 5126, "count": 3,
+                "type": "VEC4"},
+            {"bufferView": 0, "byteOffset": 84, "componentType": 5126, "count": 1,
+                "type": "MAT4"},
+            {"bufferView": 0, "byteOffset": 148, "componentType": 5121, "count": 3,
+                "type": "VEC4"}
+        ],
+        "meshes": [{"primitives": [{"attributes": {
+            "POSITION": 0, "WEIGHTS_0": 1, "JOINTS_0": 3}}]}],
+        "skins": [{"joints": [1], "inverseBindMatrices": 2}],
+        // The node transform of a skinned mesh is ignored, the joint moves it
+        "nodes": [
+            {"mesh": 0, "skin": 0, "translation": [100, 0, 0]},
+            {"translation": [0, 0, 2]}
+        ],
+        "scenes": [{"nodes": [0, 1]}]
+    });
+    let dir = crate::TestDir::new(&[("skinned.gltf", &gltf.to_string())]);
+    let options = crate::LoadOptions {
+        normalize: false,
+        ..Default::default()
+    };
+    let doc = load_file(dir.path("skinned.gltf"), &options).unwrap();
+    let ray = Ray {
+        origin: Vec3::new(0.2, 0.2, 10.),
+        direction: -Vec3::Z,
+    };
+    let hit = doc.build_bvh().intersect(&ray, f32::MAX).unwrap();
+    assert_eq!(hit.node, 0);
+    assert!((hit.distance - 8.).abs() < 1e-4);
+}
@@ -0,0 +1,308 @@
+use crate::error::*;
+use crate::options::LoadOptions;
+use crate::scene_graph::Doc;
+use gltf::animation::Interpolation;
+use gltf::material::AlphaMode;
+use gltf::mesh::Mode;
+use gltf::scene::Transform;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+use std::fmt::Display;
+use std::fs::File;
+use std::hash::Hasher;
+use std::io::{BufRead, BufReader, BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use twox_hash::XxHash64;
+
+/// Bump when the processing in `load_file` or the serialized `Doc` changes
+const LOADER_VERSION: u32 = 2;
+const MAGIC: &[u8; 8] = b"RTRCACHE";
+// Magic and loader version
+const HEADER_SIZE: usize = 12;
+// MTL texture statements besides `map_*`
+const TEXTURE_STATEMENTS: [&str; 5] = ["bump", "norm", "disp", "decal", "refl"];
+
+fn cache_error(e: impl Display) -> Error {
+    Error::Load(format!("Asset cache: {}", e))
+}
+
+/// Cache file of a model, named by the hash of its source files, the options and the loader version.
+/// Sources are the model, the files referenced by glTF URIs and the MTL files and textures of an OBJ.
+pub(crate) fn cache_path(
+    dir: &Path,
+    model: &Path,
+    raw_json: Option<&Value>,
+    options: &LoadOptions,
+) -> Result<PathBuf> {
+    let mut hasher = XxHash64::with_seed(LOADER_VERSION as _);
+    hasher.write(env!("CARGO_PKG_VERSION").as_bytes());
+    let options = LoadOptions {
+        cache_dir: None,
+        ..options.clone()
+    };
+    hasher.write(&bincode::serialize(&options).map_err(cache_error)?);
+    for source in sources(model, raw_json) {
+        let mut reader =
+            BufReader::with_capacity(1 << 20, File::open(&source).map_err(cache_error)?);
+        loop {
+            let buf = reader.fill_buf().map_err(cache_error)?;
+            if buf.is_empty() {
+                break;
+            }
+            hasher.write(buf);
+            let len = buf.len();
+            reader.consume(len);
+        }
+        // Separates the files
+        hasher.write_u8(0xff);
+    }
+    Ok(dir.join(format!("{:016x}.cache", hasher.finish())))
+}
+
+/// The model and the existing files it references
+fn sources(model: &Path, raw_json: Option<&Value>) -> Vec<PathBuf> {
+    let dir = model.parent().unwrap_or(Path::new(""));
+    let mut sources = vec![model.to_path_buf()];
+    if let Some(json) = raw_json {
+        let uris = ["buffers", "images"]
+            .iter()
+            .filter_map(|key| json.get(key)?.as_array())
+            .flatten()
+            .filter_map(|item| item.get("uri")?.as_str())
+            .filter(|uri| !uri.starts_with("data:"))
+            .filter_map(|uri| urlencoding::decode(uri).ok());
+        sources.extend(uris.map(|uri| dir.join(&*uri)));
+    } else if model
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("obj"))
+    {
+        let obj = std::fs::read_to_string(model).unwrap_or_default();
+        for mtl in statements(&obj, "mtllib") {
+            let mtl = dir.join(mtl);
+            if let Ok(text) = std::fs::read_to_string(&mtl) {
+                let textures = text
+                    .lines()
+                    .filter(|l| {
+                        let keyword = l.split_whitespace().next().unwrap_or_default();
+                        keyword.starts_with("map_") || TEXTURE_STATEMENTS.contains(&keyword)
+                    })
+                    // Options come before the file name
+                    .filter_map(|l| l.split_whitespace().last())
+                    .map(|texture| dir.join(texture));
+                sources.push(mtl);
+                sources.extend(textures);
+            }
+        }
+    }
+    // Missing files are reported by the loader, they are not an error here
+    sources.retain(|s| s.is_file());
+    sources
+}
+
+fn statements<'a>(text: &'a str, keyword: &'a str) -> impl Iterator<Item = &'a str> {
+    text.lines().filter_map(move |l| {
+        let rest = l.trim_start().strip_prefix(keyword)?;
+        rest.starts_with(char::is_whitespace).then(|| rest.trim())
+    })
+}
+
+/// Doc stored by `store`, None if there is no valid cache file.
+/// Image pixels are copied out of the file in one piece each.
+pub(crate) fn load(path: &Path) -> Result<Option<Doc>> {
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(cache_error(e)),
+    };
+    if bytes.len() < HEADER_SIZE
+        || &bytes[..8] != MAGIC
+        || bytes[8..HEADER_SIZE] != LOADER_VERSION.to_le_bytes()
+    {
+        return Ok(None);
+    }
+    bincode::deserialize(&bytes[HEADER_SIZE..])
+        .map(Some)
+        .map_err(cache_error)
+}
+
+pub(crate) fn store(path: &Path, doc: &Doc) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(cache_error)?;
+    }
+    // Readers never see a partly written file
+    let tmp = path.with_extension(format!("{}.tmp", std::process::id()));
+    let write = || -> std::result::Result<(), Box<dyn std::error::Error>> {
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&LOADER_VERSION.to_le_bytes())?;
+        bincode::serialize_into(&mut writer, doc)?;
+        writer
+            .into_inner()
+            .map_err(|e| e.into_error())?
+            .sync_all()?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    };
+    write().map_err(|e| {
+        let _ = std::fs::remove_file(&tmp);
+        cache_error(e)
+    })
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "Transform")]
+pub(crate) enum TransformDef {
+    Matrix {
+        matrix: [[f32; 4]; 4],
+    },
+    Decomposed {
+        translation: [f32; 3],
+        rotation: [f32; 4],
+        scale: [f32; 3],
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "Interpolation")]
+pub(crate) enum InterpolationDef {
+    Linear = 1,
+    Step,
+    CubicSpline,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "AlphaMode")]
+pub(crate) enum AlphaModeDef {
+    Opaque = 1,
+    Mask,
+    Blend,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "Mode")]
+enum ModeDef {
+    Points = 1,
+    Lines,
+    LineLoop,
+    LineStrip,
+    Triangles,
+    TriangleStrip,
+    TriangleFan,
+}
+
+/// `LoadReport::skipped_primitives`
+pub(crate) mod skipped_primitives {
+    use super::*;
+
+    #[derive(Serialize, Deserialize)]
+    struct Skipped([usize; 2], #[serde(with = "ModeDef")] Mode);
+
+    pub(crate) fn serialize<S: Serializer>(
+        skipped: &[([usize; 2], Mode)],
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_seq(skipped.iter().map(|&(id, mode)| Skipped(id, mode)))
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Vec<([usize; 2], Mode)>, D::Error> {
+        let skipped = Vec::<Skipped>::deserialize(deserializer)?;
+        Ok(skipped
+            .into_iter()
+            .map(|Skipped(id, mode)| (id, mode))
+            .collect())
+    }
+}
+
+/// Byte buffers, copied in one piece instead of byte by byte
+pub(crate) mod bytes {
+    use super::*;
+    use serde::de::Visitor;
+    use std::fmt;
+
+    pub(crate) fn serialize<S: Serializer>(
+        bytes: &[u8],
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(bytes)
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Vec<u8>, D::Error> {
+        struct BytesVisitor;
+        impl<'de> Visitor<'de> for BytesVisitor {
+            type Value = Vec<u8>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("bytes")
+            }
+
+            fn visit_bytes<E>(self, bytes: &[u8]) -> std::result::Result<Vec<u8>, E> {
+                Ok(bytes.to_vec())
+            }
+
+            fn visit_byte_buf<E>(self, bytes: Vec<u8>) -> std::result::Result<Vec<u8>, E> {
+                Ok(bytes)
+            }
+        }
+        deserializer.deserialize_byte_buf(BytesVisitor)
+    }
+}
+
+#[test]
+fn test_cache() {
+    use crate::load_file;
+    let dir = crate::TestDir::new(&[
+        (
+            "quad.obj",
+            "mtllib quad.mtl\nv 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3 4\n",
+        ),
+        ("quad.mtl", "newmtl red\nKd 1 0 0\nnorm -bm 1 normal.png\n"),
+        ("normal.png", "not a png"),
+    ]);
+    let path = dir.path("quad.obj");
+    let cache_dir = dir.path("cache");
+    let options = LoadOptions {
+        cache_dir: Some(cache_dir.clone()),
+        ..Default::default()
+    };
+    let cached = |doc: &Doc| {
+        doc.report
+            .timings
+            .iter()
+            .any(|(phase, _)| *phase == "Cache")
+    };
+
+    let doc = load_file(&path, &options).unwrap();
+    assert!(!cached(&doc));
+    assert_eq!(std::fs::read_dir(&cache_dir).unwrap().count(), 1);
+    let hit = load_file(&path, &options).unwrap();
+    assert!(cached(&hit));
+    assert_eq!(hit.options(), &options);
+    assert_eq!(hit.source(), doc.source());
+    let (a, b) = (&doc.geo_builder, &hit.geo_builder);
+    assert_eq!(a.indices, b.indices);
+    assert_eq!(a.offsets, b.offsets);
+    assert!(a
+        .vertices
+        .iter()
+        .zip(&b.vertices)
+        .all(|(a, b)| a.position == b.position && a.normal == b.normal));
+    assert_eq!(doc.images.len(), hit.images.len());
+    assert_eq!(doc.images[0].pixels, hit.images[0].pixels);
+    assert_eq!(doc.aabb_trans, hit.aabb_trans);
+
+    // Other options and changed sources are other entries
+    let flat = LoadOptions {
+        normals: crate::NormalMode::Flat,
+        ..options.clone()
+    };
+    assert!(!cached(&load_file(&path, &flat).unwrap()));
+    std::fs::write(dir.path("normal.png"), "still not a png").unwrap();
+    assert!(!cached(&load_file(&path, &options).unwrap()));
+    std::fs::write(dir.path("quad.mtl"), "newmtl red\nKd 0 1 0\n").unwrap();
+    assert!(!cached(&load_file(&path, &options).unwrap()));
+    assert_eq!(std::fs::read_dir(&cache_dir).unwrap().count(), 4);
+}
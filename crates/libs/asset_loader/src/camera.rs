@@ -0,0 +1,166 @@
+use crate::export::named;
+use crate::{get_name, Name};
+use glam::{Mat4, Vec3, Vec4, Vec4Swizzles};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Projection {
+    Perspective {
+        // Radians
+        yfov: f32,
+        aspect_ratio: Option<f32>,
+        znear: f32,
+        // Infinite projection if None
+        zfar: Option<f32>,
+    },
+    Orthographic {
+        xmag: f32,
+        ymag: f32,
+        znear: f32,
+        zfar: f32,
+    },
+}
+
+impl Projection {
+    fn scaled(self, s: f32) -> Self {
+        match self {
+            Projection::Perspective {
+                yfov,
+                aspect_ratio,
+                znear,
+                zfar,
+            } => Projection::Perspective {
+                yfov,
+                aspect_ratio,
+                znear: znear * s,
+                zfar: zfar.map(|z| z * s),
+            },
+            Projection::Orthographic {
+                xmag,
+                ymag,
+                znear,
+                zfar,
+            } => Projection::Orthographic {
+                xmag: xmag * s,
+                ymag: ymag * s,
+                znear: znear * s,
+                zfar: zfar * s,
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Camera {
+    pub index: usize,
+    name: Name,
+    projection: Projection,
+}
+
+/// A glTF camera placed in the scene.
+/// Positions and distances are in the normalized space of `Doc::aabb_trans`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CameraView {
+    pub name: String,
+    pub position: Vec3,
+    pub direction: Vec3,
+    pub projection: Projection,
+}
+
+impl Camera {
+    pub(crate) fn to_json(&self) -> Value {
+        let camera = match self.projection {
+            Projection::Perspective {
+                yfov,
+                aspect_ratio,
+                znear,
+                zfar,
+            } => {
+                let mut p = json!({"yfov": yfov, "znear": znear});
+                if let Some(a) = aspect_ratio {
+                    p["aspectRatio"] = a.into();
+                }
+                if let Some(z) = zfar {
+                    p["zfar"] = z.into();
+                }
+                json!({"type": "perspective", "perspective": p})
+            }
+            Projection::Orthographic {
+                xmag,
+                ymag,
+                znear,
+                zfar,
+            } => json!({"type": "orthographic", "orthographic": {
+                "xmag": xmag, "ymag": ymag, "znear": znear, "zfar": zfar,
+            }}),
+        };
+        named(camera, &self.name)
+    }
+
+    pub fn to_view(&self, transform: Mat4) -> CameraView {
+        // Cameras look down the local -Z axis
+        let forward = (transform * Vec4::NEG_Z).xyz();
+        CameraView {
+            name: self
+                .name
+                .clone()
+                .unwrap_or_else(|| format!("Camera {}", self.index)),
+            position: (transform * Vec4::W).xyz(),
+            direction: forward.normalize_or_zero(),
+            // Assume uniform scaling for the clip planes
+            projection: self.projection.scaled(forward.length()),
+        }
+    }
+}
+
+impl From<gltf::Camera<'_>> for Camera {
+    fn from(camera: gltf::Camera) -> Self {
+        let projection = match camera.projection() {
+            gltf::camera::Projection::Perspective(p) => Projection::Perspective {
+                yfov: p.yfov(),
+                aspect_ratio: p.aspect_ratio(),
+                znear: p.znear(),
+                zfar: p.zfar(),
+            },
+            gltf::camera::Projection::Orthographic(o) => Projection::Orthographic {
+                xmag: o.xmag(),
+                ymag: o.ymag(),
+                znear: o.znear(),
+                zfar: o.zfar(),
+            },
+        };
+        Self {
+            index: camera.index(),
+            name: get_name!(camera),
+            projection,
+        }
+    }
+}
+
+#[test]
+fn test_camera_view() {
+    let camera = Camera {
+        index: 0,
+        name: None,
+        projection: Projection::Perspective {
+            yfov: 0.5,
+            aspect_ratio: None,
+            znear: 0.1,
+            zfar: Some(100.),
+        },
+    };
+    let transform = Mat4::from_scale(Vec3::splat(2.)) * Mat4::from_translation(Vec3::X);
+    let view = camera.to_view(transform);
+    assert_eq!(view.position, Vec3::new(2., 0., 0.));
+    assert_eq!(view.direction, Vec3::NEG_Z);
+    assert_eq!(
+        view.projection,
+        Projection::Perspective {
+            yfov: 0.5,
+            aspect_ratio: None,
+            znear: 0.2,
+            zfar: Some(200.),
+        }
+    );
+}
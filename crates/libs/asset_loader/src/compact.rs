@@ -0,0 +1,355 @@
+use crate::geometry::{GeoBuilder, Vertex};
+use glam::{Vec2, Vec3, Vec4, Vec4Swizzles};
+
+/// Geometries with at most this many vertices use 16 bit indices
+const U16_VERTICES: usize = u16::MAX as usize + 1;
+/// Bit of an encoded tangent holding the bitangent sign, the lowest bit of the second component
+const TANGENT_SIGN: u32 = 1 << 16;
+/// Color offset of geometries without vertex colors
+pub(crate) const NO_COLORS: u32 = u32::MAX;
+/// Joint offset of vertices without skin and morph of vertices without morph targets
+pub(crate) const NO_ANIMATION: u32 = u32::MAX;
+
+/// Shading attributes of a vertex, 24 bytes
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct VertexAttributes {
+    // Octahedral, two snorm16
+    pub normal: u32,
+    // Octahedral like the normal, with `TANGENT_SIGN` for the bitangent sign
+    pub tangent: u32,
+    pub uv0: Vec2,
+    pub uv1: Vec2,
+}
+
+/// Vertex of the animation compute pass, morphed then skinned. 32 bytes, like the std430 struct.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct AnimatedVertex {
+    pub vertex: u32,
+    // First joint of the skin in the joint buffer, `NO_ANIMATION` without skin
+    pub joint_offset: u32,
+    pub joints: [u16; 4],
+    // unorm16
+    pub weights: [u16; 4],
+    // In `CompactGeometry::morphs`, `NO_ANIMATION` without morph targets
+    pub morph: u32,
+    _padding: u32,
+}
+
+/// Morph targets of a geometry, read by the animation compute pass
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MorphGeometry {
+    pub first_vertex: u32,
+    pub vertices: u32,
+    pub targets: u32,
+    // In `CompactGeometry::morph_deltas`, one target after the other
+    pub deltas: u32,
+    // In the weights of `Doc::get_morph_weights`
+    pub weights: u32,
+}
+
+/// Geometry as uploaded to the GPU.
+/// Every vertex takes 36 bytes, colors and animation data only exist for the geometries using them.
+#[derive(Default)]
+pub struct CompactGeometry {
+    // Tightly packed, the BLAS input
+    pub positions: Vec<[f32; 3]>,
+    pub attributes: Vec<VertexAttributes>,
+    // RGBA8, from `PrimInfo::color_offset`
+    pub colors: Vec<u32>,
+    // Skinned or morphed vertices
+    pub animated: Vec<AnimatedVertex>,
+    pub morphs: Vec<MorphGeometry>,
+    // Position, normal and tangent displacements, uploaded once
+    pub morph_deltas: Vec<[f32; 9]>,
+    // Geometries with 32 bit indices start on an even entry and take two entries per index
+    pub indices: Vec<u16>,
+}
+
+/// Offset of every geometry in `CompactGeometry::colors`, `NO_COLORS` if all its vertices are white
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ColorLayout {
+    pub(crate) offsets: Vec<u32>,
+    pub(crate) len: usize,
+}
+
+/// Index size and offset of a geometry in `CompactGeometry::indices`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct IndexLayout {
+    pub(crate) u16: bool,
+    // In units of the index size
+    pub(crate) offset: u32,
+}
+
+impl GeoBuilder {
+    /// Index layout of every geometry and the length of the index stream in u16
+    pub(crate) fn index_layout(&self) -> (Vec<IndexLayout>, usize) {
+        let mut len = 0;
+        let layout = self
+            .len
+            .iter()
+            .map(|&[v_len, i_len]| {
+                if v_len <= U16_VERTICES {
+                    let offset = len;
+                    len += i_len;
+                    IndexLayout {
+                        u16: true,
+                        offset: offset as _,
+                    }
+                } else {
+                    len += len % 2;
+                    let offset = len / 2;
+                    len += i_len * 2;
+                    IndexLayout {
+                        u16: false,
+                        offset: offset as _,
+                    }
+                }
+            })
+            .collect();
+        // Storage buffers are read in 32 bit words
+        (layout, len + len % 2)
+    }
+
+    /// Scans every vertex, computed once and shared by `flatten` and `compact`
+    pub fn color_layout(&self) -> ColorLayout {
+        let mut len = 0;
+        let offsets = self
+            .offsets
+            .iter()
+            .zip(&self.len)
+            .map(|(&[v_offset, ..], &[v_len, _])| {
+                let v_offset = v_offset as usize;
+                let vertices = &self.vertices[v_offset..v_offset + v_len];
+                if vertices.iter().all(|v| v.color == Vec4::ONE) {
+                    return NO_COLORS;
+                }
+                len += v_len;
+                (len - v_len) as _
+            })
+            .collect();
+        ColorLayout { offsets, len }
+    }
+
+    /// Morph targets of every geometry, None for the ones without
+    pub(crate) fn morph_layout(&self) -> Vec<Option<MorphGeometry>> {
+        let (mut deltas, mut weights) = (0, 0);
+        self.morph_targets
+            .iter()
+            .enumerate()
+            .map(|(geo_id, targets)| {
+                if targets.is_empty() {
+                    return None;
+                }
+                let [vertices, _] = self.len[geo_id];
+                let targets = targets.len();
+                let morph = MorphGeometry {
+                    first_vertex: self.offsets[geo_id][0],
+                    vertices: vertices as _,
+                    targets: targets as _,
+                    deltas: deltas as _,
+                    weights: weights as _,
+                };
+                deltas += vertices * targets;
+                weights += targets;
+                Some(morph)
+            })
+            .collect()
+    }
+
+    /// Pack the vertices and indices for the GPU
+    pub fn compact(&self, color_layout: &ColorLayout) -> CompactGeometry {
+        let vertices = &self.vertices;
+        let (index_layout, index_len) = self.index_layout();
+
+        let mut indices = vec![0; index_len];
+        let mut colors = Vec::with_capacity(color_layout.len);
+        for (geo_id, &[v_offset, i_offset, _]) in self.offsets.iter().enumerate() {
+            let [v_len, i_len] = self.len[geo_id];
+            let (v_offset, i_offset) = (v_offset as usize, i_offset as usize);
+            let source = &self.indices[i_offset..i_offset + i_len];
+            let layout = index_layout[geo_id];
+            let offset = layout.offset as usize;
+            if layout.u16 {
+                indices[offset..offset + i_len]
+                    .iter_mut()
+                    .zip(source)
+                    .for_each(|(dst, &i)| *dst = i as u16);
+            } else {
+                indices[offset * 2..(offset + i_len) * 2]
+                    .chunks_exact_mut(2)
+                    .zip(source)
+                    .for_each(|(dst, &i)| {
+                        dst[0] = i as u16;
+                        dst[1] = (i >> 16) as u16;
+                    });
+            }
+            if color_layout.offsets[geo_id] != NO_COLORS {
+                colors.extend(
+                    vertices[v_offset..v_offset + v_len]
+                        .iter()
+                        .map(|v| pack_unorm4x8(v.color)),
+                );
+            }
+        }
+
+        let mut vertex_morphs = vec![NO_ANIMATION; vertices.len()];
+        let mut morphs = vec![];
+        let mut morph_deltas = vec![];
+        for (morph, targets) in self.morph_layout().into_iter().zip(&self.morph_targets) {
+            let Some(morph) = morph else {
+                continue;
+            };
+            let first = morph.first_vertex as usize;
+            let len = morph.vertices as usize;
+            vertex_morphs[first..first + len].fill(morphs.len() as _);
+            for target in targets {
+                morph_deltas.extend((0..len).map(|v| target.delta(v)));
+            }
+            morphs.push(morph);
+        }
+
+        CompactGeometry {
+            positions: vertices
+                .iter()
+                .map(|v| v.position.xyz().to_array())
+                .collect(),
+            attributes: vertices.iter().map(VertexAttributes::from).collect(),
+            colors,
+            animated: vertices
+                .iter()
+                .enumerate()
+                .filter(|&(i, v)| v.joint_offset >= 0 || vertex_morphs[i] != NO_ANIMATION)
+                .map(|(i, v)| AnimatedVertex {
+                    vertex: i as _,
+                    joint_offset: u32::try_from(v.joint_offset).unwrap_or(NO_ANIMATION),
+                    joints: v.joints.to_array().map(|j| j as u16),
+                    weights: v
+                        .weights
+                        .to_array()
+                        .map(|w| (w.clamp(0., 1.) * 65535.).round() as u16),
+                    morph: vertex_morphs[i],
+                    _padding: 0,
+                })
+                .collect(),
+            morphs,
+            morph_deltas,
+            indices,
+        }
+    }
+}
+
+impl From<&Vertex> for VertexAttributes {
+    fn from(v: &Vertex) -> Self {
+        let [x, y, z, w] = v.tangent;
+        let sign = if w < 0. { TANGENT_SIGN } else { 0 };
+        Self {
+            normal: encode_octahedral(v.normal.xyz()),
+            tangent: encode_octahedral(Vec3::new(x, y, z)) & !TANGENT_SIGN | sign,
+            uv0: v.uv0,
+            uv1: v.uv1,
+        }
+    }
+}
+
+fn sign_not_zero(v: Vec2) -> Vec2 {
+    Vec2::new(
+        if v.x >= 0. { 1. } else { -1. },
+        if v.y >= 0. { 1. } else { -1. },
+    )
+}
+
+/// Octahedral mapping as two snorm16, as read by `unpackSnorm2x16`. Zero vectors map to +Z.
+pub(crate) fn encode_octahedral(n: Vec3) -> u32 {
+    let sum = n.x.abs() + n.y.abs() + n.z.abs();
+    if sum == 0. || !sum.is_finite() {
+        return 0;
+    }
+    let n = n / sum;
+    let e = if n.z >= 0. {
+        n.truncate()
+    } else {
+        (Vec2::ONE - Vec2::new(n.y, n.x).abs()) * sign_not_zero(n.truncate())
+    };
+    let snorm = |f: f32| (f.clamp(-1., 1.) * 32767.).round() as i16 as u16 as u32;
+    snorm(e.x) | snorm(e.y) << 16
+}
+
+fn pack_unorm4x8(c: Vec4) -> u32 {
+    c.to_array()
+        .iter()
+        .enumerate()
+        .map(|(i, f)| ((f.clamp(0., 1.) * 255.).round() as u32) << (8 * i))
+        .sum()
+}
+
+#[test]
+fn test_compact() {
+    use crate::load_file;
+    // As `decodeOctahedral` in the shaders
+    let decode_octahedral = |bits: u32| {
+        let snorm = |b: u32| (b as u16 as i16 as f32 / 32767.).max(-1.);
+        let e = Vec2::new(snorm(bits), snorm(bits >> 16));
+        let n = Vec3::new(e.x, e.y, 1. - e.x.abs() - e.y.abs());
+        let t = (-n.z).max(0.);
+        let xy = n.truncate() - sign_not_zero(n.truncate()) * t;
+        xy.extend(n.z).normalize()
+    };
+    let mut n = Vec3::new(0.3, -0.8, -0.5).normalize();
+    for _ in 0..2 {
+        assert!((decode_octahedral(encode_octahedral(n)) - n).length() < 1e-4);
+        n = -n;
+    }
+    assert_eq!(decode_octahedral(encode_octahedral(Vec3::ZERO)), Vec3::Z);
+    let v = Vertex {
+        position: Vec4::ZERO,
+        normal: Vec4::Z,
+        tangent: [0., 0., -1., -1.],
+        color: Vec4::ONE,
+        weights: Vec4::ZERO,
+        joints: glam::UVec4::ZERO,
+        uv0: Vec2::ZERO,
+        uv1: Vec2::ZERO,
+        joint_offset: -1,
+    };
+    let tangent = VertexAttributes::from(&v).tangent;
+    assert_ne!(tangent & TANGENT_SIGN, 0);
+    assert!((decode_octahedral(tangent & !TANGENT_SIGN) + Vec3::Z).length() < 1e-4);
+
+    let dir = crate::TestDir::new(&[(
+        "quads.obj",
+        "o a\nv 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3 4\n\
+         o b\nv 0 0 1\nv 1 0 1\nv 1 1 1\nf 5 6 7\n",
+    )]);
+    let mut doc = load_file(dir.path("quads.obj"), &Default::default()).unwrap();
+    let builder = &mut doc.geo_builder;
+    // Pretend the first quad is too large for 16 bit indices
+    builder.len[0][0] = U16_VERTICES + 1;
+    let (layout, len) = builder.index_layout();
+    assert_eq!(
+        layout,
+        [
+            IndexLayout {
+                u16: false,
+                offset: 0
+            },
+            IndexLayout {
+                u16: true,
+                offset: 12
+            }
+        ]
+    );
+    assert_eq!(len, 16);
+    builder.len[0][0] = 4;
+    builder.vertices[4].color = Vec4::new(1., 0., 0., 1.);
+    let color_layout = builder.color_layout();
+    let compact = builder.compact(&color_layout);
+    assert_eq!(compact.positions.len(), 7);
+    assert_eq!(compact.colors, [0xff0000ff, u32::MAX, u32::MAX]);
+    assert_eq!(color_layout.offsets, [NO_COLORS, 0]);
+    assert!(compact.animated.is_empty());
+    assert_eq!(compact.indices.len(), 10);
+    assert_eq!(compact.indices[6..9], [0, 1, 2]);
+}
@@ -0,0 +1,258 @@
+use crate::error::*;
+use crate::geometry::PrimitiveData;
+use gltf::{buffer, Document};
+use log::info;
+use std::collections::HashMap;
+
+pub const KHR_DRACO_MESH_COMPRESSION: &str = "KHR_draco_mesh_compression";
+
+/// A Draco compressed primitive.
+/// Attributes map glTF semantics to Draco attribute ids.
+#[cfg_attr(not(feature = "draco"), allow(dead_code))]
+struct DracoPrimitive {
+    mesh: usize,
+    primitive: usize,
+    buffer_view: usize,
+    attributes: HashMap<String, u32>,
+}
+
+/// The gltf crate drops KHR_draco_mesh_compression, so read it from the raw JSON.
+fn find_draco_primitives(json: &serde_json::Value) -> Vec<DracoPrimitive> {
+    let raw_meshes = match json.get("meshes").and_then(|m| m.as_array()) {
+        Some(m) => m,
+        None => return vec![],
+    };
+    let mut res = vec![];
+    for (mesh, raw_mesh) in raw_meshes.iter().enumerate() {
+        let raw_primitives = raw_mesh.get("primitives").and_then(|p| p.as_array());
+        for (primitive, raw) in raw_primitives.into_iter().flatten().enumerate() {
+            let ext = match raw.pointer(&format!("/extensions/{}", KHR_DRACO_MESH_COMPRESSION)) {
+                Some(ext) => ext,
+                None => continue,
+            };
+            let buffer_view = ext.get("bufferView").and_then(|b| b.as_u64());
+            let attributes = ext.get("attributes").and_then(|a| a.as_object());
+            if let (Some(buffer_view), Some(attributes)) = (buffer_view, attributes) {
+                res.push(DracoPrimitive {
+                    mesh,
+                    primitive,
+                    buffer_view: buffer_view as _,
+                    attributes: attributes
+                        .iter()
+                        .filter_map(|(k, v)| Some((k.clone(), v.as_u64()? as u32)))
+                        .collect(),
+                });
+            }
+        }
+    }
+    res
+}
+
+/// Decode every Draco compressed primitive, keyed by [mesh, primitive] index.
+pub(crate) fn decode_primitives(
+    json: &serde_json::Value,
+    doc: &Document,
+    buffers: &[buffer::Data],
+) -> Result<HashMap<[usize; 2], PrimitiveData>> {
+    let primitives = find_draco_primitives(json);
+    if primitives.is_empty() {
+        return Ok(HashMap::new());
+    }
+    info!("Decoding {} Draco primitives", primitives.len());
+    cfg_if::cfg_if! {
+        if #[cfg(feature = "draco")] {
+            primitives
+                .iter()
+                .map(|p| Ok(([p.mesh, p.primitive], decode(p, doc, buffers)?)))
+                .collect()
+        } else {
+            let _ = (doc, buffers);
+            Err(Error::Support(format!(
+                "{} requires the `draco` feature of asset_loader",
+                KHR_DRACO_MESH_COMPRESSION
+            )))
+        }
+    }
+}
+
+#[cfg(feature = "draco")]
+fn decode(p: &DracoPrimitive, doc: &Document, buffers: &[buffer::Data]) -> Result<PrimitiveData> {
+    use glam::{UVec4, Vec2, Vec4};
+    use gltf::Semantic;
+
+    let fail = |reason: &str| {
+        Error::Support(format!(
+            "Draco decoding failed for mesh {} primitive {}: {}",
+            p.mesh, p.primitive, reason
+        ))
+    };
+    let view = doc
+        .views()
+        .nth(p.buffer_view)
+        .ok_or_else(|| fail("missing buffer view"))?;
+    let data = &buffers[view.buffer().index()][view.offset()..view.offset() + view.length()];
+    // The decoder panics on malformed bitstreams
+    let decoded = std::panic::catch_unwind(|| draco_decoder::decode_mesh_with_config_sync(data))
+        .ok()
+        .flatten()
+        .ok_or_else(|| fail("invalid bitstream"))?;
+    let gltf_primitive = doc
+        .meshes()
+        .nth(p.mesh)
+        .and_then(|m| m.primitives().nth(p.primitive))
+        .ok_or_else(|| fail("missing primitive"))?;
+
+    let config = &decoded.config;
+    let index_bytes = &decoded.data[..config.index_length() as usize];
+    let indices = if config.index_count() <= u16::MAX as u32 {
+        index_bytes
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]) as u32)
+            .collect()
+    } else {
+        index_bytes
+            .chunks_exact(4)
+            .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+            .collect()
+    };
+
+    // Components of an attribute as f32 with at least 4 per vertex, padded like glTF does
+    let attribute = |semantic: Semantic| -> Option<Vec<[f32; 4]>> {
+        let id = *p.attributes.get(&semantic.to_string())?;
+        let attr = config
+            .attributes()
+            .into_iter()
+            .find(|a| a.unique_id() == id)?;
+        let normalized = gltf_primitive
+            .get(&semantic)
+            .is_some_and(|a| a.normalized());
+        let bytes = &decoded.data[attr.offset() as usize..(attr.offset() + attr.lenght()) as usize];
+        let values = read_components(bytes, attr.data_type(), normalized);
+        let dim = attr.dim() as usize;
+        Some(
+            values
+                .chunks_exact(dim)
+                .map(|c| {
+                    let mut v = [0., 0., 0., 1.];
+                    v[..dim.min(4)].copy_from_slice(&c[..dim.min(4)]);
+                    v
+                })
+                .collect(),
+        )
+    };
+
+    let positions = attribute(Semantic::Positions)
+        .ok_or_else(|| fail("missing positions"))?
+        .into_iter()
+        .map(|[x, y, z, _]| Vec4::new(x, y, z, 0.))
+        .collect();
+    let uvs = |set| {
+        attribute(Semantic::TexCoords(set))
+            .map(|uv| uv.into_iter().map(|[u, v, ..]| Vec2::new(u, v)).collect())
+    };
+    Ok(PrimitiveData {
+        positions,
+        indices: Some(indices),
+        normals: attribute(Semantic::Normals).map(|n| {
+            n.into_iter()
+                .map(|[x, y, z, _]| Vec4::new(x, y, z, 0.))
+                .collect()
+        }),
+        tangents: attribute(Semantic::Tangents),
+        uvs0: uvs(0),
+        uvs1: uvs(1),
+        colors: attribute(Semantic::Colors(0)).map(|c| c.into_iter().map(Vec4::from).collect()),
+        weights: attribute(Semantic::Weights(0))
+            .map_or(vec![], |w| w.into_iter().map(Vec4::from).collect()),
+        joints: attribute(Semantic::Joints(0)).map_or(vec![], |j| {
+            j.into_iter()
+                .map(|j| UVec4::from(j.map(|x| x as u32)))
+                .collect()
+        }),
+    })
+}
+
+#[cfg(feature = "draco")]
+fn read_components(
+    bytes: &[u8],
+    data_type: draco_decoder::AttributeDataType,
+    normalized: bool,
+) -> Vec<f32> {
+    use draco_decoder::AttributeDataType::*;
+    // glTF rules for normalized integers
+    let norm = |v: f32, max: f32, signed: bool| {
+        if !normalized {
+            v
+        } else if signed {
+            (v / max).max(-1.)
+        } else {
+            v / max
+        }
+    };
+    match data_type {
+        Int8 => bytes
+            .iter()
+            .map(|&b| norm(b as i8 as f32, i8::MAX as f32, true))
+            .collect(),
+        UInt8 => bytes
+            .iter()
+            .map(|&b| norm(b as f32, u8::MAX as f32, false))
+            .collect(),
+        Int16 => bytes
+            .chunks_exact(2)
+            .map(|c| {
+                norm(
+                    i16::from_le_bytes([c[0], c[1]]) as f32,
+                    i16::MAX as f32,
+                    true,
+                )
+            })
+            .collect(),
+        UInt16 => bytes
+            .chunks_exact(2)
+            .map(|c| {
+                norm(
+                    u16::from_le_bytes([c[0], c[1]]) as f32,
+                    u16::MAX as f32,
+                    false,
+                )
+            })
+            .collect(),
+        Int32 => bytes
+            .chunks_exact(4)
+            .map(|c| i32::from_le_bytes([c[0], c[1], c[2], c[3]]) as f32)
+            .collect(),
+        UInt32 => bytes
+            .chunks_exact(4)
+            .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]) as f32)
+            .collect(),
+        Float32 => bytes
+            .chunks_exact(4)
+            .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+            .collect(),
+    }
+}
+
+#[test]
+fn test_find_draco_primitives() {
+    let json = serde_json::json!({
+        "meshes": [
+            {"primitives": [{"attributes": {"POSITION": 0}}]},
+            {"primitives": [
+                {"attributes": {"POSITION": 1}},
+                {
+                    "attributes": {"POSITION": 2, "NORMAL": 3},
+                    "extensions": {"KHR_draco_mesh_compression": {
+                        "bufferView": 4,
+                        "attributes": {"POSITION": 0, "NORMAL": 1}
+                    }}
+                }
+            ]}
+        ]
+    });
+    let primitives = find_draco_primitives(&json);
+    assert_eq!(primitives.len(), 1);
+    let p = &primitives[0];
+    assert_eq!([p.mesh, p.primitive, p.buffer_view], [1, 1, 4]);
+    assert_eq!(p.attributes["NORMAL"], 1);
+}
@@ -8,4 +8,8 @@ pub enum Error {
     Load(String),
     #[error("Unsupported gltf feature: {0}")]
     Support(String),
+    #[error("Failed to export gltf file: {0}")]
+    Export(String),
+    #[error("Loading was cancelled")]
+    Cancelled,
 }
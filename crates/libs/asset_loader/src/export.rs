@@ -0,0 +1,413 @@
+use crate::error::*;
+use crate::geometry::Mesh;
+use crate::Doc;
+use glam::{Vec2, Vec4};
+use log::{info, warn};
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+use std::path::Path;
+
+const UNSIGNED_SHORT: u32 = 5123;
+const UNSIGNED_INT: u32 = 5125;
+const FLOAT: u32 = 5126;
+
+/// Binary buffer of a glTF being built, with its views and accessors.
+/// Everything goes to buffer 0.
+#[derive(Default)]
+pub(crate) struct GltfBuffer {
+    pub(crate) data: Vec<u8>,
+    pub(crate) views: Vec<Value>,
+    pub(crate) accessors: Vec<Value>,
+}
+
+fn components(kind: &str) -> usize {
+    match kind {
+        "SCALAR" => 1,
+        "VEC2" => 2,
+        "VEC3" => 3,
+        "VEC4" => 4,
+        "MAT4" => 16,
+        _ => unreachable!("Unknown accessor type {}", kind),
+    }
+}
+
+impl GltfBuffer {
+    /// Float accessor. Bounds are required for positions and animation inputs.
+    pub(crate) fn push_floats(&mut self, values: &[f32], kind: &str, bounds: bool) -> usize {
+        let n = components(kind);
+        let mut accessor = json!({
+            "componentType": FLOAT,
+            "count": values.len() / n,
+            "type": kind,
+        });
+        if bounds {
+            let bound = |f: fn(f32, f32) -> f32, init: f32| {
+                (0..n)
+                    .map(|c| values.iter().skip(c).step_by(n).fold(init, |a, &b| f(a, b)))
+                    .collect::<Vec<_>>()
+            };
+            accessor["min"] = json!(bound(f32::min, f32::MAX));
+            accessor["max"] = json!(bound(f32::max, f32::MIN));
+        }
+        let bytes: Vec<_> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+        self.push_accessor(&bytes, accessor)
+    }
+
+    pub(crate) fn push_u16(&mut self, values: &[u16], kind: &str) -> usize {
+        let accessor = json!({
+            "componentType": UNSIGNED_SHORT,
+            "count": values.len() / components(kind),
+            "type": kind,
+        });
+        let bytes: Vec<_> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+        self.push_accessor(&bytes, accessor)
+    }
+
+    pub(crate) fn push_indices(&mut self, indices: &[u32]) -> usize {
+        let accessor = json!({
+            "componentType": UNSIGNED_INT,
+            "count": indices.len(),
+            "type": "SCALAR",
+        });
+        let bytes: Vec<_> = indices.iter().flat_map(|i| i.to_le_bytes()).collect();
+        self.push_accessor(&bytes, accessor)
+    }
+
+    /// A buffer view of raw bytes, kept 4 byte aligned
+    pub(crate) fn push_view(&mut self, bytes: &[u8]) -> usize {
+        self.views.push(json!({
+            "buffer": 0,
+            "byteOffset": self.data.len(),
+            "byteLength": bytes.len(),
+        }));
+        self.data.extend_from_slice(bytes);
+        self.data.resize(self.data.len().next_multiple_of(4), 0);
+        self.views.len() - 1
+    }
+
+    fn push_accessor(&mut self, bytes: &[u8], mut accessor: Value) -> usize {
+        accessor["bufferView"] = self.push_view(bytes).into();
+        self.accessors.push(accessor);
+        self.accessors.len() - 1
+    }
+}
+
+/// Add the name to a glTF object if there is one
+pub(crate) fn named(mut value: Value, name: &Option<String>) -> Value {
+    if let Some(name) = name {
+        value["name"] = name.as_str().into();
+    }
+    value
+}
+
+/// Keys of every `extensions` object, for `extensionsUsed`
+fn extensions_used(value: &Value, used: &mut BTreeSet<String>) {
+    match value {
+        Value::Object(map) => map.iter().for_each(|(key, v)| {
+            if key == "extensions" {
+                used.extend(v.as_object().into_iter().flat_map(|e| e.keys().cloned()));
+            }
+            extensions_used(v, used);
+        }),
+        Value::Array(values) => values.iter().for_each(|v| extensions_used(v, used)),
+        _ => {}
+    }
+}
+
+fn mesh_json(doc: &Doc, mesh: &Mesh, buffer: &mut GltfBuffer) -> Value {
+    let geo_builder = &doc.geo_builder;
+    let primitives: Vec<_> = mesh
+        .primitives
+        .iter()
+        .map(|p| {
+            let geo_id = p.geometry_id as usize;
+            let [v_offset, i_offset, _] = geo_builder.offsets[geo_id].map(|o| o as usize);
+            let [v_len, i_len] = geo_builder.len[geo_id];
+            let vertices = &geo_builder.vertices[v_offset..v_offset + v_len];
+            let indices = &geo_builder.indices[i_offset..i_offset + i_len];
+
+            let positions: Vec<_> = vertices
+                .iter()
+                .flat_map(|v| v.position.truncate().to_array())
+                .collect();
+            let normals: Vec<_> = vertices
+                .iter()
+                .flat_map(|v| v.normal.truncate().to_array())
+                .collect();
+            let uvs: Vec<_> = vertices.iter().flat_map(|v| v.uv0.to_array()).collect();
+            let mut attributes = json!({
+                "POSITION": buffer.push_floats(&positions, "VEC3", true),
+                "NORMAL": buffer.push_floats(&normals, "VEC3", false),
+                "TEXCOORD_0": buffer.push_floats(&uvs, "VEC2", false),
+            });
+            // Placeholder tangents have w = 0
+            if vertices.iter().all(|v| v.tangent[3].abs() == 1.) {
+                let tangents: Vec<_> = vertices.iter().flat_map(|v| v.tangent).collect();
+                attributes["TANGENT"] = buffer.push_floats(&tangents, "VEC4", false).into();
+            }
+            if vertices.iter().any(|v| v.uv1 != Vec2::ZERO) {
+                let uvs: Vec<_> = vertices.iter().flat_map(|v| v.uv1.to_array()).collect();
+                attributes["TEXCOORD_1"] = buffer.push_floats(&uvs, "VEC2", false).into();
+            }
+            if vertices.iter().any(|v| v.color != Vec4::ONE) {
+                let colors: Vec<_> = vertices.iter().flat_map(|v| v.color.to_array()).collect();
+                attributes["COLOR_0"] = buffer.push_floats(&colors, "VEC4", false).into();
+            }
+            if vertices.iter().any(|v| v.weights != Vec4::ZERO) {
+                let joints: Vec<_> = vertices
+                    .iter()
+                    .flat_map(|v| v.joints.to_array().map(|j| j as u16))
+                    .collect();
+                let weights: Vec<_> = vertices.iter().flat_map(|v| v.weights.to_array()).collect();
+                attributes["JOINTS_0"] = buffer.push_u16(&joints, "VEC4").into();
+                attributes["WEIGHTS_0"] = buffer.push_floats(&weights, "VEC4", false).into();
+            }
+
+            let mut primitive = json!({
+                "attributes": attributes,
+                "indices": buffer.push_indices(indices),
+            });
+            // The selected variant is baked in
+            let material = geo_builder.material_id[geo_id];
+            if material < doc.materials.len() {
+                primitive["material"] = material.into();
+            }
+            let targets = &geo_builder.morph_targets[geo_id];
+            if !targets.is_empty() {
+                primitive["targets"] = targets.iter().map(|t| t.to_json(buffer)).collect();
+            }
+            primitive
+        })
+        .collect();
+    let mut res = named(json!({ "primitives": primitives }), &mesh.name);
+    if !mesh.weights.is_empty() {
+        res["weights"] = json!(mesh.weights);
+    }
+    res
+}
+
+impl Doc {
+    /// Write the doc to a .glb, or to a .gltf with a .bin next to it.
+    /// Geometry is written as processed, with generated normals and tangents
+    /// and the selected material variant. Images are stored as PNG.
+    /// Nodes keep the units and up axis of the file, `aabb_trans` is not applied.
+    pub fn export<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let mut buffer = GltfBuffer::default();
+
+        // Images used as color were linearized if they had more than 8 bits
+        let color_images: BTreeSet<_> = self
+            .materials
+            .iter()
+            .flat_map(|m| m.color_textures())
+            .filter_map(|t| self.textures.get(t).map(|t| t.image_index))
+            .collect();
+        let mut image_map = vec![None; self.images.len()];
+        let mut images = vec![];
+        for image in self.images.iter().skip(1) {
+            match image.to_png(color_images.contains(&image.index)) {
+                Some(png) => {
+                    image_map[image.index] = Some(images.len());
+                    let view = buffer.push_view(&png);
+                    images.push(json!({"bufferView": view, "mimeType": "image/png"}));
+                }
+                None => warn!(
+                    "Skipping image {:?}: {:?} can not be exported",
+                    image.source, image.format
+                ),
+            }
+        }
+
+        let mut texture_map = vec![None; self.textures.len()];
+        let mut textures = vec![];
+        for texture in self.textures.iter().skip(1) {
+            if let Some(source) = image_map[texture.image_index] {
+                texture_map[texture.index] = Some(textures.len());
+                let mut res = named(json!({ "source": source }), &texture.name);
+                if texture.sampler_index > 0 {
+                    res["sampler"] = (texture.sampler_index - 1).into();
+                }
+                textures.push(res);
+            }
+        }
+        let texture = |index: i32| {
+            usize::try_from(index)
+                .ok()
+                .and_then(|i| texture_map.get(i).copied().flatten())
+        };
+
+        let materials: Vec<_> = self.materials.iter().map(|m| m.to_json(&texture)).collect();
+        let meshes: Vec<_> = self
+            .meshes
+            .iter()
+            .map(|m| mesh_json(self, m, &mut buffer))
+            .collect();
+        let nodes: Vec<_> = self.nodes.iter().map(|n| n.to_json(&mut buffer)).collect();
+        let skins: Vec<_> = self.skins.iter().map(|s| s.to_json(&mut buffer)).collect();
+        let animations: Vec<_> = self
+            .animations
+            .iter()
+            .map(|a| a.to_json(&mut buffer))
+            .collect();
+
+        let mut root = json!({
+            "asset": {"version": "2.0", "generator": "rustracer"},
+            "scene": self.current_scene,
+            "scenes": self.scenes.iter().map(|s| s.to_json()).collect::<Vec<_>>(),
+            "nodes": nodes,
+            "meshes": meshes,
+            "materials": materials,
+            "textures": textures,
+            "images": images,
+            "samplers": self.samplers.iter().skip(1).map(|s| s.to_json()).collect::<Vec<_>>(),
+            "cameras": self.cameras.iter().map(|c| c.to_json()).collect::<Vec<_>>(),
+            "skins": skins,
+            "animations": animations,
+        });
+        if !self.lights.is_empty() {
+            root["extensions"] = json!({"KHR_lights_punctual": {
+                "lights": self.lights.iter().map(|l| l.to_json()).collect::<Vec<_>>()
+            }});
+        }
+        // Empty arrays are not allowed
+        if let Some(map) = root.as_object_mut() {
+            map.retain(|_, v| !v.as_array().is_some_and(|a| a.is_empty()));
+        }
+        let mut used = BTreeSet::new();
+        extensions_used(&root, &mut used);
+        if !used.is_empty() {
+            root["extensionsUsed"] = json!(used);
+        }
+
+        let glb = path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("glb"));
+        let bin = buffer.data;
+        let export_error = |e: std::io::Error| Error::Export(e.to_string());
+        if glb {
+            root["buffers"] = json!([{ "byteLength": bin.len() }]);
+            root["bufferViews"] = json!(buffer.views);
+            root["accessors"] = json!(buffer.accessors);
+            std::fs::write(path, to_glb(&root, bin)).map_err(export_error)?;
+        } else {
+            let bin_path = path.with_extension("bin");
+            let uri = bin_path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .ok_or_else(|| Error::Export(format!("Invalid path {}", path.display())))?;
+            root["buffers"] = json!([{ "byteLength": bin.len(), "uri": urlencoding::encode(uri) }]);
+            root["bufferViews"] = json!(buffer.views);
+            root["accessors"] = json!(buffer.accessors);
+            let json =
+                serde_json::to_vec_pretty(&root).map_err(|e| Error::Export(e.to_string()))?;
+            std::fs::write(&bin_path, bin).map_err(export_error)?;
+            std::fs::write(path, json).map_err(export_error)?;
+        }
+        info!("Exported {}", path.display());
+        Ok(())
+    }
+}
+
+fn to_glb(root: &Value, mut bin: Vec<u8>) -> Vec<u8> {
+    let mut json = root.to_string().into_bytes();
+    // Chunks are 4 byte aligned, JSON with spaces
+    json.resize(json.len().next_multiple_of(4), b' ');
+    bin.resize(bin.len().next_multiple_of(4), 0);
+    let length = 12 + 8 + json.len() + 8 + bin.len();
+    let mut glb = Vec::with_capacity(length);
+    glb.extend_from_slice(b"glTF");
+    glb.extend_from_slice(&2u32.to_le_bytes());
+    glb.extend_from_slice(&(length as u32).to_le_bytes());
+    for (chunk, kind) in [(json, b"JSON"), (bin, b"BIN\0")] {
+        glb.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+        glb.extend_from_slice(kind);
+        glb.extend_from_slice(&chunk);
+    }
+    glb
+}
+
+#[test]
+fn test_export() {
+    let dir = crate::TestDir::new(&[
+        ("tri.mtl", "newmtl red\nKd 1 0 0\nKs 0.5 0.5 0.5\n"),
+        (
+            "tri.obj",
+            "mtllib tri.mtl\no tri\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl red\nf 1 2 3\n",
+        ),
+    ]);
+    let doc = crate::load_file(dir.path("tri.obj"), &Default::default()).unwrap();
+
+    for file in ["tri.glb", "tri.gltf"] {
+        doc.export(dir.path(file)).unwrap();
+        let (document, buffers, _) = gltf::import(dir.path(file)).unwrap();
+        let primitive = document
+            .meshes()
+            .next()
+            .unwrap()
+            .primitives()
+            .next()
+            .unwrap();
+        let reader = primitive.reader(|b| Some(&buffers[b.index()]));
+        assert_eq!(reader.read_positions().unwrap().count(), 3);
+        // Normals were generated by the loader
+        let normal = reader.read_normals().unwrap().next().unwrap();
+        assert_eq!(normal.map(f32::abs), [0., 0., 1.]);
+        let material = primitive.material();
+        assert_eq!(
+            material.pbr_metallic_roughness().base_color_factor(),
+            [1., 0., 0., 1.]
+        );
+        assert_eq!(
+            material.specular().unwrap().specular_color_factor(),
+            [0.5; 3]
+        );
+        assert_eq!(document.scenes().next().unwrap().nodes().count(), 1);
+    }
+}
+
+#[test]
+fn test_export_rest_pose() {
+    use serde_json::json;
+    let triangle = [0., 0., 0., 1., 0., 0., 0., 1., 0.];
+    let keyframes = [0., 1., 0., 0., 0., 5., 0., 0.];
+    let floats: Vec<f32> = [&triangle[..], &keyframes].concat();
+    let bytes: Vec<u8> = floats.iter().flat_map(|f| f.to_le_bytes()).collect();
+    let gltf = json!({
+        "asset": {"version": "2.0"},
+        "buffers": [{"byteLength": 68, "uri": format!(
+            "data:application/octet-stream;base64,{}", base64::encode(bytes))}],
+        "bufferViews": [{"buffer": 0, "byteLength": 68}],
+        "accessors": [
+            {"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                "min": [0, 0, 0], "max": [1, 1, 0]},
+            {"bufferView": 0, "byteOffset": 36, "componentType": 5126, "count": 2,
+                "type": "SCALAR", "min": [0], "max": [1]},
+            {"bufferView": 0, "byteOffset": 44, "componentType": 5126, "count": 2,
+                "type": "VEC3"}
+        ],
+        "meshes": [{"primitives": [{"attributes": {"POSITION": 0}}]}],
+        "nodes": [{"mesh": 0, "translation": [1, 0, 0]}],
+        "animations": [{
+            "samplers": [{"input": 1, "output": 2}],
+            "channels": [{"sampler": 0, "target": {"node": 0, "path": "translation"}}]
+        }],
+        "scenes": [{"nodes": [0]}]
+    });
+    let dir = crate::TestDir::new(&[("moving.gltf", &gltf.to_string())]);
+    let mut doc = crate::load_file(dir.path("moving.gltf"), &Default::default()).unwrap();
+    doc.update_animations(0.5);
+
+    doc.export(dir.path("out.glb")).unwrap();
+    let (document, buffers, _) = gltf::import(dir.path("out.glb")).unwrap();
+    let node = document.nodes().next().unwrap();
+    assert_eq!(node.transform().decomposed().0, [1., 0., 0.]);
+    // Not fitted into the 10 unit box
+    let primitive = node.mesh().unwrap().primitives().next().unwrap();
+    let reader = primitive.reader(|b| Some(&buffers[b.index()]));
+    let max_x = reader
+        .read_positions()
+        .unwrap()
+        .map(|p| p[0])
+        .fold(0., f32::max);
+    assert_eq!(max_x, 1.);
+}
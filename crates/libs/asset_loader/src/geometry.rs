@@ -1,14 +1,25 @@
 use crate::aabb::{get_aabb, Aabb};
+use crate::compact::ColorLayout;
 use crate::material::Material;
+use crate::morph::MorphTarget;
+use crate::report::LoadReport;
 use crate::{a3toa4, get_name, Index, MeshID, Name};
+use cfg_if::cfg_if;
 use glam::{vec4, UVec4, Vec2, Vec3, Vec4, Vec4Swizzles};
 use gltf::mesh::Mode;
-use gltf::{buffer, Semantic};
+use gltf::{buffer, Document, Semantic};
 use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
+use std::hash::Hasher;
+use twox_hash::XxHash64;
+
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
 
 #[repr(C)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct Vertex {
     pub position: Vec4,
     pub normal: Vec4,
@@ -18,18 +29,22 @@ pub struct Vertex {
     pub joints: UVec4,
     pub uv0: Vec2,
     pub uv1: Vec2,
-    pub skin_index: i32,
+    // First joint of the skin in the packed joint buffer, -1 without skin
+    pub joint_offset: i32,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Mesh {
     pub(crate) index: MeshID,
     pub name: Name,
     pub(crate) primitives: Vec<Primitive>,
+    // Default morph target weights
+    pub(crate) weights: Vec<f32>,
 }
 
-#[derive(Default)]
+#[derive(Default, Serialize, Deserialize)]
 pub struct GeoBuilder {
+    #[serde(skip)]
     pub(crate) buffers: Vec<buffer::Data>,
     pub vertices: Vec<Vertex>,
     pub(crate) indices: Vec<Index>,
@@ -41,6 +56,17 @@ pub struct GeoBuilder {
     pub normal_textures: Vec<bool>,
     pub opaque: Vec<bool>,
     pub material_id: Vec<usize>,
+    // Morph targets of each geometry
+    pub(crate) morph_targets: Vec<Vec<MorphTarget>>,
+    // Already decoded primitives by [mesh, primitive] index
+    #[serde(skip)]
+    pub(crate) decoded: HashMap<[usize; 2], PrimitiveData>,
+    // From `LoadOptions`, the crease angle is in radians
+    pub(crate) crease_angle: f32,
+    pub(crate) regenerate_tangents: bool,
+    pub(crate) weld: bool,
+    // Skipped primitives and generated attributes
+    pub(crate) report: LoadReport,
 }
 
 impl GeoBuilder {
@@ -60,30 +86,31 @@ impl GeoBuilder {
     pub fn fully_opaque(&self) -> bool {
         self.opaque.iter().all(|o| *o)
     }
+
+    pub fn has_morph_targets(&self) -> bool {
+        self.morph_targets.iter().any(|t| !t.is_empty())
+    }
+
+    pub(crate) fn set_material(&mut self, geo_id: u32, material_id: usize) {
+        let geo_id = geo_id as usize;
+        self.material_id[geo_id] = material_id;
+        self.offsets[geo_id][2] = material_id as _;
+    }
 }
 
 #[repr(C)]
 #[derive(Copy, Clone)]
 pub struct PrimInfo {
     v_offset: u32,
+    // In units of the index size
     i_offset: u32,
     material_id: u32,
-    skin_id: u32,
+    index_u16: u32,
+    // `NO_COLORS` without vertex colors
+    color_offset: u32,
     // transform: Mat4
 }
 
-impl PrimInfo {
-    fn new(&[v_off, i_off, mat]: &[u32; 3]) -> Self {
-        Self {
-            v_offset: v_off,
-            i_offset: i_off,
-            material_id: mat,
-            skin_id: 0,
-            // transform: Default::default(),
-        }
-    }
-}
-
 impl GeoBuilder {
     pub fn next_geo_id(&mut self, material_id: u32) -> u32 {
         let cur = self.geo_counter;
@@ -92,25 +119,292 @@ impl GeoBuilder {
         cur
     }
 
-    pub fn flatten(&self) -> Vec<PrimInfo> {
-        self.offsets.iter().map(PrimInfo::new).collect()
+    /// Copy the vertices, indices and morph targets of a geometry into a new one
+    pub(crate) fn duplicate(&mut self, geo_id: u32) -> u32 {
+        let geo_id = geo_id as usize;
+        let [v_offset, i_offset, material_id] = self.offsets[geo_id];
+        let [v_len, i_len] = self.len[geo_id];
+        let new_id = self.next_geo_id(material_id);
+        self.offsets.push([
+            self.vertices.len() as _,
+            self.indices.len() as _,
+            material_id,
+        ]);
+        self.len.push([v_len, i_len]);
+        let (v_offset, i_offset) = (v_offset as usize, i_offset as usize);
+        self.vertices.extend_from_within(v_offset..v_offset + v_len);
+        self.indices.extend_from_within(i_offset..i_offset + i_len);
+        self.morph_targets.push(self.morph_targets[geo_id].clone());
+        new_id
+    }
+
+    /// Geometry infos for the shaders, matching the layout of `compact`
+    pub fn flatten(&self, color_layout: &ColorLayout) -> Vec<PrimInfo> {
+        let (index_layout, _) = self.index_layout();
+        self.offsets
+            .iter()
+            .zip(index_layout)
+            .zip(&color_layout.offsets)
+            .map(
+                |((&[v_offset, _, material_id], index), &color_offset)| PrimInfo {
+                    v_offset,
+                    i_offset: index.offset,
+                    material_id,
+                    index_u16: index.u16 as _,
+                    color_offset,
+                },
+            )
+            .collect()
     }
 }
 
-impl Mesh {
-    pub(crate) fn new(mesh: gltf::Mesh, builder: &mut GeoBuilder) -> Self {
-        let index = mesh.index();
-        let mut primitives = vec![];
-        let name = mesh.name();
-        info!("Building mesh {}: {:?} ", index, name);
-        for primitive in mesh.primitives().filter(is_primitive_supported) {
-            primitives.push(Primitive::from(primitive, builder));
+/// Vertex attributes of a primitive before any generation step.
+#[derive(Default)]
+pub(crate) struct PrimitiveData {
+    pub(crate) positions: Vec<Vec4>,
+    pub(crate) indices: Option<Vec<Index>>,
+    pub(crate) normals: Option<Vec<Vec4>>,
+    pub(crate) tangents: Option<Vec<[f32; 4]>>,
+    pub(crate) uvs0: Option<Vec<Vec2>>,
+    pub(crate) uvs1: Option<Vec<Vec2>>,
+    pub(crate) colors: Option<Vec<Vec4>>,
+    pub(crate) weights: Vec<Vec4>,
+    pub(crate) joints: Vec<UVec4>,
+}
+
+impl PrimitiveData {
+    fn read<'a, 's, F>(reader: &gltf::mesh::Reader<'a, 's, F>) -> Self
+    where
+        F: Clone + Fn(gltf::Buffer<'a>) -> Option<&'s [u8]>,
+    {
+        let read_tex_coords = |set| {
+            reader
+                .read_tex_coords(set)
+                .map(|reader| reader.into_f32().map(Vec2::from).collect::<Vec<_>>())
+        };
+        Self {
+            positions: reader
+                .read_positions()
+                .unwrap()
+                .map(|p| vec4(p[0], p[1], p[2], 0.))
+                .collect(),
+            indices: reader
+                .read_indices()
+                .map(|index_reader| index_reader.into_u32().collect()),
+            normals: reader
+                .read_normals()
+                .map(|rn| rn.map(|n| vec4(n[0], n[1], n[2], 0.0)).collect()),
+            tangents: reader.read_tangents().map(|iter| iter.collect()),
+            uvs0: read_tex_coords(0),
+            uvs1: read_tex_coords(1),
+            colors: reader
+                .read_colors(0)
+                .map(|reader| reader.into_rgba_f32().map(Vec4::from).collect()),
+            weights: reader.read_weights(0).map_or(vec![], |weights| {
+                weights.into_f32().map(Vec4::from).collect()
+            }),
+            joints: reader.read_joints(0).map_or(vec![], |joints| {
+                joints
+                    .into_u16()
+                    .map(|[x, y, z, w]| [u32::from(x), u32::from(y), u32::from(z), u32::from(w)])
+                    .map(UVec4::from)
+                    .collect()
+            }),
+        }
+    }
+}
+
+/// Mesh cleanup before the vertices are built
+impl PrimitiveData {
+    /// Keep the vertices at `sources`, in order, along with their morph target displacements
+    fn reindex(&mut self, sources: &[Index], targets: &mut [MorphTarget]) {
+        reindex(&mut self.positions, sources);
+        self.normals.iter_mut().for_each(|n| reindex(n, sources));
+        self.tangents.iter_mut().for_each(|t| reindex(t, sources));
+        self.uvs0.iter_mut().for_each(|uv| reindex(uv, sources));
+        self.uvs1.iter_mut().for_each(|uv| reindex(uv, sources));
+        self.colors.iter_mut().for_each(|c| reindex(c, sources));
+        reindex(&mut self.weights, sources);
+        reindex(&mut self.joints, sources);
+        targets.iter_mut().for_each(|t| t.reindex(sources));
+    }
+
+    /// Drop triangles with out of range indices or non-finite positions,
+    /// and the ones without area. Returns both counts.
+    fn remove_bad_triangles(&self, indices: &mut Vec<Index>) -> [usize; 2] {
+        let (mut invalid, mut degenerate) = (0, 0);
+        let triangles = indices.len() / 3;
+        let mut kept = Vec::with_capacity(indices.len());
+        for t in indices.chunks_exact(3) {
+            let Some(p) = t
+                .iter()
+                .map(|&i| self.positions.get(i as usize).map(|p| p.xyz()))
+                .collect::<Option<Vec<_>>>()
+                .filter(|p| p.iter().all(|p| p.is_finite()))
+            else {
+                invalid += 1;
+                continue;
+            };
+            let (e1, e2) = (p[1] - p[0], p[2] - p[0]);
+            // Relative to the edges, so that small triangles are kept
+            let area2 = e1.cross(e2).length_squared();
+            if area2 <= f32::EPSILON * f32::EPSILON * e1.length_squared() * e2.length_squared() {
+                degenerate += 1;
+                continue;
+            }
+            kept.extend_from_slice(t);
         }
-        Mesh {
-            primitives,
-            index,
-            name: get_name!(mesh),
+        if invalid + degenerate > 0 {
+            warn!(
+                "Removed {} invalid and {} degenerate triangles of {}",
+                invalid, degenerate, triangles
+            );
         }
+        *indices = kept;
+        [invalid, degenerate]
+    }
+
+    /// Attributes and morph target displacements of a vertex as bits
+    fn vertex_bits<'a>(
+        &'a self,
+        v: usize,
+        targets: &'a [MorphTarget],
+    ) -> impl Iterator<Item = u32> + 'a {
+        let bits = |v: Vec4| v.to_array().map(f32::to_bits);
+        let uvs = self.uvs0.iter().chain(&self.uvs1);
+        bits(self.positions[v])
+            .into_iter()
+            .chain(self.normals.iter().flat_map(move |n| bits(n[v])))
+            .chain(
+                self.tangents
+                    .iter()
+                    .flat_map(move |t| t[v].map(f32::to_bits)),
+            )
+            .chain(uvs.flat_map(move |uvs| uvs[v].to_array().map(f32::to_bits)))
+            .chain(self.colors.iter().flat_map(move |c| bits(c[v])))
+            .chain(self.weights.get(v).into_iter().flat_map(move |&w| bits(w)))
+            .chain(self.joints.get(v).into_iter().flat_map(|j| j.to_array()))
+            .chain(targets.iter().flat_map(move |t| t.vertex_bits(v)))
+    }
+
+    /// Merge vertices with equal attributes and drop unused ones.
+    /// Returns the number of vertices removed.
+    fn weld(&mut self, indices: &mut [Index], targets: &mut [MorphTarget]) -> usize {
+        // Hash of the attributes to the welded vertex. Colliding hashes probe the next one.
+        let mut welded: HashMap<u64, Index> = HashMap::new();
+        let mut sources = vec![];
+        // Welded vertex of each vertex, indices share vertices
+        let mut remap = vec![None; self.positions.len()];
+        for index in indices.iter_mut() {
+            let v = *index as usize;
+            if let Some(welded) = remap[v] {
+                *index = welded;
+                continue;
+            }
+            let mut hasher = XxHash64::default();
+            self.vertex_bits(v, targets)
+                .for_each(|bits| hasher.write_u32(bits));
+            let mut hash = hasher.finish();
+            *index = loop {
+                match welded.entry(hash) {
+                    Entry::Vacant(entry) => {
+                        sources.push(v as Index);
+                        break *entry.insert(sources.len() as Index - 1);
+                    }
+                    Entry::Occupied(entry) => {
+                        let source = sources[*entry.get() as usize] as usize;
+                        if self
+                            .vertex_bits(source, targets)
+                            .eq(self.vertex_bits(v, targets))
+                        {
+                            break *entry.get();
+                        }
+                    }
+                }
+                hash = hash.wrapping_add(1);
+            };
+            remap[v] = Some(*index);
+        }
+        // Vertices are renumbered in order of first use
+        let removed = self.positions.len() - sources.len();
+        self.reindex(&sources, targets);
+        removed
+    }
+
+    /// Normals weighted by triangle area and corner angle. Triangles sharing a position
+    /// are smoothed together when their normals are within `crease_angle` radians,
+    /// vertices on sharper edges are split.
+    fn generate_normals(
+        &mut self,
+        indices: &mut [Index],
+        targets: &mut [MorphTarget],
+        crease_angle: f32,
+    ) {
+        info!(
+            "Creating normals, crease angle {}",
+            crease_angle.to_degrees()
+        );
+        let position = |i: Index| self.positions[i as usize].xyz();
+        let faces: Vec<_> = indices
+            .chunks_exact(3)
+            .map(|t| (position(t[1]) - position(t[0])).cross(position(t[2]) - position(t[0])))
+            .collect();
+        let units: Vec<_> = faces.iter().map(|f| f.normalize_or_zero()).collect();
+        let weighted: Vec<_> = (0..indices.len())
+            .map(|corner| {
+                let t = &indices[corner / 3 * 3..][..3];
+                let k = corner % 3;
+                let p = position(t[k]);
+                let angle =
+                    (position(t[(k + 1) % 3]) - p).angle_between(position(t[(k + 2) % 3]) - p);
+                faces[corner / 3] * angle
+            })
+            .collect();
+
+        // Corners on the same position, across UV seams and other splits
+        let mut groups: HashMap<[u32; 3], Vec<usize>> = HashMap::new();
+        for (corner, &i) in indices.iter().enumerate() {
+            let key = position(i).to_array().map(f32::to_bits);
+            groups.entry(key).or_default().push(corner);
+        }
+        // Flat normals still merge coplanar triangles
+        let min_cos = crease_angle.cos() - 1e-5;
+        let mut normals = vec![Vec3::ZERO; indices.len()];
+        for corners in groups.values() {
+            for &corner in corners {
+                let face = units[corner / 3];
+                normals[corner] = corners
+                    .iter()
+                    .filter(|&&c| units[c / 3].dot(face) >= min_cos)
+                    .map(|&c| weighted[c])
+                    .sum::<Vec3>()
+                    .normalize_or_zero();
+            }
+        }
+
+        // One vertex per original vertex and distinct normal
+        let mut split: HashMap<(Index, [u32; 3]), Index> = HashMap::new();
+        let mut sources = vec![];
+        let mut vertex_normals = vec![];
+        for (corner, index) in indices.iter_mut().enumerate() {
+            let (original, normal) = (*index, normals[corner]);
+            let key = (original, normal.to_array().map(f32::to_bits));
+            *index = *split.entry(key).or_insert_with(|| {
+                sources.push(original);
+                vertex_normals.push(Vec4::from((normal, 0.)));
+                sources.len() as Index - 1
+            });
+        }
+        self.reindex(&sources, targets);
+        self.normals = Some(vertex_normals);
+    }
+}
+
+impl Mesh {
+    pub fn has_morph_targets(&self, builder: &GeoBuilder) -> bool {
+        self.primitives
+            .iter()
+            .any(|p| !builder.morph_targets[p.geometry_id as usize].is_empty())
     }
 
     pub fn get_aabb(&self) -> Option<Aabb> {
@@ -119,17 +413,211 @@ impl Mesh {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Primitive {
-    // pub(crate) material: MaterialID,
+    // Material without any variant selected
+    pub(crate) material: usize,
     pub geometry_id: u32,
+    // KHR_materials_variants: variant index to material
     mapping: HashMap<u32, usize>,
     aabb: Aabb,
 }
 
 pub const DEFAULT_MATERIAL_INDEX: usize = 0;
-impl Primitive {
-    fn from(primitive: gltf::Primitive, builder: &mut GeoBuilder) -> Self {
+
+/// Vertices of one primitive, built independently of the others
+struct BuiltPrimitive {
+    vertices: Vec<Vertex>,
+    indices: Vec<Index>,
+    morph_targets: Vec<MorphTarget>,
+    // Generated attributes and cleanup of this primitive
+    report: LoadReport,
+}
+
+impl GeoBuilder {
+    /// Build every mesh of the document. Primitives are decoded and processed in parallel
+    /// with the `rayon` feature, then appended in document order,
+    /// so geometry ids and offsets do not depend on scheduling.
+    pub(crate) fn build_meshes(&mut self, doc: &Document) -> Vec<Mesh> {
+        let mut meshes = vec![];
+        let mut jobs = vec![];
+        for mesh in doc.meshes() {
+            let index = mesh.index();
+            info!("Building mesh {}: {:?} ", index, mesh.name());
+            for primitive in mesh.primitives() {
+                if primitive.get(&Semantic::Positions).is_none() {
+                    warn!(
+                        "Mesh {} primitive {} has no positions",
+                        index,
+                        primitive.index()
+                    );
+                } else if is_primitive_supported(&primitive) {
+                    // Draco compressed primitives are decoded up front
+                    let decoded = self.decoded.remove(&[index, primitive.index()]);
+                    jobs.push((index, primitive, decoded));
+                } else {
+                    warn!(
+                        "Skipping mesh {} primitive {}: {:?} are not supported",
+                        index,
+                        primitive.index(),
+                        primitive.mode()
+                    );
+                    self.report
+                        .skipped_primitives
+                        .push(([index, primitive.index()], primitive.mode()));
+                }
+            }
+            meshes.push(Mesh {
+                primitives: vec![],
+                index,
+                name: get_name!(mesh),
+                weights: mesh.weights().map_or(vec![], |w| w.to_vec()),
+            });
+        }
+        for (mesh, primitive, built) in self.build_primitives(jobs) {
+            let primitive = self.append(&primitive, built);
+            meshes[mesh].primitives.push(primitive);
+        }
+        meshes
+    }
+
+    fn build_primitives<'a>(
+        &self,
+        jobs: Vec<(MeshID, gltf::Primitive<'a>, Option<PrimitiveData>)>,
+    ) -> Vec<(MeshID, gltf::Primitive<'a>, BuiltPrimitive)> {
+        let build = |(mesh, primitive, decoded)| {
+            let built = self.build_primitive(mesh, &primitive, decoded);
+            (mesh, primitive, built)
+        };
+        cfg_if! {
+            if #[cfg(feature = "rayon")] {
+                info!("Rayon enabled. Processing {} primitives", jobs.len());
+                jobs.into_par_iter().map(build).collect()
+            } else {
+                jobs.into_iter().map(build).collect()
+            }
+        }
+    }
+
+    /// Read, clean up and complete the attributes of a primitive
+    fn build_primitive(
+        &self,
+        mesh_index: MeshID,
+        primitive: &gltf::Primitive,
+        decoded: Option<PrimitiveData>,
+    ) -> BuiltPrimitive {
+        let id = [mesh_index, primitive.index()];
+        let mut report = LoadReport::default();
+        let material_index = primitive
+            .material()
+            .index()
+            .unwrap_or(DEFAULT_MATERIAL_INDEX);
+        let reader = primitive.reader(|buffer| Some(&self.buffers[buffer.index()]));
+        let mut morph_targets: Vec<_> = reader
+            .read_morph_targets()
+            .map(|(position_d, normal_d, tangent_d)| {
+                MorphTarget::new(
+                    position_d.map_or(vec![], |p| p.map(Vec3::from).collect()),
+                    normal_d.map_or(vec![], |n| n.map(Vec3::from).collect()),
+                    tangent_d.map_or(vec![], |t| t.map(Vec3::from).collect()),
+                )
+            })
+            .collect();
+
+        let mut data = decoded.unwrap_or_else(|| PrimitiveData::read(&reader));
+        let indices: Vec<Index> = data.indices.take().unwrap_or_else(|| {
+            // Create index
+            warn!("Creating index...");
+            report.generated_indices.push(id);
+            (0..data.positions.len() as Index).collect()
+        });
+        let mut indices = triangulate(primitive.mode(), indices);
+
+        let [invalid, degenerate] = data.remove_bad_triangles(&mut indices);
+        let welded = if self.weld {
+            data.weld(&mut indices, &mut morph_targets)
+        } else {
+            0
+        };
+        for (list, count) in [
+            (&mut report.invalid_triangles, invalid),
+            (&mut report.degenerate_triangles, degenerate),
+            (&mut report.welded_vertices, welded),
+        ] {
+            if count > 0 {
+                list.push((id, count));
+            }
+        }
+        if data.normals.is_none() {
+            report.generated_normals.push(id);
+            data.generate_normals(&mut indices, &mut morph_targets, self.crease_angle);
+        }
+        let PrimitiveData {
+            positions,
+            normals,
+            tangents,
+            uvs0,
+            uvs1,
+            colors,
+            weights,
+            joints,
+            ..
+        } = data;
+        let normals = normals.expect("Normals are generated above");
+
+        let uvs0 = uvs0.unwrap_or(vec![Vec2::ZERO; positions.len()]);
+        let uvs1 = uvs1.unwrap_or(vec![Vec2::ZERO; positions.len()]);
+
+        let (mut tangents, tangents_found) = if let Some(tangents) = tangents {
+            (tangents, true)
+        } else {
+            (vec![[1.0, 0.0, 0.0, 0.0]; positions.len()], false)
+        };
+        let normal_texture = *self.normal_textures.get(material_index).unwrap_or(&false);
+        if (self.regenerate_tangents || !tangents_found && normal_texture) && !uvs0.is_empty() {
+            info!("Generating tangents...");
+            report.generated_tangents.push(id);
+            mikktspace::generate_tangents(&mut TangentCalcContext {
+                indices: indices.as_slice(),
+                positions: positions.as_slice(),
+                normals: normals.as_slice(),
+                uvs: uvs0.as_slice(),
+                tangents: tangents.as_mut_slice(),
+            });
+        }
+
+        let vertices = positions
+            .into_iter()
+            .enumerate()
+            .map(|(index, position)| {
+                let normal = normals[index];
+                let color = colors.as_ref().map_or(Vec4::ONE, |colors| colors[index]);
+                let uv = uvs0[index];
+                let weights = *weights.get(index).unwrap_or(&Default::default());
+                let joints = *joints.get(index).unwrap_or(&Default::default());
+                Vertex {
+                    position,
+                    normal,
+                    tangent: tangents[index],
+                    color,
+                    weights,
+                    joints,
+                    uv0: uv,
+                    uv1: uvs1[index],
+                    joint_offset: -1,
+                }
+            })
+            .collect();
+        BuiltPrimitive {
+            vertices,
+            indices,
+            morph_targets,
+            report,
+        }
+    }
+
+    /// Give a built primitive the next geometry id and append its vertices
+    fn append(&mut self, primitive: &gltf::Primitive, built: BuiltPrimitive) -> Primitive {
         let mapping: HashMap<_, _> = primitive
             .mappings()
             .flat_map(|m| {
@@ -141,125 +629,31 @@ impl Primitive {
 
         let material = primitive.material();
         let material_index = material.index().unwrap_or(DEFAULT_MATERIAL_INDEX) as u32;
-        let geo_id = builder.next_geo_id(material_index);
+        let geo_id = self.next_geo_id(material_index);
 
         if !mapping.is_empty() {
             info!("Geo id {} material variants: {:?}", geo_id, mapping);
         }
+        if !built.morph_targets.is_empty() {
+            info!(
+                "Geo id {} morph targets: {}",
+                geo_id,
+                built.morph_targets.len()
+            );
+        }
 
-        let (vertices, indices): (Vec<Vertex>, Vec<Index>) = {
-            let reader = primitive.reader(|buffer| Some(&builder.buffers[buffer.index()]));
-            let pos_reader = reader.read_positions().unwrap();
-            // let joints_reader = reader.read_joints(0).unwrap();
-            // let joints_reader = reader.read_weights(0).unwrap();
-            let _morph_targets: Vec<_> = reader
-                .read_morph_targets()
-                .map(|(position_d, normal_d, tangent_d)| {
-                    (
-                        position_d.map(|p| p.map(Vec3::from)),
-                        normal_d.map(|n| n.map(Vec3::from)),
-                        tangent_d.map(|t| t.map(Vec3::from)),
-                    )
-                })
-                .collect();
-
-            let positions: Vec<_> = pos_reader.map(|p| vec4(p[0], p[1], p[2], 0.)).collect();
-
-            let indices: Vec<Index> = if let Some(index_reader) = reader.read_indices() {
-                let index_reader = index_reader.into_u32();
-                index_reader.collect()
-            } else {
-                // Create index
-                warn!("Creating index...");
-                (0..positions.len() as Index).collect()
-            };
-
-            let normals = if let Some(rn) = reader.read_normals() {
-                rn.map(|n| vec4(n[0], n[1], n[2], 0.0)).collect()
-            } else {
-                create_geo_normal(&positions, &indices)
-            };
-
-            let uvs0 = reader
-                .read_tex_coords(0)
-                .map(|reader| reader.into_f32().map(Vec2::from).collect::<Vec<_>>())
-                .unwrap_or(vec![Vec2::ZERO; positions.len()]);
-            let uvs1 = reader
-                .read_tex_coords(1)
-                .map(|reader| reader.into_f32().map(Vec2::from).collect::<Vec<_>>())
-                .unwrap_or(vec![Vec2::ZERO; positions.len()]);
-
-            let (mut tangents, tangents_found) = if let Some(iter) = reader.read_tangents() {
-                (iter.collect::<Vec<_>>(), true)
-            } else {
-                (vec![[1.0, 0.0, 0.0, 0.0]; positions.len()], false)
-            };
-            if !tangents_found
-                && !uvs0.is_empty()
-                && *builder
-                    .normal_textures
-                    .get(material_index as usize)
-                    .unwrap_or(&false)
-            {
-                info!("Normal map found but tangents not found. Generating tangents...");
-                mikktspace::generate_tangents(&mut TangentCalcContext {
-                    indices: indices.as_slice(),
-                    positions: positions.as_slice(),
-                    normals: normals.as_slice(),
-                    uvs: uvs0.as_slice(),
-                    tangents: tangents.as_mut_slice(),
-                });
-            }
-
-            let colors = reader
-                .read_colors(0)
-                .map(|reader| reader.into_rgba_f32().map(Vec4::from).collect::<Vec<_>>());
-
-            let weights = reader.read_weights(0).map_or(vec![], |weights| {
-                weights.into_f32().map(Vec4::from).collect()
-            });
-            let joints = reader.read_joints(0).map_or(vec![], |joints| {
-                joints
-                    .into_u16()
-                    .map(|[x, y, z, w]| [u32::from(x), u32::from(y), u32::from(z), u32::from(w)])
-                    .map(UVec4::from)
-                    .collect()
-            });
-            let vertices = positions
-                .into_iter()
-                .enumerate()
-                .map(|(index, position)| {
-                    let normal = normals[index];
-                    let color = colors.as_ref().map_or(Vec4::ONE, |colors| colors[index]);
-                    let uv = uvs0[index];
-                    let weights = *weights.get(index).unwrap_or(&Default::default());
-                    let joints = *joints.get(index).unwrap_or(&Default::default());
-                    Vertex {
-                        position,
-                        normal,
-                        tangent: tangents[index],
-                        color,
-                        weights,
-                        joints,
-                        uv0: uv,
-                        uv1: uvs1[index],
-                        skin_index: -1,
-                    }
-                })
-                .collect();
-            (vertices, indices)
-        };
-        let v_offset = builder.vertices.len();
-        let i_offset = builder.indices.len();
-        builder.len.push([vertices.len(), indices.len()]);
-        builder.vertices.extend(vertices);
-        builder.indices.extend(indices);
-        builder
-            .offsets
+        let v_offset = self.vertices.len();
+        let i_offset = self.indices.len();
+        self.len.push([built.vertices.len(), built.indices.len()]);
+        self.vertices.extend(built.vertices);
+        self.indices.extend(built.indices);
+        self.morph_targets.push(built.morph_targets);
+        self.offsets
             .push([v_offset as _, i_offset as _, material_index as _]);
+        self.report.append(built.report);
 
         Primitive {
-            // material: material_index as usize,
+            material: material_index as usize,
             geometry_id: geo_id,
             mapping,
             aabb: get_aabb(&primitive.bounding_box()),
@@ -267,30 +661,44 @@ impl Primitive {
     }
 }
 
-fn create_geo_normal(position: &[Vec4], indices: &[u32]) -> Vec<Vec4> {
-    warn!("Creating normals");
-    let mut normals = vec![Vec4::default(); indices.len()];
-    assert_eq!(indices.len() % 3, 0);
-    for i in 0..indices.len() / 3 {
-        let i0 = indices[3 * i + 0] as usize;
-        let i1 = indices[3 * i + 1] as usize;
-        let i2 = indices[3 * i + 2] as usize;
-        let p0 = position[i0];
-        let p1 = position[i1];
-        let p2 = position[i2];
-        let v0 = (p1 - p0).xyz().normalize();
-        let v1 = (p2 - p0).xyz().normalize();
-        let n = v0.cross(v1).normalize();
-        let n = Vec4::new(n[0], n[1], n[2], 0.);
-        normals[i0] = n;
-        normals[i1] = n;
-        normals[i2] = n;
-    }
-    normals
+impl Primitive {
+    pub(crate) fn variant_material(&self, variant: Option<usize>) -> usize {
+        variant
+            .and_then(|v| self.mapping.get(&(v as u32)))
+            .copied()
+            .unwrap_or(self.material)
+    }
+}
+
+/// Values at `sources`, in order. Empty attributes stay empty.
+pub(crate) fn reindex<T: Copy>(values: &mut Vec<T>, sources: &[Index]) {
+    if !values.is_empty() {
+        *values = sources.iter().map(|&i| values[i as usize]).collect();
+    }
 }
 
 fn is_primitive_supported(primitive: &gltf::Primitive) -> bool {
-    primitive.get(&Semantic::Positions).is_some() && primitive.mode() == Mode::Triangles
+    matches!(
+        primitive.mode(),
+        Mode::Triangles | Mode::TriangleStrip | Mode::TriangleFan
+    )
+}
+
+/// Convert strips and fans to triangle lists, keeping the winding as in the glTF spec
+fn triangulate(mode: Mode, indices: Vec<Index>) -> Vec<Index> {
+    let triangles = indices.len().saturating_sub(2);
+    match mode {
+        Mode::TriangleStrip => (0..triangles)
+            .flat_map(|i| {
+                let odd = i % 2;
+                [indices[i], indices[i + 1 + odd], indices[i + 2 - odd]]
+            })
+            .collect(),
+        Mode::TriangleFan => (0..triangles)
+            .flat_map(|i| [indices[i + 1], indices[i + 2], indices[0]])
+            .collect(),
+        _ => indices,
+    }
 }
 
 struct TangentCalcContext<'a> {
@@ -348,3 +756,88 @@ impl<'a> mikktspace::Geometry for TangentCalcContext<'a> {
         self.set_tangent_encoded(a3toa4(&tangent, sign), face, vert);
     }
 }
+
+#[test]
+fn test_triangulate() {
+    let indices = vec![0, 1, 2, 3, 4];
+    assert_eq!(
+        triangulate(Mode::TriangleStrip, indices.clone()),
+        vec![0, 1, 2, 1, 3, 2, 2, 3, 4]
+    );
+    assert_eq!(
+        triangulate(Mode::TriangleFan, indices),
+        vec![1, 2, 0, 2, 3, 0, 3, 4, 0]
+    );
+}
+
+#[test]
+fn test_mesh_cleanup() {
+    let positions = [
+        [0., 0., 0.],
+        [1., 0., 0.],
+        [0., 1., 0.],
+        [0., 1., 0.],
+        [f32::NAN; 3],
+        [-1., 0., 1.],
+        [2., 0., 0.],
+    ];
+    let mut data = PrimitiveData {
+        positions: positions.map(|p| Vec4::from((Vec3::from(p), 0.))).to_vec(),
+        ..Default::default()
+    };
+    // Vertex 3 duplicates 2. Then a NaN, an out of range index, a repeated index and a line.
+    let mut indices = vec![0, 1, 2, 0, 3, 5, 0, 4, 1, 0, 1, 9, 0, 0, 1, 0, 1, 6];
+    assert_eq!(data.remove_bad_triangles(&mut indices), [2, 2]);
+    assert_eq!(indices, [0, 1, 2, 0, 3, 5]);
+    assert_eq!(data.weld(&mut indices, &mut []), 3);
+    assert_eq!(indices, [0, 1, 2, 0, 2, 3]);
+
+    // The triangles are 45 degrees apart
+    let mut flat = PrimitiveData {
+        positions: data.positions.clone(),
+        ..Default::default()
+    };
+    let mut flat_indices = indices.clone();
+    flat.generate_normals(&mut flat_indices, &mut [], 0.);
+    assert_eq!(flat.positions.len(), 6);
+    assert_eq!(flat.normals.unwrap()[0], Vec4::Z);
+
+    data.generate_normals(&mut indices, &mut [], 60f32.to_radians());
+    assert_eq!(data.positions.len(), 4);
+    // Right angles at vertex 0
+    let normal = data.normals.unwrap()[0].xyz();
+    assert!((normal - Vec3::new(1., 0., 2.).normalize()).length() < 1e-5);
+}
+
+#[test]
+fn test_build_meshes_in_order() {
+    // Triangles with 3, 4 and 5 vertices
+    let mut obj = String::new();
+    let mut first = 1;
+    for n in 3..6 {
+        obj += &format!("o fan{}\n", n);
+        for i in 0..n {
+            let angle = i as f32 / n as f32 * std::f32::consts::TAU;
+            obj += &format!("v {} {} {}\n", angle.cos(), angle.sin(), n);
+        }
+        obj += "f";
+        (first..first + n).for_each(|i| obj += &format!(" {}", i));
+        obj += "\n";
+        first += n;
+    }
+    let dir = crate::TestDir::new(&[("fans.obj", &obj)]);
+    let doc = crate::load_file(dir.path("fans.obj"), &Default::default()).unwrap();
+    let builder = &doc.geo_builder;
+    assert_eq!(builder.len, [[3, 3], [4, 6], [5, 9]]);
+    assert_eq!(
+        builder
+            .offsets
+            .iter()
+            .map(|o| [o[0], o[1]])
+            .collect::<Vec<_>>(),
+        [[0, 0], [3, 3], [7, 9]]
+    );
+    for (mesh, m) in doc.meshes.iter().enumerate() {
+        assert_eq!(m.primitives[0].geometry_id, mesh as u32);
+    }
+}
@@ -1,6 +1,7 @@
+use crate::compact::ColorLayout;
 use crate::cubumap::SkyBox;
 use crate::geometry::{GeoBuilder, PrimInfo};
-use crate::image::TexGamma;
+use crate::image::{PixelFormat, TexGamma};
 use crate::light::LightRaw;
 use crate::material::MaterialRaw;
 use crate::scene_graph::Doc;
@@ -8,17 +9,17 @@ use crate::texture;
 use crate::texture::WrapMode;
 use anyhow::Result;
 use log::info;
-use std::mem::size_of_val;
+use std::mem::{size_of, size_of_val};
 use std::time::Instant;
 
-use crate::skinning::{JointRaw, MAX_JOINTS};
+use crate::skinning::JointRaw;
 use vulkan::ash::vk;
 use vulkan::ash::vk::SamplerAddressMode;
 use vulkan::gpu_allocator::MemoryLocation;
 use vulkan::utils::create_gpu_only_buffer_from_data_batch;
 use vulkan::{
-    Buffer, Context, DescriptorSet, Image, ImageBarrier, ImageView, Sampler, WriteDescriptorSet,
-    WriteDescriptorSetKind,
+    Buffer, CommandBuffer, Context, DescriptorSet, Image, ImageBarrier, ImageView, Sampler,
+    WriteDescriptorSet, WriteDescriptorSetKind,
 };
 
 impl Into<vk::Format> for TexGamma {
@@ -30,61 +31,155 @@ impl Into<vk::Format> for TexGamma {
     }
 }
 
+fn get_image_format(i: &crate::image::Image) -> vk::Format {
+    use PixelFormat::*;
+    let srgb = i.gamma == TexGamma::Srgb;
+    match (i.format, srgb) {
+        (Rgba8, _) => i.gamma.into(),
+        // Linearized when loaded
+        (Rgba16, _) => vk::Format::R16G16B16A16_UNORM,
+        (Rgba32F, _) => vk::Format::R32G32B32A32_SFLOAT,
+        (Bc1, false) => vk::Format::BC1_RGBA_UNORM_BLOCK,
+        (Bc1, true) => vk::Format::BC1_RGBA_SRGB_BLOCK,
+        (Bc2, false) => vk::Format::BC2_UNORM_BLOCK,
+        (Bc2, true) => vk::Format::BC2_SRGB_BLOCK,
+        (Bc3, false) => vk::Format::BC3_UNORM_BLOCK,
+        (Bc3, true) => vk::Format::BC3_SRGB_BLOCK,
+        // No sRGB variants
+        (Bc4, _) => vk::Format::BC4_UNORM_BLOCK,
+        (Bc5, _) => vk::Format::BC5_UNORM_BLOCK,
+        (Bc6h, _) => vk::Format::BC6H_UFLOAT_BLOCK,
+        (Bc7, false) => vk::Format::BC7_UNORM_BLOCK,
+        (Bc7, true) => vk::Format::BC7_SRGB_BLOCK,
+    }
+}
+
 pub struct Buffers {
+    // Tightly packed positions, the BLAS input
     pub vertex_buffer: Buffer,
-    pub animation_buffers: Option<(Buffer, Buffer)>,
+    pub attribute_buffer: Buffer,
+    pub color_buffer: Buffer,
+    pub animation_buffers: Option<AnimationBuffers>,
+    // Mixed 16 and 32 bit indices, see `PrimInfo`
     pub index_buffer: Buffer,
     pub geo_buffer: Buffer,
     pub material_buffer: Buffer,
     pub dlights_buffer: Buffer,
     pub plights_buffer: Buffer,
+    // Vertex colors do not change with material variants
+    color_layout: ColorLayout,
+}
+
+/// Buffers of the animation compute pass.
+/// `positions` and `attributes` start as copies of the vertex streams,
+/// then the animated vertices are morphed and skinned in place every frame.
+pub struct AnimationBuffers {
+    // Joint matrices, rewritten every frame
+    pub joints: Buffer,
+    pub animated: Buffer,
+    pub animated_vertices: u32,
+    pub morphs: Buffer,
+    pub morph_deltas: Buffer,
+    // Morph weights, rewritten every frame
+    pub morph_weights: Buffer,
+    pub positions: Buffer,
+    pub attributes: Buffer,
 }
 
 impl Buffers {
     pub fn new(context: &Context, geo_builder: &GeoBuilder, globals: &VkGlobal) -> Result<Self> {
-        let vertices = geo_builder.vertices.as_slice();
-        let indices = geo_builder.indices.as_slice();
-        let no_skin = globals.skins.is_empty();
+        let compact = geo_builder.compact(&globals.color_layout);
+        let morph = geo_builder.has_morph_targets();
+        let need_compute = !globals.joints.is_empty() || morph;
         let now = Instant::now();
         let cmd_buffer = context
             .command_pool
             .allocate_command_buffer(vk::CommandBufferLevel::PRIMARY)?;
         cmd_buffer.begin(Some(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT))?;
 
+        let vertex_usage = vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
+            | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR
+            | vk::BufferUsageFlags::STORAGE_BUFFER;
         let (vertex_buffer, _v) = create_gpu_only_buffer_from_data_batch(
             context,
-            vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
-                | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR
-                | vk::BufferUsageFlags::STORAGE_BUFFER,
-            vertices,
+            vertex_usage,
+            &compact.positions,
             &cmd_buffer,
         )?;
-        let animation_buffer = if !no_skin {
-            Some(create_gpu_only_buffer_from_data_batch(
+        let (attribute_buffer, _a) = create_gpu_only_buffer_from_data_batch(
+            context,
+            vertex_usage,
+            &compact.attributes,
+            &cmd_buffer,
+        )?;
+        let (animation_buffers, _s) = if need_compute {
+            let (animated, s0) = create_storage_buffer(context, &compact.animated, &cmd_buffer)?;
+            let (morphs, s1) = create_storage_buffer(context, &compact.morphs, &cmd_buffer)?;
+            let (morph_deltas, s2) =
+                create_storage_buffer(context, &compact.morph_deltas, &cmd_buffer)?;
+            let (positions, s3) = create_gpu_only_buffer_from_data_batch(
+                context,
+                vertex_usage,
+                &compact.positions,
+                &cmd_buffer,
+            )?;
+            let (attributes, s4) = create_gpu_only_buffer_from_data_batch(
                 context,
-                vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
-                    | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR
-                    | vk::BufferUsageFlags::STORAGE_BUFFER,
-                vertices,
+                vertex_usage,
+                &compact.attributes,
                 &cmd_buffer,
-            )?)
+            )?;
+            let joints = context.create_buffer(
+                vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS | vk::BufferUsageFlags::STORAGE_BUFFER,
+                MemoryLocation::CpuToGpu,
+                (globals.joints.len().max(1) * size_of::<JointRaw>()) as _,
+            )?;
+            joints.copy_data_to_buffer(globals.joints.as_slice())?;
+            let morph_weights = create_host_visible_buffer(
+                context,
+                vk::BufferUsageFlags::STORAGE_BUFFER,
+                globals.morph_weights.as_slice(),
+            )?;
+            let buffers = AnimationBuffers {
+                joints,
+                animated,
+                animated_vertices: compact.animated.len() as _,
+                morphs,
+                morph_deltas,
+                morph_weights,
+                positions,
+                attributes,
+            };
+            (Some(buffers), Some([s0, s1, s2, s3, s4]))
         } else {
-            None
+            (None, None)
         };
 
+        // Bound even when no geometry has vertex colors
+        let colors = if compact.colors.is_empty() {
+            vec![u32::MAX]
+        } else {
+            compact.colors
+        };
+        let (color_buffer, _c) = create_gpu_only_buffer_from_data_batch(
+            context,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            &colors,
+            &cmd_buffer,
+        )?;
         let (index_buffer, _i) = create_gpu_only_buffer_from_data_batch(
             context,
             vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
                 | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR
                 | vk::BufferUsageFlags::STORAGE_BUFFER,
-            indices,
+            &compact.indices,
             &cmd_buffer,
         )?;
-        let (geo_buffer, _g) = create_gpu_only_buffer_from_data_batch(
+        // Host visible so that material variants can be switched at runtime
+        let geo_buffer = create_host_visible_buffer(
             context,
             vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS | vk::BufferUsageFlags::STORAGE_BUFFER,
-            &globals.prim_info,
-            &cmd_buffer,
+            globals.prim_info.as_slice(),
         )?;
 
         let (material_buffer, _s) = create_gpu_only_buffer_from_data_batch(
@@ -106,50 +201,93 @@ impl Buffers {
         fence.wait(None)?;
         // Free
         context.command_pool.free_command_buffer(&cmd_buffer)?;
-        let animation_buffer = animation_buffer.map(|(b, _)| b);
-
-        let _size_of_slice = size_of_val(globals.d_lights.as_slice());
-        let _size = size_of_val(&globals.d_lights);
-        let dlights_buffer = context.create_buffer(
-            vk::BufferUsageFlags::STORAGE_BUFFER,
-            MemoryLocation::CpuToGpu,
-            size_of_val(globals.d_lights.as_slice()) as _,
-        )?;
-        dlights_buffer.copy_data_to_buffer(globals.d_lights.as_slice())?;
-
-        let plights_buffer = context.create_buffer(
-            vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS | vk::BufferUsageFlags::STORAGE_BUFFER,
-            MemoryLocation::CpuToGpu,
-            size_of_val(globals.p_lights.as_slice()) as _,
-        )?;
-        plights_buffer.copy_data_to_buffer(globals.p_lights.as_slice())?;
 
-        let animation_buffers = if let Some(ani) = animation_buffer {
-            let skins_buffer = context.create_buffer(
-                vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS | vk::BufferUsageFlags::STORAGE_BUFFER,
-                MemoryLocation::CpuToGpu,
-                size_of_val(globals.skins.as_slice()) as _,
-            )?;
-            skins_buffer.copy_data_to_buffer(globals.skins.as_slice())?;
-            Some((skins_buffer, ani))
-        } else {
-            None
-        };
+        let (dlights_buffer, plights_buffer) = create_light_buffers(context, globals)?;
 
         info!("Buffers: {}s", now.elapsed().as_secs());
-        // println!("v_b: {:#02x}, i_b: {:#02x}, g_b: {:#02x}, m_b: {:#02x}", vertex_buffer.as_raw(), index_buffer.as_raw(), geo_buffer.as_raw(), material_buffer.as_raw());
-        let _src_stage = vk::PipelineStageFlags2::TRANSFER | vk::PipelineStageFlags2::ALL_COMMANDS;
-        let _ray_tracing_dst = vk::PipelineStageFlags2::ALL_COMMANDS;
         Ok(Self {
             vertex_buffer,
+            attribute_buffer,
+            color_buffer,
             animation_buffers,
             index_buffer,
             geo_buffer,
             material_buffer,
             plights_buffer,
             dlights_buffer,
+            color_layout: globals.color_layout.clone(),
         })
     }
+
+    /// Positions as seen by the BLAS and hit shaders, after animation
+    pub fn positions(&self) -> &Buffer {
+        self.animation_buffers
+            .as_ref()
+            .map_or(&self.vertex_buffer, |a| &a.positions)
+    }
+
+    /// Vertex attributes as seen by the hit shaders, after animation
+    pub fn attributes(&self) -> &Buffer {
+        self.animation_buffers
+            .as_ref()
+            .map_or(&self.attribute_buffer, |a| &a.attributes)
+    }
+
+    /// Recreate the light buffers, as the number of lights may have changed.
+    /// Descriptor sets referring to them have to be updated.
+    pub fn update_lights(&mut self, context: &Context, globals: &VkGlobal) -> Result<()> {
+        (self.dlights_buffer, self.plights_buffer) = create_light_buffers(context, globals)?;
+        Ok(())
+    }
+
+    /// Re-upload the geometry infos, e.g. after switching material variants.
+    pub fn update_prim_info(&self, doc: &Doc) -> Result<()> {
+        self.geo_buffer
+            .copy_data_to_buffer(&doc.geo_builder.flatten(&self.color_layout))
+    }
+}
+
+/// Storage buffers can not be empty, one default element is uploaded instead
+fn create_storage_buffer<T: Copy + Default>(
+    context: &Context,
+    data: &[T],
+    cmd_buffer: &CommandBuffer,
+) -> Result<(Buffer, Buffer)> {
+    let default = [T::default()];
+    let data = if data.is_empty() { &default[..] } else { data };
+    create_gpu_only_buffer_from_data_batch(
+        context,
+        vk::BufferUsageFlags::STORAGE_BUFFER,
+        data,
+        cmd_buffer,
+    )
+}
+
+fn create_host_visible_buffer<T: Copy>(
+    context: &Context,
+    usage: vk::BufferUsageFlags,
+    data: &[T],
+) -> Result<Buffer> {
+    let buffer = context.create_buffer(usage, MemoryLocation::CpuToGpu, size_of_val(data) as _)?;
+    buffer.copy_data_to_buffer(data)?;
+    Ok(buffer)
+}
+
+fn create_light_buffers(context: &Context, globals: &VkGlobal) -> Result<(Buffer, Buffer)> {
+    let dlights_buffer = context.create_buffer(
+        vk::BufferUsageFlags::STORAGE_BUFFER,
+        MemoryLocation::CpuToGpu,
+        size_of_val(globals.d_lights.as_slice()) as _,
+    )?;
+    dlights_buffer.copy_data_to_buffer(globals.d_lights.as_slice())?;
+
+    let plights_buffer = context.create_buffer(
+        vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS | vk::BufferUsageFlags::STORAGE_BUFFER,
+        MemoryLocation::CpuToGpu,
+        size_of_val(globals.p_lights.as_slice()) as _,
+    )?;
+    plights_buffer.copy_data_to_buffer(globals.p_lights.as_slice())?;
+    Ok((dlights_buffer, plights_buffer))
 }
 
 pub struct VkGlobal {
@@ -159,10 +297,14 @@ pub struct VkGlobal {
     pub textures: Vec<[usize; 3]>,
 
     pub prim_info: Vec<PrimInfo>,
+    pub(crate) color_layout: ColorLayout,
     materials: Vec<MaterialRaw>,
     pub d_lights: Vec<LightRaw>,
     pub p_lights: Vec<LightRaw>,
-    pub skins: Vec<[JointRaw; MAX_JOINTS]>,
+    // Joint matrices of all skins, see `Doc::get_skins`
+    pub joints: Vec<JointRaw>,
+    // See `Doc::get_morph_weights`
+    pub morph_weights: Vec<f32>,
 }
 
 pub struct SkyboxResource {
@@ -271,13 +413,19 @@ fn create_image_view(context: &Context, i: &crate::image::Image) -> Result<(Imag
 
     staging.copy_data_to_buffer(pixels)?;
 
-    let image = context.create_image(
+    let image = context.create_image_with_mips(
         vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
         MemoryLocation::GpuOnly,
-        i.gamma.into(),
+        get_image_format(i),
         width,
         height,
+        i.mip_levels,
     )?;
+    let level_offsets = i
+        .mip_offsets()
+        .into_iter()
+        .map(|o| o as vk::DeviceSize)
+        .collect::<Vec<_>>();
 
     context.execute_one_time_commands(|cmd| {
         cmd.pipeline_image_barriers(&[ImageBarrier {
@@ -290,7 +438,12 @@ fn create_image_view(context: &Context, i: &crate::image::Image) -> Result<(Imag
             dst_stage_mask: vk::PipelineStageFlags2::TRANSFER,
         }]);
 
-        cmd.copy_buffer_to_image(&staging, &image, vk::ImageLayout::TRANSFER_DST_OPTIMAL);
+        cmd.copy_buffer_to_image_mips(
+            &staging,
+            &image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &level_offsets,
+        );
 
         cmd.pipeline_image_barriers(&[ImageBarrier {
             image: &image,
@@ -335,17 +488,20 @@ pub fn create_global(context: &Context, doc: &Doc) -> Result<VkGlobal> {
         .collect::<Vec<_>>();
     // Dummy texture
     let [d_lights, p_lights] = doc.get_lights_raw();
+    let color_layout = doc.geo_builder.color_layout();
 
     Ok(VkGlobal {
         _images: images,
         views,
         samplers,
         textures,
-        prim_info: doc.geo_builder.flatten(),
+        prim_info: doc.geo_builder.flatten(&color_layout),
+        color_layout,
         materials: doc.get_materials_raw(),
         d_lights,
         p_lights,
-        skins: doc.get_skins(),
+        joints: doc.get_skins(),
+        morph_weights: doc.get_morph_weights(),
     })
 }
 
@@ -374,9 +530,18 @@ fn map_gltf_sampler<'a>(sampler: &texture::Sampler) -> vk::SamplerCreateInfoBuil
         | texture::MinFilter::NearestMipmapNearest => vk::Filter::NEAREST,
     };
 
+    let mipmap_mode = match sampler.min_filter {
+        texture::MinFilter::NearestMipmapNearest | texture::MinFilter::LinearMipmapNearest => {
+            vk::SamplerMipmapMode::NEAREST
+        }
+        _ => vk::SamplerMipmapMode::LINEAR,
+    };
+
     vk::SamplerCreateInfo::builder()
         .mag_filter(mag_filter)
         .min_filter(min_filter)
+        .mipmap_mode(mipmap_mode)
+        .max_lod(vk::LOD_CLAMP_NONE)
         .address_mode_u(sampler.wrap_s.into())
         .address_mode_v(sampler.wrap_t.into())
 }
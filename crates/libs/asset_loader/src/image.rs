@@ -1,3 +1,4 @@
+use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 
 use std::path::Path;
@@ -8,21 +9,61 @@ use cfg_if::cfg_if;
 use gltf::image::{Format, Source};
 use gltf::Document;
 use image::io::Reader as ImageReader;
-use image::GenericImageView;
-use log::info;
+use log::{info, warn};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Image {
+    // All mip levels, largest first
+    #[serde(with = "crate::cache::bytes")]
     pub pixels: Vec<u8>,
     pub width: u32,
     pub height: u32,
+    pub mip_levels: u32,
     pub source: Name,
     pub index: usize,
     pub gamma: TexGamma,
-    format: Format,
+    pub format: PixelFormat,
 }
 
-#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+/// Layout of `Image::pixels`. BCn formats use 4x4 blocks.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PixelFormat {
+    Rgba8,
+    Rgba16,
+    Rgba32F,
+    Bc1,
+    Bc2,
+    Bc3,
+    Bc4,
+    Bc5,
+    Bc6h,
+    Bc7,
+}
+
+impl PixelFormat {
+    /// Byte size of one mip level
+    pub fn level_size(self, width: u32, height: u32) -> usize {
+        use PixelFormat::*;
+        let blocks = |w: u32| w.div_ceil(4) as usize;
+        match self {
+            Rgba8 => (width * height * 4) as usize,
+            Rgba16 => (width * height * 8) as usize,
+            Rgba32F => (width * height * 16) as usize,
+            Bc1 | Bc4 => blocks(width) * blocks(height) * 8,
+            Bc2 | Bc3 | Bc5 | Bc6h | Bc7 => blocks(width) * blocks(height) * 16,
+        }
+    }
+}
+
+/// Image data before it is processed into `Image`
+pub(crate) enum RawImage {
+    Gltf(gltf::image::Data),
+    Loaded(Image),
+    // Could not be read or decoded, with the reason
+    Missing(String),
+}
+
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Serialize, Deserialize)]
 pub enum TexGamma {
     Linear,
     Srgb,
@@ -34,10 +75,11 @@ impl Default for Image {
             pixels: vec![1; 4],
             width: 1,
             height: 1,
+            mip_levels: 1,
             source: None,
             index: 0,
             gamma: TexGamma::Srgb,
-            format: Format::R8G8B8A8,
+            format: PixelFormat::Rgba8,
         }
     }
 }
@@ -49,141 +91,286 @@ impl Image {
         if linear.contains(&original_index) {
             self.gamma = TexGamma::Linear;
         }
+        self.linearize();
         self.source = match info.source() {
             Source::View { .. } => None,
             Source::Uri { uri, .. } => Some(uri.to_string()),
         };
 
-        info!("Image:{:?} format: {:?}", self.source, self.format);
+        info!(
+            "Image:{:?} format: {:?} mip levels: {}",
+            self.source, self.format, self.mip_levels
+        );
+    }
+
+    /// Byte offsets of the mip levels in `pixels`
+    pub fn mip_offsets(&self) -> Vec<usize> {
+        (0..self.mip_levels)
+            .scan(0, |offset, level| {
+                let current = *offset;
+                *offset += self
+                    .format
+                    .level_size((self.width >> level).max(1), (self.height >> level).max(1));
+                Some(current)
+            })
+            .collect()
     }
 
     pub fn load_image<P: AsRef<Path>>(p: P) -> anyhow::Result<Self> {
         let source = p.as_ref().to_str().map(|i| i.to_string());
         let img = ImageReader::open(p)?.decode()?;
-
-        let width = img.width();
-        let height = img.height();
-        let iter = img.pixels().flat_map(|(_x, _y, c)| c.0);
-        let pixels =
-        //     if let Some(collecter) = collector {
-        //     collecter.extend(iter);
-        //     Vec::with_capacity(0)
-        // } else {
-                iter.collect();
-        // };
+        // Skyboxes are always uploaded as 8 bit
         Ok(Self {
-            pixels,
-            width,
-            height,
             source,
-            index: 0,
-            gamma: TexGamma::Srgb,
-            format: Format::R8G8B8A8,
+            ..Self::from_dynamic(img.to_rgba8().into())
         })
     }
-}
 
-impl TryFrom<&gltf::image::Data> for Image {
-    type Error = Error;
-
-    fn try_from(image: &gltf::image::Data) -> Result<Self> {
-        let width = image.width;
-        let height = image.height;
-        let pixel_count = width * height;
-        use gltf::image::Format::*;
-        let pixels = match image.format {
-            R8G8B8A8 => image.pixels.clone(),
-            _ => PixelIter::new(image, pixel_count as _)?
-                .flatten()
-                .collect::<Vec<_>>(),
+    pub(crate) fn from_dynamic(img: image::DynamicImage) -> Self {
+        use image::DynamicImage::*;
+        let format = match &img {
+            ImageLuma8(_) => Format::R8,
+            ImageLumaA8(_) => Format::R8G8,
+            ImageRgb8(_) => Format::R8G8B8,
+            ImageRgba8(_) => Format::R8G8B8A8,
+            ImageLuma16(_) => Format::R16,
+            ImageLumaA16(_) => Format::R16G16,
+            ImageRgb16(_) => Format::R16G16B16,
+            ImageRgba16(_) => Format::R16G16B16A16,
+            ImageRgb32F(_) => Format::R32G32B32FLOAT,
+            ImageRgba32F(_) => Format::R32G32B32A32FLOAT,
+            _ => return Self::from_dynamic(img.to_rgba8().into()),
         };
+        Self::from_pixels(format, img.as_bytes(), img.width(), img.height())
+    }
 
-        Ok(Self {
+    /// Expand to four channels, keeping the bit depth
+    fn from_pixels(format: Format, pixels: &[u8], width: u32, height: u32) -> Self {
+        use Format::*;
+        let (format, channels, one) = match format {
+            R8 => (PixelFormat::Rgba8, 1, vec![u8::MAX]),
+            R8G8 => (PixelFormat::Rgba8, 2, vec![u8::MAX]),
+            R8G8B8 => (PixelFormat::Rgba8, 3, vec![u8::MAX]),
+            R8G8B8A8 => (PixelFormat::Rgba8, 4, vec![]),
+            R16 => (PixelFormat::Rgba16, 1, u16::MAX.to_ne_bytes().to_vec()),
+            R16G16 => (PixelFormat::Rgba16, 2, u16::MAX.to_ne_bytes().to_vec()),
+            R16G16B16 => (PixelFormat::Rgba16, 3, u16::MAX.to_ne_bytes().to_vec()),
+            R16G16B16A16 => (PixelFormat::Rgba16, 4, vec![]),
+            R32G32B32FLOAT => (PixelFormat::Rgba32F, 3, 1f32.to_ne_bytes().to_vec()),
+            R32G32B32A32FLOAT => (PixelFormat::Rgba32F, 4, vec![]),
+        };
+        let pixels = if channels == 4 {
+            pixels.to_vec()
+        } else {
+            expand_to_rgba(pixels, channels, &one)
+        };
+        Self {
             pixels,
             width,
             height,
-            format: image.format,
+            format,
             ..Default::default()
-        })
+        }
+    }
+
+    /// First mip level as PNG, or `None` for block compressed images.
+    /// Linearized color images are encoded back to sRGB with `srgb`.
+    pub(crate) fn to_png(&self, srgb: bool) -> Option<Vec<u8>> {
+        use image::{DynamicImage, ImageBuffer};
+        let pixels = &self.pixels[..self.format.level_size(self.width, self.height)];
+        let floats: Vec<f32> = match self.format {
+            PixelFormat::Rgba8 => {
+                let buffer = ImageBuffer::from_raw(self.width, self.height, pixels.to_vec())?;
+                return encode_png(DynamicImage::ImageRgba8(buffer));
+            }
+            PixelFormat::Rgba16 => pixels
+                .chunks_exact(2)
+                .map(|c| u16::from_ne_bytes([c[0], c[1]]) as f32 / u16::MAX as f32)
+                .collect(),
+            PixelFormat::Rgba32F => pixels
+                .chunks_exact(4)
+                .map(|c| f32::from_ne_bytes([c[0], c[1], c[2], c[3]]))
+                .collect(),
+            _ => return None,
+        };
+        let channels: Vec<_> = floats
+            .iter()
+            .enumerate()
+            .map(|(i, &c)| {
+                // Skip alpha
+                let c = if srgb && i % 4 != 3 {
+                    linear_to_srgb(c)
+                } else {
+                    c
+                };
+                (c.clamp(0., 1.) * u16::MAX as f32).round() as u16
+            })
+            .collect();
+        let buffer = ImageBuffer::from_raw(self.width, self.height, channels)?;
+        encode_png(DynamicImage::ImageRgba16(buffer))
+    }
+
+    /// Fit the larger side into `max_size`, keeping the aspect ratio.
+    /// Mip chains drop their larger levels, block compressed images without mips are kept.
+    fn downscale(&mut self, max_size: u32) {
+        use image::{imageops::FilterType, DynamicImage, ImageBuffer};
+        let size = |level: u32| (self.width >> level).max(self.height >> level);
+        if size(0) <= max_size {
+            return;
+        }
+        let (width, height) = (self.width, self.height);
+        if self.mip_levels > 1 {
+            let level = (1..self.mip_levels)
+                .find(|&l| size(l) <= max_size)
+                .unwrap_or(self.mip_levels - 1);
+            let offset = self.mip_offsets()[level as usize];
+            self.pixels.drain(..offset);
+            self.width = (self.width >> level).max(1);
+            self.height = (self.height >> level).max(1);
+            self.mip_levels -= level;
+        } else {
+            let pixels = &self.pixels;
+            let image = match self.format {
+                PixelFormat::Rgba8 => ImageBuffer::from_raw(width, height, pixels.clone())
+                    .map(DynamicImage::ImageRgba8),
+                PixelFormat::Rgba16 => ImageBuffer::from_raw(
+                    width,
+                    height,
+                    pixels
+                        .chunks_exact(2)
+                        .map(|c| u16::from_ne_bytes([c[0], c[1]]))
+                        .collect(),
+                )
+                .map(DynamicImage::ImageRgba16),
+                PixelFormat::Rgba32F => ImageBuffer::from_raw(
+                    width,
+                    height,
+                    pixels
+                        .chunks_exact(4)
+                        .map(|c| f32::from_ne_bytes([c[0], c[1], c[2], c[3]]))
+                        .collect(),
+                )
+                .map(DynamicImage::ImageRgba32F),
+                _ => None,
+            };
+            let Some(image) = image else {
+                warn!("Image {:?} can not be downscaled", self.source);
+                return;
+            };
+            let image = image.resize(max_size, max_size, FilterType::Triangle);
+            self.pixels = image.as_bytes().to_vec();
+            self.width = image.width();
+            self.height = image.height();
+        }
+        info!(
+            "Image {:?} downscaled from {}x{} to {}x{}",
+            self.source, width, height, self.width, self.height
+        );
+    }
+
+    /// There are no sRGB formats above 8 bits, so decode those on the CPU
+    fn linearize(&mut self) {
+        match self.format {
+            PixelFormat::Rgba16 if self.gamma == TexGamma::Srgb => {
+                self.pixels
+                    .chunks_exact_mut(2)
+                    .enumerate()
+                    // Skip alpha
+                    .filter(|(i, _)| i % 4 != 3)
+                    .for_each(|(_, c)| {
+                        let v = u16::from_ne_bytes([c[0], c[1]]) as f32 / u16::MAX as f32;
+                        let linear = (srgb_to_linear(v) * u16::MAX as f32).round() as u16;
+                        c.copy_from_slice(&linear.to_ne_bytes());
+                    });
+                self.gamma = TexGamma::Linear;
+            }
+            // Float images are linear already
+            PixelFormat::Rgba32F => self.gamma = TexGamma::Linear,
+            _ => {}
+        }
     }
 }
 
-#[derive(Clone, Debug)]
-pub(crate) struct PixelIter<'a> {
-    pixels: &'a [u8],
-    format: Format,
-    pixel_size: usize,
-    position: usize,
-    pixel_count: usize,
+fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
 }
 
-impl<'a> PixelIter<'a> {
-    pub(crate) fn new(image: &'a gltf::image::Data, pixel_count: usize) -> Result<Self> {
-        use Format::*;
-        let pixels = &image.pixels;
-        let format = image.format;
-        let pixel_size = match format {
-            R8 => 1,
-            R8G8 => 2,
-            R8G8B8 => 3,
-            // R8G8B8A8 => 4,
-            _ => return Err(Error::Support("16 bytes images".to_string())),
+fn encode_png(image: image::DynamicImage) -> Option<Vec<u8>> {
+    let mut bytes = std::io::Cursor::new(vec![]);
+    image
+        .write_to(&mut bytes, image::ImageOutputFormat::Png)
+        .ok()?;
+    Some(bytes.into_inner())
+}
+
+fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1. / 2.4) - 0.055
+    }
+}
+
+/// One and two channels are luma and luma with alpha
+fn expand_to_rgba(pixels: &[u8], channels: usize, one: &[u8]) -> Vec<u8> {
+    let size = one.len();
+    let mut rgba = Vec::with_capacity(pixels.len() / channels * 4);
+    for pixel in pixels.chunks_exact(channels * size) {
+        let c = |i: usize| &pixel[i * size..(i + 1) * size];
+        let [r, g, b, a] = match channels {
+            1 => [c(0), c(0), c(0), one],
+            2 => [c(0), c(0), c(0), c(1)],
+            _ => [c(0), c(1), c(2), one],
         };
+        [r, g, b, a].iter().for_each(|c| rgba.extend_from_slice(c));
+    }
+    rgba
+}
 
-        Ok(Self {
-            pixels,
-            format,
-            pixel_size,
-            position: 0,
-            pixel_count,
-        })
+impl From<&gltf::image::Data> for Image {
+    fn from(image: &gltf::image::Data) -> Self {
+        Self::from_pixels(image.format, &image.pixels, image.width, image.height)
     }
 }
 
-impl<'a> Iterator for PixelIter<'a> {
-    type Item = [u8; 4];
+impl TryFrom<RawImage> for Image {
+    type Error = Error;
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.position == self.pixel_count {
-            return None;
+    fn try_from(image: RawImage) -> Result<Self> {
+        match image {
+            RawImage::Gltf(data) => Ok(Image::from(&data)),
+            RawImage::Loaded(image) => Ok(image),
+            RawImage::Missing(reason) => Err(Error::Load(reason)),
         }
-        let pixels = self.pixels;
-        let index = self.position;
-        use Format::*;
-        let pixel = match self.format {
-            R8 => [pixels[index], pixels[index], pixels[index], u8::MAX],
-            // actually luma8 with alpha
-            R8G8 => [
-                pixels[index * 2],
-                pixels[index * 2],
-                pixels[index * 2],
-                pixels[index * 2 + 1],
-            ],
-            R8G8B8 => [
-                pixels[index * 3],
-                pixels[index * 3 + 1],
-                pixels[index * 3 + 2],
-                u8::MAX,
-            ],
-            // R8G8B8A8 => [
-            //     pixels[index * 4],
-            //     pixels[index * 4 + 1],
-            //     pixels[index * 4 + 2],
-            //     pixels[index * 4 + 3],
-            // ],
-            _ => unreachable!("Self::new already checks"),
-        };
+    }
+}
 
-        self.position += 1;
-        Some(pixel)
+/// Name or URI of an image, for messages
+pub(crate) fn image_label(info: &gltf::Image) -> String {
+    match (info.name(), info.source()) {
+        (Some(name), _) => name.to_string(),
+        (None, Source::Uri { uri, .. }) => uri.to_string(),
+        (None, Source::View { .. }) => "(embedded)".to_string(),
     }
 }
 
+fn or_placeholder(image: Result<Image>) -> Image {
+    image.unwrap_or_else(|e| {
+        warn!("Failed to process image: {}", e);
+        Image::default()
+    })
+}
+
 #[cfg(feature = "rayon")]
-pub fn process_images_par(
-    gltf_images: &[gltf::image::Data],
+pub(crate) fn process_images_par(
+    gltf_images: Vec<RawImage>,
     doc: &Document,
     linear: &HashSet<usize>,
+    max_size: Option<u32>,
 ) -> Vec<Image> {
     use rayon::prelude::*;
     let image_infos = doc.images().collect::<Vec<_>>();
@@ -191,12 +378,15 @@ pub fn process_images_par(
     let images: Vec<_> = rayon::iter::once(Image::default())
         .chain(
             gltf_images
-                .par_iter()
+                .into_par_iter()
                 .map(Image::try_from)
-                .map(Result::unwrap)
+                .map(or_placeholder)
                 .zip(image_infos)
                 .map(|(mut img, info)| {
                     img.update_info(info, linear);
+                    if let Some(max_size) = max_size {
+                        img.downscale(max_size);
+                    }
                     img
                 }),
         )
@@ -205,37 +395,42 @@ pub fn process_images_par(
     images
 }
 
-pub fn process_images_unified(
-    gltf_images: &[gltf::image::Data],
+pub(crate) fn process_images_unified(
+    gltf_images: Vec<RawImage>,
     doc: &Document,
     linear: &HashSet<usize>,
+    max_size: Option<u32>,
 ) -> Vec<Image> {
     cfg_if! {
         if #[cfg(feature = "rayon")] {
-            process_images_par(gltf_images, doc, linear)
+            process_images_par(gltf_images, doc, linear, max_size)
         } else {
-            process_images(gltf_images, doc, linear)
+            process_images(gltf_images, doc, linear, max_size)
         }
     }
 }
 
 #[cfg(not(feature = "rayon"))]
-pub fn process_images(
-    gltf_images: &[gltf::image::Data],
+pub(crate) fn process_images(
+    gltf_images: Vec<RawImage>,
     doc: &Document,
     linear: &HashSet<usize>,
+    max_size: Option<u32>,
 ) -> Vec<Image> {
     let image_infos = doc.images().collect::<Vec<_>>();
     info!("Rayon disabled. Processing {} images", image_infos.len());
     let images: Vec<_> = once(Image::default())
         .chain(
             gltf_images
-                .iter()
+                .into_iter()
                 .map(Image::try_from)
-                .map(Result::unwrap)
+                .map(or_placeholder)
                 .zip(image_infos)
                 .map(|(mut img, info)| {
                     img.update_info(info, &linear);
+                    if let Some(max_size) = max_size {
+                        img.downscale(max_size);
+                    }
                     img
                 }),
         )
@@ -243,3 +438,44 @@ pub fn process_images(
     check_indices!(images);
     images
 }
+
+#[test]
+fn test_16_bit_image() {
+    let rgb: Vec<u8> = [0u16, u16::MAX, 0x8000]
+        .iter()
+        .flat_map(|c| c.to_ne_bytes())
+        .collect();
+    let mut image = Image::from_pixels(Format::R16G16B16, &rgb, 1, 1);
+    assert_eq!(image.format, PixelFormat::Rgba16);
+    assert_eq!(image.pixels.len(), PixelFormat::Rgba16.level_size(1, 1));
+    image.linearize();
+    assert_eq!(image.gamma, TexGamma::Linear);
+    let channels: Vec<_> = image
+        .pixels
+        .chunks_exact(2)
+        .map(|c| u16::from_ne_bytes([c[0], c[1]]))
+        .collect();
+    assert_eq!(channels[..2], [0, u16::MAX]);
+    assert!((channels[2] as f32 / u16::MAX as f32 - 0.214).abs() < 1e-3);
+    assert_eq!(channels[3], u16::MAX);
+}
+
+#[test]
+fn test_downscale() {
+    let mut image = Image::from_pixels(Format::R8G8B8A8, &[255; 8 * 4 * 4], 8, 4);
+    image.downscale(2);
+    assert_eq!((image.width, image.height), (2, 1));
+    assert_eq!(image.pixels, [255; 2 * 4]);
+
+    // Mip chains drop levels instead
+    let mut image = Image {
+        mip_levels: 3,
+        width: 4,
+        height: 4,
+        pixels: [0; 4 * 4 * 4].into_iter().chain([1; 5 * 4]).collect(),
+        ..Default::default()
+    };
+    image.downscale(2);
+    assert_eq!((image.width, image.height, image.mip_levels), (2, 2, 2));
+    assert_eq!(image.pixels, [1; 5 * 4]);
+}
@@ -0,0 +1,117 @@
+use glam::{Mat4, Quat, Vec3};
+use gltf::accessor::{DataType, Dimensions, Iter};
+use gltf::animation::util::Rotations;
+use gltf::{buffer, Accessor, Document};
+use log::{info, warn};
+
+pub const EXT_MESH_GPU_INSTANCING: &str = "EXT_mesh_gpu_instancing";
+
+/// Instance transforms of every node using EXT_mesh_gpu_instancing.
+/// The gltf crate drops the extension, so the accessor indices come from the raw JSON.
+pub(crate) fn read_instances(
+    json: &serde_json::Value,
+    doc: &Document,
+    buffers: &[buffer::Data],
+) -> Vec<(usize, Vec<Mat4>)> {
+    let raw_nodes = match json.get("nodes").and_then(|n| n.as_array()) {
+        Some(n) => n,
+        None => return vec![],
+    };
+    let accessors: Vec<_> = doc.accessors().collect();
+    let res: Vec<(usize, Vec<Mat4>)> = raw_nodes
+        .iter()
+        .enumerate()
+        .filter_map(|(node, raw)| {
+            let attributes = raw.pointer(&format!(
+                "/extensions/{}/attributes",
+                EXT_MESH_GPU_INSTANCING
+            ))?;
+            let get_accessor = |name: &str| {
+                attributes
+                    .get(name)
+                    .and_then(|a| a.as_u64())
+                    .and_then(|a| accessors.get(a as usize))
+                    .cloned()
+            };
+            let translations = get_accessor("TRANSLATION").map(|a| read_vec3(a, buffers));
+            let rotations = get_accessor("ROTATION").map(|a| read_rotations(a, buffers));
+            let scales = get_accessor("SCALE").map(|a| read_vec3(a, buffers));
+            let count = [
+                translations.as_ref().map(|t| t.len()),
+                rotations.as_ref().map(|r| r.len()),
+                scales.as_ref().map(|s| s.len()),
+            ]
+            .into_iter()
+            .flatten()
+            .min()?;
+            let transforms = (0..count)
+                .map(|i| {
+                    Mat4::from_scale_rotation_translation(
+                        scales.as_ref().map_or(Vec3::ONE, |s| s[i]),
+                        rotations.as_ref().map_or(Quat::IDENTITY, |r| r[i]),
+                        translations.as_ref().map_or(Vec3::ZERO, |t| t[i]),
+                    )
+                })
+                .collect();
+            Some((node, transforms))
+        })
+        .collect();
+    if !res.is_empty() {
+        info!(
+            "GPU instancing: {} nodes, {} instances",
+            res.len(),
+            res.iter().map(|(_, t)| t.len()).sum::<usize>()
+        );
+    }
+    res
+}
+
+fn read_vec3(accessor: Accessor, buffers: &[buffer::Data]) -> Vec<Vec3> {
+    if accessor.data_type() != DataType::F32 || accessor.dimensions() != Dimensions::Vec3 {
+        warn!("Unsupported instance accessor {}", accessor.index());
+        return vec![];
+    }
+    Iter::<[f32; 3]>::new(accessor, |b| Some(&buffers[b.index()]))
+        .map_or(vec![], |iter| iter.map(Vec3::from).collect())
+}
+
+fn read_rotations(accessor: Accessor, buffers: &[buffer::Data]) -> Vec<Quat> {
+    let get = |b: buffer::Buffer| Some(&buffers[b.index()].0[..]);
+    let rotations = match accessor.data_type() {
+        DataType::I8 => Iter::new(accessor, get).map(Rotations::I8),
+        DataType::U8 => Iter::new(accessor, get).map(Rotations::U8),
+        DataType::I16 => Iter::new(accessor, get).map(Rotations::I16),
+        DataType::U16 => Iter::new(accessor, get).map(Rotations::U16),
+        DataType::F32 => Iter::new(accessor, get).map(Rotations::F32),
+        DataType::U32 => None,
+    };
+    rotations.map_or(vec![], |r| r.into_f32().map(Quat::from_array).collect())
+}
+
+#[test]
+fn test_read_instances() {
+    let json = r#"{
+        "asset": {"version": "2.0"},
+        "extensionsUsed": ["EXT_mesh_gpu_instancing"],
+        "buffers": [{"byteLength": 24, "uri": "data:application/octet-stream;base64,AACAPwAAAAAAAAAAAAAAAAAAAEAAAAAA"}],
+        "bufferViews": [{"buffer": 0, "byteLength": 24}],
+        "accessors": [{"bufferView": 0, "componentType": 5126, "count": 2, "type": "VEC3"}],
+        "nodes": [
+            {},
+            {"extensions": {"EXT_mesh_gpu_instancing": {"attributes": {"TRANSLATION": 0}}}}
+        ]
+    }"#;
+    let (doc, buffers, _) = gltf::import_slice(json.as_bytes()).unwrap();
+    let json = serde_json::from_str(json).unwrap();
+    let instances = read_instances(&json, &doc, &buffers);
+    assert_eq!(instances.len(), 1);
+    let (node, transforms) = &instances[0];
+    assert_eq!(*node, 1);
+    assert_eq!(
+        transforms,
+        &[
+            Mat4::from_translation(Vec3::X),
+            Mat4::from_translation(Vec3::Y * 2.)
+        ]
+    );
+}
@@ -0,0 +1,320 @@
+use crate::error::*;
+use crate::image::{Image, PixelFormat, RawImage};
+use gltf::image::Source;
+use gltf::{buffer, Document};
+use log::{info, warn};
+use std::io::Read;
+use std::path::Path;
+
+pub const KHR_TEXTURE_BASISU: &str = "KHR_texture_basisu";
+
+const IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+const HEADER_SIZE: usize = 80;
+const LEVEL_INDEX_ENTRY_SIZE: usize = 24;
+
+const SUPERCOMPRESSION_NONE: u32 = 0;
+const SUPERCOMPRESSION_ZSTD: u32 = 2;
+
+// KHR_DF_MODEL_UASTC and its channel ids with alpha
+const COLOR_MODEL_UASTC: u8 = 166;
+const UASTC_CHANNEL_RGBA: u8 = 3;
+const UASTC_CHANNEL_RRRG: u8 = 5;
+
+pub(crate) fn is_ktx2(bytes: &[u8]) -> bool {
+    bytes.starts_with(&IDENTIFIER)
+}
+
+fn invalid() -> Error {
+    Error::Load("Invalid KTX2 file".to_string())
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32> {
+    bytes
+        .get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(invalid)
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Result<u64> {
+    Ok(read_u32(bytes, offset)? as u64 | (read_u32(bytes, offset + 4)? as u64) << 32)
+}
+
+/// BCn and RGBA8 vkFormat values that can be uploaded as is
+fn pixel_format(vk_format: u32) -> Option<PixelFormat> {
+    use PixelFormat::*;
+    Some(match vk_format {
+        // R8G8B8A8_UNORM, R8G8B8A8_SRGB
+        37 | 43 => Rgba8,
+        // BC1_RGB and BC1_RGBA, UNORM and SRGB
+        131..=134 => Bc1,
+        135 | 136 => Bc2,
+        137 | 138 => Bc3,
+        139 => Bc4,
+        141 => Bc5,
+        // BC6H_UFLOAT
+        143 => Bc6h,
+        145 | 146 => Bc7,
+        _ => return None,
+    })
+}
+
+/// Parse a 2D KTX2 texture with its mip chain.
+/// BCn payloads are kept as is; UASTC is transcoded to BC7 with the `basisu` feature.
+pub(crate) fn read_ktx2(bytes: &[u8]) -> Result<Image> {
+    if !is_ktx2(bytes) {
+        return Err(invalid());
+    }
+    let header = |i: usize| read_u32(bytes, IDENTIFIER.len() + i * 4);
+    let vk_format = header(0)?;
+    let width = header(2)?;
+    let height = header(3)?.max(1);
+    let depth = header(4)?;
+    let layers = header(5)?;
+    let faces = header(6)?;
+    let mip_levels = header(7)?.max(1);
+    let supercompression = header(8)?;
+    if depth > 1 || layers > 1 || faces != 1 {
+        return Err(Error::Support(
+            "KTX2 3D, array and cubemap textures".to_string(),
+        ));
+    }
+    if supercompression != SUPERCOMPRESSION_NONE && supercompression != SUPERCOMPRESSION_ZSTD {
+        return Err(Error::Support(format!(
+            "KTX2 supercompression scheme {} (BasisLZ/ETC1S is not supported)",
+            supercompression
+        )));
+    }
+
+    // The basic data format descriptor block follows dfdTotalSize
+    let dfd = read_u32(bytes, 48)? as usize;
+    let color_model = *bytes.get(dfd + 12).ok_or_else(invalid)?;
+    let uastc = vk_format == 0 && color_model == COLOR_MODEL_UASTC;
+    let format = if uastc {
+        PixelFormat::Bc7
+    } else {
+        pixel_format(vk_format)
+            .ok_or_else(|| Error::Support(format!("KTX2 vkFormat {}", vk_format)))?
+    };
+    let has_alpha = matches!(
+        bytes.get(dfd + 31).map(|c| c & 0xF),
+        Some(UASTC_CHANNEL_RGBA | UASTC_CHANNEL_RRRG)
+    );
+
+    let mut pixels = vec![];
+    for level in 0..mip_levels {
+        let index = HEADER_SIZE + level as usize * LEVEL_INDEX_ENTRY_SIZE;
+        let offset = read_u64(bytes, index)? as usize;
+        let length = read_u64(bytes, index + 8)? as usize;
+        let data = bytes.get(offset..offset + length).ok_or_else(invalid)?;
+        let data = if supercompression == SUPERCOMPRESSION_ZSTD {
+            let mut decoded = vec![];
+            ruzstd::decoding::StreamingDecoder::new(data)
+                .map_err(|e| Error::Load(e.to_string()))?
+                .read_to_end(&mut decoded)
+                .map_err(|e| Error::Load(e.to_string()))?;
+            decoded
+        } else {
+            data.to_vec()
+        };
+        let [level_width, level_height] = [width, height].map(|d| (d >> level).max(1));
+        let data = if uastc {
+            transcode_uastc(&data, level_width, level_height, has_alpha)?
+        } else {
+            data
+        };
+        if data.len() != format.level_size(level_width, level_height) {
+            return Err(invalid());
+        }
+        pixels.extend(data);
+    }
+
+    Ok(Image {
+        pixels,
+        width,
+        height,
+        mip_levels,
+        format,
+        ..Default::default()
+    })
+}
+
+fn transcode_uastc(data: &[u8], width: u32, height: u32, has_alpha: bool) -> Result<Vec<u8>> {
+    cfg_if::cfg_if! {
+        if #[cfg(feature = "basisu")] {
+            use basis_universal::{
+                DecodeFlags, LowLevelUastcTranscoder, SliceParametersUastc, TranscoderBlockFormat,
+            };
+            LowLevelUastcTranscoder::new()
+                .transcode_slice(
+                    data,
+                    SliceParametersUastc {
+                        num_blocks_x: width.div_ceil(4),
+                        num_blocks_y: height.div_ceil(4),
+                        has_alpha,
+                        original_width: width,
+                        original_height: height,
+                    },
+                    DecodeFlags::HIGH_QUALITY,
+                    TranscoderBlockFormat::BC7,
+                )
+                .map_err(|e| Error::Load(format!("UASTC transcoding failed: {:?}", e)))
+        } else {
+            let _ = (data, width, height, has_alpha);
+            Err(Error::Support(
+                "UASTC KTX2 textures require the `basisu` feature of asset_loader".to_string(),
+            ))
+        }
+    }
+}
+
+fn read_uri(uri: &str, base: &Path) -> std::result::Result<Vec<u8>, String> {
+    match uri.strip_prefix("data:") {
+        Some(data) => {
+            let encoded = data.split(";base64,").nth(1).ok_or("Invalid data URI")?;
+            base64::decode(encoded).map_err(|e| e.to_string())
+        }
+        None => {
+            let uri = urlencoding::decode(uri).map_err(|e| e.to_string())?;
+            std::fs::read(base.join(&*uri)).map_err(|e| e.to_string())
+        }
+    }
+}
+
+/// Same as what gltf::import does for buffers
+fn read_buffers(
+    document: &Document,
+    base: &Path,
+    mut blob: Option<Vec<u8>>,
+) -> std::result::Result<Vec<buffer::Data>, String> {
+    document
+        .buffers()
+        .map(|b| {
+            let mut data = match b.source() {
+                buffer::Source::Uri(uri) => read_uri(uri, base)?,
+                buffer::Source::Bin => blob.take().ok_or("Missing binary chunk")?,
+            };
+            if data.len() < b.length() {
+                return Err(format!("Buffer {} is too short", b.index()));
+            }
+            data.resize(data.len().div_ceil(4) * 4, 0);
+            Ok(buffer::Data(data))
+        })
+        .collect()
+}
+
+fn read_image_bytes(
+    image: gltf::Image,
+    base: &Path,
+    buffers: &[buffer::Data],
+) -> std::result::Result<Vec<u8>, String> {
+    match image.source() {
+        Source::View { view, .. } => Ok(buffers[view.buffer().index()]
+            [view.offset()..view.offset() + view.length()]
+            .to_vec()),
+        Source::Uri { uri, .. } => read_uri(uri, base),
+    }
+}
+
+/// Import a glTF using KHR_texture_basisu.
+/// gltf::import cannot decode KTX2 images, so they are read here.
+/// Textures use their KTX2 source when it decodes and fall back to `source` otherwise.
+pub(crate) fn import_basisu(
+    path: &Path,
+    json: &serde_json::Value,
+) -> Result<(Document, Vec<buffer::Data>, Vec<RawImage>)> {
+    let load_error = |e: &dyn ToString| Error::Load(e.to_string());
+    let bytes = std::fs::read(path).map_err(|e| load_error(&e))?;
+    let blob = if bytes.starts_with(b"glTF") {
+        gltf::Glb::from_slice(&bytes)
+            .map_err(|e| load_error(&e))?
+            .bin
+            .map(|b| b.into_owned())
+    } else {
+        None
+    };
+
+    // Textures may only have the KTX2 source
+    let mut json = json.clone();
+    let mut basisu_sources = vec![];
+    if let Some(textures) = json.get_mut("textures").and_then(|t| t.as_array_mut()) {
+        for (texture, raw) in textures.iter_mut().enumerate() {
+            let source = raw
+                .pointer(&format!("/extensions/{}/source", KHR_TEXTURE_BASISU))
+                .and_then(|s| s.as_u64());
+            if let Some(source) = source {
+                basisu_sources.push((texture, source as usize, raw.get("source").is_some()));
+                if raw.get("source").is_none() {
+                    raw["source"] = source.into();
+                }
+            }
+        }
+    }
+    let root: gltf::json::Root = serde_json::from_value(json).map_err(|e| load_error(&e))?;
+    let document = Document::from_json(root).map_err(|e| load_error(&e))?;
+    let base = path.parent().unwrap_or_else(|| Path::new("./"));
+    let buffers = read_buffers(&document, base, blob).map_err(|e| load_error(&e))?;
+
+    let mut ktx2_ok = vec![false; document.images().len()];
+    let images = document
+        .images()
+        .map(|image| {
+            let index = image.index();
+            let decoded = read_image_bytes(image, base, &buffers).and_then(|bytes| {
+                if is_ktx2(&bytes) {
+                    ktx2_ok[index] = true;
+                    read_ktx2(&bytes).map_err(|e| e.to_string())
+                } else {
+                    image::load_from_memory(&bytes)
+                        .map(Image::from_dynamic)
+                        .map_err(|e| e.to_string())
+                }
+            });
+            decoded.map(RawImage::Loaded).unwrap_or_else(|e| {
+                ktx2_ok[index] = false;
+                RawImage::Missing(e)
+            })
+        })
+        .collect();
+
+    let mut root = document.into_json();
+    let mut ktx2_textures = 0;
+    for (texture, source, has_fallback) in basisu_sources {
+        if ktx2_ok.get(source).copied().unwrap_or(false) {
+            root.textures[texture].source = gltf::json::Index::new(source as _);
+            ktx2_textures += 1;
+        } else if !has_fallback {
+            warn!("Texture {} has no usable image", texture);
+        }
+    }
+    info!("{} textures use KTX2 images", ktx2_textures);
+    let document = Document::from_json(root).map_err(|e| load_error(&e))?;
+    Ok((document, buffers, images))
+}
+
+#[test]
+fn test_read_ktx2() {
+    let mut bytes = IDENTIFIER.to_vec();
+    // BC1_RGBA_SRGB 8x4 with 2 levels
+    let header = [134, 1, 8, 4, 0, 0, 1, 2, SUPERCOMPRESSION_NONE];
+    bytes.extend(header.iter().flat_map(|h: &u32| h.to_le_bytes()));
+    // Descriptor with a zero color model, then no key/value or global data
+    bytes.extend(
+        [HEADER_SIZE as u32 + 48, 16, 0, 0]
+            .iter()
+            .flat_map(|i| i.to_le_bytes()),
+    );
+    bytes.extend([0u64; 2].iter().flat_map(|i| i.to_le_bytes()));
+    let data_start = HEADER_SIZE as u64 + 48 + 16;
+    let level_index = [[data_start, 16, 16], [data_start + 16, 8, 8]];
+    bytes.extend(level_index.iter().flatten().flat_map(|i| i.to_le_bytes()));
+    bytes.extend([0u8; 16]);
+    bytes.extend((0..24u8).collect::<Vec<_>>());
+
+    let image = read_ktx2(&bytes).unwrap();
+    assert_eq!(image.format, PixelFormat::Bc1);
+    assert_eq!([image.width, image.height, image.mip_levels], [8, 4, 2]);
+    assert_eq!(image.mip_offsets(), vec![0, 16]);
+    assert_eq!(image.pixels, (0..24).collect::<Vec<_>>());
+}
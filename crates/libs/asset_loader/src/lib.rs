@@ -2,13 +2,25 @@ mod aabb;
 #[cfg(feature = "ash")]
 pub mod acceleration_structures;
 mod animation;
+mod bvh;
+mod cache;
+pub mod camera;
+mod compact;
 mod cubumap;
+mod draco;
 mod error;
+mod export;
 mod geometry;
 mod image;
+mod instancing;
+mod ktx2;
 pub mod light;
 mod material;
 mod morph;
+mod obj;
+mod options;
+mod progress;
+mod report;
 mod scene_graph;
 mod skinning;
 mod texture;
@@ -16,9 +28,18 @@ mod texture;
 #[cfg(feature = "ash")]
 pub mod globals;
 
-pub use crate::scene_graph::load_file;
+pub use crate::animation::{AnimationPlayer, Clip, PlayMode};
+pub use crate::bvh::{Bvh, Hit, Ray};
+pub use crate::compact::{
+    AnimatedVertex, ColorLayout, CompactGeometry, MorphGeometry, VertexAttributes,
+};
+pub use crate::options::{LoadOptions, NormalMode};
+pub use crate::progress::{LoadPhase, LoadProgress};
+pub use crate::report::LoadReport;
 pub use crate::scene_graph::Doc;
+pub use crate::scene_graph::{load_file, load_file_with_progress};
 use gltf::Document;
+use std::path::Path;
 
 type Name = Option<String>;
 type Index = u32;
@@ -35,20 +56,46 @@ fn a3toa4<T: Copy>(a3: &[T], w: T) -> [T; 4] {
     [a3[0], a3[1], a3[2], w]
 }
 
-fn check_extensions(doc: &Document) {
-    const SUPPORTED: [&str; 0] = [
-        // "KHR_materials_ior",
-        // "KHR_materials_pbrSpecularGlossiness",
-        // "KHR_materials_transmission",
-        // "KHR_materials_variants",
-        // "KHR_materials_volume",
-        // "KHR_materials_specular",
-        // "KHR_texture_transform",
-        // "KHR_materials_unlit"
+/// Extensions used by the document that are not supported
+fn check_extensions(doc: &Document) -> Vec<String> {
+    const SUPPORTED: &[&str] = &[
+        "KHR_materials_ior",
+        "KHR_materials_pbrSpecularGlossiness",
+        "KHR_materials_transmission",
+        "KHR_materials_variants",
+        "KHR_materials_volume",
+        "KHR_materials_specular",
+        "KHR_texture_transform",
+        "KHR_materials_unlit",
+        "KHR_lights_punctual",
+        "EXT_mesh_gpu_instancing",
+        #[cfg(feature = "basisu")]
+        ktx2::KHR_TEXTURE_BASISU,
+        #[cfg(feature = "draco")]
+        draco::KHR_DRACO_MESH_COMPRESSION,
     ];
     doc.extensions_used()
         .filter(|ext| SUPPORTED.iter().all(|s| s != ext))
-        .for_each(|ext| log::error!("Extension {} is used but not supported", ext));
+        .inspect(|ext| log::error!("Extension {} is used but not supported", ext))
+        .map(|ext| ext.to_string())
+        .collect()
+}
+
+/// Raw JSON of a .gltf or .glb file, for extensions the gltf crate drops.
+fn read_raw_json(path: &Path) -> Option<serde_json::Value> {
+    use std::io::Read;
+    let mut file = std::fs::File::open(path).ok()?;
+    // A .glb header followed by the header of its JSON chunk
+    let mut header = [0; 20];
+    if file.read_exact(&mut header).is_ok() && header.starts_with(b"glTF") {
+        // Only the JSON chunk is read, the binary chunk can be large
+        let length = u32::from_le_bytes([header[12], header[13], header[14], header[15]]);
+        let mut json = vec![0; length as usize];
+        file.read_exact(&mut json).ok()?;
+        serde_json::from_slice(&json).ok()
+    } else {
+        serde_json::from_slice(&std::fs::read(path).ok()?).ok()
+    }
 }
 
 #[macro_export]
@@ -78,3 +125,37 @@ macro_rules! get_name {
         $expr.name().map(|n| n.to_string())
     };
 }
+
+/// Files written by a test into its own temporary directory, removed on drop
+#[cfg(test)]
+pub(crate) struct TestDir(std::path::PathBuf);
+
+#[cfg(test)]
+impl TestDir {
+    /// `files` are names and contents, the directory is unique to the call
+    pub(crate) fn new(files: &[(&str, &str)]) -> Self {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "rustracer_test_{}_{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, contents) in files {
+            std::fs::write(dir.join(name), contents).unwrap();
+        }
+        Self(dir)
+    }
+
+    pub(crate) fn path(&self, name: &str) -> std::path::PathBuf {
+        self.0.join(name)
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
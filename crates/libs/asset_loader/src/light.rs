@@ -1,8 +1,12 @@
+use crate::export::named;
 use crate::{a3toa4, get_name, Name};
-use glam::{Mat4, Vec4, Vec4Swizzles};
+use glam::{Mat4, Vec3, Vec4, Vec4Swizzles};
 use gltf::khr_lights_punctual::Kind;
-use log::{error, info};
+use log::info;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 
+#[derive(Serialize, Deserialize)]
 pub struct Light {
     pub index: usize,
     color: [f32; 3],
@@ -10,23 +14,73 @@ pub struct Light {
     kind: LightType,
     range: f32,
     intensity: f32,
+    // Cone angles in radians, only used by spot lights
+    inner_cone_angle: f32,
+    outer_cone_angle: f32,
 }
 
 impl Light {
     pub fn to_raw(&self, transform: Mat4) -> LightRaw {
         let kind = self.kind;
+        let (light_angle_scale, light_angle_offset) = if kind == LightType::Spot {
+            spot_angle_attenuation(self.inner_cone_angle, self.outer_cone_angle)
+        } else {
+            (0., 1.)
+        };
         LightRaw {
             color: Vec4::from_array(a3toa4(&self.color, 0.)),
             transform: kind.get_transform(transform),
+            direction: (transform * FORWARD).normalize_or_zero(),
             kind: kind as _,
-            range: self.range,
+            // Positions are in world space, which may be scaled and fitted by `normalize`
+            range: if self.range < f32::MAX {
+                self.range * transform.transform_vector3(Vec3::X).length()
+            } else {
+                self.range
+            },
             intensity: self.intensity,
-            _padding: 0,
+            light_angle_scale,
+            light_angle_offset,
+            _padding: [0; 3],
         }
     }
+
+    pub(crate) fn to_json(&self) -> Value {
+        let kind = match self.kind {
+            LightType::Directional => "directional",
+            LightType::Point => "point",
+            LightType::Spot => "spot",
+        };
+        let mut light = named(
+            json!({"type": kind, "color": self.color, "intensity": self.intensity}),
+            &self.name,
+        );
+        // Infinite by default
+        if self.range < f32::MAX {
+            light["range"] = self.range.into();
+        }
+        if self.kind == LightType::Spot {
+            light["spot"] = json!({
+                "innerConeAngle": self.inner_cone_angle,
+                "outerConeAngle": self.outer_cone_angle,
+            });
+        }
+        light
+    }
+}
+
+// Lights point down the local -Z axis
+const FORWARD: Vec4 = Vec4::from_array([0., 0., -1.0, 0.]);
+
+/// Angular attenuation factors as suggested by KHR_lights_punctual:
+/// clamp(cos(angle) * scale + offset, 0, 1)^2
+fn spot_angle_attenuation(inner_cone_angle: f32, outer_cone_angle: f32) -> (f32, f32) {
+    let cos_outer = outer_cone_angle.cos();
+    let scale = 1. / (inner_cone_angle.cos() - cos_outer).max(0.001);
+    (scale, -cos_outer * scale)
 }
 
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Copy, Clone, PartialEq, Serialize, Deserialize)]
 enum LightType {
     Directional = 0,
     Point = 1,
@@ -37,7 +91,7 @@ impl LightType {
     fn get_transform(&self, transform: Mat4) -> Vec4 {
         transform
             * match self {
-                LightType::Directional => Vec4::from_array([0., 0., -1.0, 0.]),
+                LightType::Directional => FORWARD,
                 LightType::Point | LightType::Spot => Vec4::from_array([0., 0., 0., 1.]),
             }
     }
@@ -48,13 +102,7 @@ impl From<Kind> for LightType {
         match value {
             Kind::Directional => Self::Directional,
             Kind::Point => Self::Point,
-            Kind::Spot {
-                inner_cone_angle: _,
-                outer_cone_angle: _,
-            } => {
-                error!("Unimplemented; treat spot light as point light");
-                Self::Point
-            }
+            Kind::Spot { .. } => Self::Spot,
         }
     }
 }
@@ -64,10 +112,14 @@ impl From<Kind> for LightType {
 pub struct LightRaw {
     pub color: Vec4,
     pub(crate) transform: Vec4,
+    // Forward axis of the light node, used by spot lights
+    pub(crate) direction: Vec4,
     pub kind: u32,
     pub range: f32,
     pub intensity: f32,
-    pub _padding: u32,
+    light_angle_scale: f32,
+    light_angle_offset: f32,
+    pub _padding: [u32; 3],
 }
 
 impl LightRaw {
@@ -95,10 +147,13 @@ impl LightRaw {
         Self {
             color: Vec4::ONE,
             transform: (get_random() - 0.5) * 2. * distance,
+            direction: FORWARD,
             kind: LightType::Point as _,
             range: f32::INFINITY,
             intensity: 0.0,
-            _padding: 0,
+            light_angle_scale: 0.,
+            light_angle_offset: 1.,
+            _padding: [0; 3],
         }
     }
 
@@ -117,16 +172,26 @@ impl Default for LightRaw {
         Self {
             color: Vec4::from_array([1.; 4]),
             transform: Vec4::ONE,
+            direction: FORWARD,
             kind: LightType::Directional as _,
             range: f32::INFINITY,
             intensity: 0.,
-            _padding: 0,
+            light_angle_scale: 0.,
+            light_angle_offset: 1.,
+            _padding: [0; 3],
         }
     }
 }
 
 impl<'a> From<gltf::khr_lights_punctual::Light<'a>> for Light {
     fn from(light: gltf::khr_lights_punctual::Light) -> Self {
+        let (inner_cone_angle, outer_cone_angle) = match light.kind() {
+            Kind::Spot {
+                inner_cone_angle,
+                outer_cone_angle,
+            } => (inner_cone_angle, outer_cone_angle),
+            _ => (0., std::f32::consts::FRAC_PI_4),
+        };
         Self {
             index: light.index(),
             color: light.color(),
@@ -134,6 +199,8 @@ impl<'a> From<gltf::khr_lights_punctual::Light<'a>> for Light {
             kind: light.kind().into(),
             range: light.range().unwrap_or(f32::MAX),
             intensity: light.intensity(),
+            inner_cone_angle,
+            outer_cone_angle,
         }
     }
 }
@@ -144,5 +211,54 @@ pub fn report_lights(lights: &[Light]) {
         .filter(|l| l.kind == LightType::Directional)
         .count();
     let points = lights.iter().filter(|l| l.kind == LightType::Point).count();
-    info!("Directional lights: {}; point lights: {}", dirs, points);
+    let spots = lights.iter().filter(|l| l.kind == LightType::Spot).count();
+    info!(
+        "Directional lights: {}; point lights: {}; spot lights: {}",
+        dirs, points, spots
+    );
+}
+
+#[test]
+fn test_spot_angle_attenuation() {
+    let (scale, offset) = spot_angle_attenuation(0.2, 0.6);
+    let cd = |angle: f32| (angle.cos() * scale + offset).clamp(0., 1.);
+    assert!((cd(0.2) - 1.).abs() < 1e-5);
+    assert!(cd(0.6).abs() < 1e-5);
+    assert_eq!(cd(0.), 1.);
+    assert_eq!(cd(1.), 0.);
+}
+
+#[test]
+fn test_light_range() {
+    use crate::load_file;
+    let triangle: [f32; 9] = [0., 0., 0., 1., 0., 0., 0., 1., 0.];
+    let bytes: Vec<u8> = triangle.iter().flat_map(|f| f.to_le_bytes()).collect();
+    let gltf = json!({
+        "asset": {"version": "2.0"},
+        "extensionsUsed": ["KHR_lights_punctual"],
+        "extensions": {"KHR_lights_punctual": {"lights": [
+            {"type": "point", "range": 3.},
+            {"type": "point"}
+        ]}},
+        "buffers": [{"byteLength": 36, "uri": format!(
+            "data:application/octet-stream;base64,{}", base64::encode(bytes))}],
+        "bufferViews": [{"buffer": 0, "byteLength": 36}],
+        "accessors": [{"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+            "min": [0, 0, 0], "max": [1, 1, 0]}],
+        "meshes": [{"primitives": [{"attributes": {"POSITION": 0}}]}],
+        "nodes": [
+            {"mesh": 0},
+            {"scale": [2, 2, 2], "extensions": {"KHR_lights_punctual": {"light": 0}}},
+            {"extensions": {"KHR_lights_punctual": {"light": 1}}}
+        ],
+        "scenes": [{"nodes": [0, 1, 2]}]
+    });
+    let dir = crate::TestDir::new(&[("light.gltf", &gltf.to_string())]);
+    let doc = load_file(dir.path("light.gltf"), &Default::default()).unwrap();
+    // Fitted into the 10 unit box
+    let fit = doc.aabb_trans.transform_vector3(Vec3::X).length();
+    assert!((fit - 10.).abs() < 1e-4);
+    let [_, points] = doc.get_lights_raw();
+    assert!((points[0].range - 3. * 2. * fit).abs() < 1e-3);
+    assert_eq!(points[1].range, f32::MAX);
 }
@@ -1,72 +1,172 @@
+use crate::export::named;
 use crate::geometry::DEFAULT_MATERIAL_INDEX;
 use crate::{a3toa4, get_name, Name};
+use gltf::json::extensions::texture::TextureTransform;
 use gltf::material::{
     AlphaMode, NormalTexture, OcclusionTexture, PbrMetallicRoughness, PbrSpecularGlossiness,
     Specular, Transmission, Volume,
 };
 use gltf::{texture, Document};
 use log::info;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use std::collections::HashSet;
 
 #[repr(C)]
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub struct TextureInfo {
     pub texture_index: i32,
     // Most glTF only uses tex_coord 0
     tex_coord: i32,
+    // KHR_texture_transform as a column-major 3x2 matrix
+    transform: [f32; 6],
+    _padding: [i32; 2],
 }
 
+const IDENTITY_UV_TRANSFORM: [f32; 6] = [1., 0., 0., 1., 0., 0.];
+
 impl Default for TextureInfo {
     fn default() -> Self {
         Self {
             tex_coord: -1,
             texture_index: -1,
+            transform: IDENTITY_UV_TRANSFORM,
+            _padding: [0; 2],
         }
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub enum MaterialType {
     MetallicRoughness,
 }
 
 impl TextureInfo {
+    fn from_texture(texture: texture::Texture, tex_coord: u32) -> Self {
+        Self {
+            texture_index: (1 + texture.index()) as _,
+            tex_coord: tex_coord as _,
+            ..Default::default()
+        }
+    }
+
     fn new(info: Option<texture::Info>) -> Self {
-        info.map(|t| Self {
-            texture_index: (1 + t.texture().index()) as _,
-            tex_coord: t.tex_coord() as _,
+        info.map(|t| {
+            let info = Self::from_texture(t.texture(), t.tex_coord());
+            match t.texture_transform() {
+                Some(tr) => {
+                    info.with_transform(tr.offset(), tr.rotation(), tr.scale(), tr.tex_coord())
+                }
+                None => info,
+            }
         })
         .unwrap_or_default()
     }
 
     fn new_normal(info: Option<NormalTexture>) -> Self {
-        info.map(|t| Self {
-            texture_index: (1 + t.texture().index()) as _,
-            tex_coord: t.tex_coord() as _,
-        })
-        .unwrap_or_default()
+        info.map(|t| Self::from_texture(t.texture(), t.tex_coord()))
+            .unwrap_or_default()
     }
 
     fn new_occ(info: Option<OcclusionTexture>) -> Self {
-        info.map(|t| Self {
-            texture_index: (1 + t.texture().index()) as _,
-            tex_coord: t.tex_coord() as _,
-        })
-        .unwrap_or_default()
+        info.map(|t| Self::from_texture(t.texture(), t.tex_coord()))
+            .unwrap_or_default()
+    }
+
+    fn with_transform(
+        mut self,
+        offset: [f32; 2],
+        rotation: f32,
+        scale: [f32; 2],
+        tex_coord: Option<u32>,
+    ) -> Self {
+        if self.is_none() {
+            return self;
+        }
+        self.transform = uv_transform(offset, rotation, scale);
+        if let Some(tex_coord) = tex_coord {
+            self.tex_coord = tex_coord as _;
+        }
+        self
     }
 
     fn is_none(&self) -> bool {
         self.texture_index == -1
     }
+
+    /// glTF textureInfo, with the texture index mapped by `texture`
+    fn to_json(self, texture: &dyn Fn(i32) -> Option<usize>) -> Option<Value> {
+        let index = texture(self.texture_index)?;
+        let mut info = json!({"index": index, "texCoord": self.tex_coord.max(0)});
+        if self.transform != IDENTITY_UV_TRANSFORM {
+            let [m0, m1, m2, m3, x, y] = self.transform;
+            info["extensions"] = json!({"KHR_texture_transform": {
+                "offset": [x, y],
+                "rotation": (-m1).atan2(m0),
+                "scale": [m0.hypot(m1), m2.hypot(m3)],
+            }});
+        }
+        Some(info)
+    }
+}
+
+/// Set `key` to the texture info if the texture is exported
+fn set_texture(
+    value: &mut Value,
+    key: &str,
+    info: TextureInfo,
+    texture: &dyn Fn(i32) -> Option<usize>,
+) {
+    if let Some(info) = info.to_json(texture) {
+        value[key] = info;
+    }
+}
+
+/// translation * rotation * scale, as defined by KHR_texture_transform
+fn uv_transform(offset: [f32; 2], rotation: f32, scale: [f32; 2]) -> [f32; 6] {
+    let (s, c) = rotation.sin_cos();
+    [
+        c * scale[0],
+        -s * scale[0],
+        s * scale[1],
+        c * scale[1],
+        offset[0],
+        offset[1],
+    ]
+}
+
+/// The gltf crate drops KHR_texture_transform on normal and occlusion textures,
+/// so read them from the raw JSON instead.
+pub(crate) fn patch_texture_transforms(json: &serde_json::Value, materials: &mut [Material]) {
+    let raw_materials = match json.get("materials").and_then(|m| m.as_array()) {
+        Some(m) => m,
+        None => return,
+    };
+    for (material, raw) in materials.iter_mut().zip(raw_materials) {
+        for (slot, info) in [
+            ("normalTexture", &mut material.normal_texture),
+            ("occlusionTexture", &mut material.occlusion_texture),
+        ] {
+            let transform = raw
+                .get(slot)
+                .and_then(|t| t.pointer("/extensions/KHR_texture_transform"))
+                .cloned()
+                .and_then(|t| serde_json::from_value::<TextureTransform>(t).ok());
+            if let Some(t) = transform {
+                *info = info.with_transform(t.offset.0, t.rotation.0, t.scale.0, t.tex_coord);
+            }
+        }
+    }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Material {
     pub(crate) name: Name,
     pub(crate) index: usize,
 
     pub unlit: bool,
 
+    #[serde(with = "crate::cache::AlphaModeDef")]
     pub alpha_mode: AlphaMode,
     pub alpha_cutoff: Option<f32>,
     pub double_sided: bool,
@@ -97,6 +197,137 @@ impl Material {
     pub fn is_opaque(&self) -> bool {
         self.alpha_mode == AlphaMode::Opaque
     }
+
+    /// Textures holding sRGB color data
+    pub(crate) fn color_textures(&self) -> Vec<usize> {
+        let mut textures = vec![self.base_color_texture, self.emissive_texture];
+        if let Some(sp) = self.specular_info {
+            textures.push(sp.specular_color_texture);
+        }
+        if let Some(sg) = self.specular_glossiness {
+            textures.extend([sg.diffuse_texture, sg.specular_glossiness_texture]);
+        }
+        textures
+            .iter()
+            .filter_map(|t| usize::try_from(t.texture_index).ok())
+            .collect()
+    }
+
+    /// glTF material, with texture indices mapped by `texture`
+    pub(crate) fn to_json(&self, texture: &dyn Fn(i32) -> Option<usize>) -> Value {
+        let mr = &self.metallic_roughness_info;
+        let mut pbr = json!({
+            "baseColorFactor": self.base_color,
+            "metallicFactor": mr.metallic_factor,
+            "roughnessFactor": mr.roughness_factor,
+        });
+        set_texture(
+            &mut pbr,
+            "baseColorTexture",
+            self.base_color_texture,
+            texture,
+        );
+        set_texture(
+            &mut pbr,
+            "metallicRoughnessTexture",
+            mr.metallic_roughness_texture,
+            texture,
+        );
+        let [r, g, b, _] = self.emissive_factor;
+        let mut material = named(
+            json!({
+                "pbrMetallicRoughness": pbr,
+                "emissiveFactor": [r, g, b],
+                "alphaMode": match self.alpha_mode {
+                    AlphaMode::Opaque => "OPAQUE",
+                    AlphaMode::Mask => "MASK",
+                    AlphaMode::Blend => "BLEND",
+                },
+                "doubleSided": self.double_sided,
+            }),
+            &self.name,
+        );
+        if self.alpha_mode == AlphaMode::Mask {
+            material["alphaCutoff"] = self.alpha_cutoff.unwrap_or(0.5).into();
+        }
+        set_texture(&mut material, "normalTexture", self.normal_texture, texture);
+        set_texture(
+            &mut material,
+            "emissiveTexture",
+            self.emissive_texture,
+            texture,
+        );
+        set_texture(
+            &mut material,
+            "occlusionTexture",
+            self.occlusion_texture,
+            texture,
+        );
+
+        let mut extensions = json!({});
+        if self.unlit {
+            extensions["KHR_materials_unlit"] = json!({});
+        }
+        if self.ior != 1.5 {
+            extensions["KHR_materials_ior"] = json!({ "ior": self.ior });
+        }
+        if let Some(t) = self.transmission {
+            let mut ext = json!({ "transmissionFactor": t.transmission_factor });
+            set_texture(
+                &mut ext,
+                "transmissionTexture",
+                t.transmission_texture,
+                texture,
+            );
+            extensions["KHR_materials_transmission"] = ext;
+        }
+        if let Some(v) = self.volume_info {
+            let mut ext = json!({
+                "thicknessFactor": v.thickness_factor,
+                "attenuationColor": v.attenuation_color,
+            });
+            // Infinite by default
+            if v.attenuation_distance < f32::MAX {
+                ext["attenuationDistance"] = v.attenuation_distance.into();
+            }
+            set_texture(&mut ext, "thicknessTexture", v.thickness_texture, texture);
+            extensions["KHR_materials_volume"] = ext;
+        }
+        if let Some(sp) = self.specular_info {
+            let [r, g, b, _] = sp.specular_color_factor;
+            let mut ext = json!({
+                "specularFactor": sp.specular_factor,
+                "specularColorFactor": [r, g, b],
+            });
+            set_texture(&mut ext, "specularTexture", sp.specular_texture, texture);
+            set_texture(
+                &mut ext,
+                "specularColorTexture",
+                sp.specular_color_texture,
+                texture,
+            );
+            extensions["KHR_materials_specular"] = ext;
+        }
+        if let Some(sg) = self.specular_glossiness {
+            let mut ext = json!({
+                "diffuseFactor": sg.diffuse_factor,
+                "specularFactor": sg.specular_factor,
+                "glossinessFactor": sg.glossiness_factor,
+            });
+            set_texture(&mut ext, "diffuseTexture", sg.diffuse_texture, texture);
+            set_texture(
+                &mut ext,
+                "specularGlossinessTexture",
+                sg.specular_glossiness_texture,
+                texture,
+            );
+            extensions["KHR_materials_pbrSpecularGlossiness"] = ext;
+        }
+        if extensions.as_object().is_some_and(|e| !e.is_empty()) {
+            material["extensions"] = extensions;
+        }
+        material
+    }
 }
 
 pub fn find_linear_textures(doc: &Document) -> HashSet<usize> {
@@ -191,7 +422,7 @@ impl From<&Material> for MaterialRaw {
 
 // 8 floats
 #[repr(C)]
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Serialize, Deserialize)]
 pub struct VolumeInfo {
     attenuation_color: [f32; 3],
     thickness_factor: f32,
@@ -230,7 +461,7 @@ impl From<Volume<'_>> for VolumeInfo {
 
 // 4 floats
 #[repr(C)]
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Serialize, Deserialize)]
 pub struct MetallicRoughnessInfo {
     pub metallic_factor: f32,
     pub roughness_factor: f32,
@@ -248,7 +479,7 @@ impl From<PbrMetallicRoughness<'_>> for MetallicRoughnessInfo {
 }
 
 #[repr(C)]
-#[derive(Default, Copy, Clone)]
+#[derive(Default, Copy, Clone, Serialize, Deserialize)]
 pub struct TransmissionInfo {
     transmission_texture: TextureInfo,
     transmission_factor: f32,
@@ -266,7 +497,7 @@ impl From<Transmission<'_>> for TransmissionInfo {
 }
 
 #[repr(C)]
-#[derive(Copy, Clone, Default)]
+#[derive(Copy, Clone, Default, Serialize, Deserialize)]
 struct SpecularGlossiness {
     diffuse_factor: [f32; 4],
     specular_factor: [f32; 3],
@@ -288,7 +519,7 @@ impl From<PbrSpecularGlossiness<'_>> for SpecularGlossiness {
 }
 
 #[repr(C)]
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Serialize, Deserialize)]
 struct SpecularInfo {
     specular_texture: TextureInfo,
     specular_color_texture: TextureInfo,
@@ -379,3 +610,17 @@ impl From<gltf::Material<'_>> for Material {
         }
     }
 }
+
+#[test]
+fn test_uv_transform() {
+    let apply =
+        |m: [f32; 6], [u, v]: [f32; 2]| [m[0] * u + m[2] * v + m[4], m[1] * u + m[3] * v + m[5]];
+    assert_eq!(uv_transform([0., 0.], 0., [1., 1.]), IDENTITY_UV_TRANSFORM);
+    let m = uv_transform([0.5, 0.25], 0., [2., 4.]);
+    assert_eq!(apply(m, [1., 1.]), [2.5, 4.25]);
+    let [u, v] = apply(
+        uv_transform([0., 0.], std::f32::consts::FRAC_PI_2, [1., 1.]),
+        [1., 0.],
+    );
+    assert!(u.abs() < 1e-6 && (v + 1.).abs() < 1e-6);
+}
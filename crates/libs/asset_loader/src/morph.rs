@@ -1 +1,140 @@
-struct MorphTarget {}
+use crate::export::GltfBuffer;
+use crate::geometry::reindex;
+use crate::Index;
+use glam::Vec3;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// Per-vertex displacements of one morph target.
+/// Missing attributes are stored as empty vectors.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct MorphTarget {
+    positions: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    tangents: Vec<Vec3>,
+}
+
+impl MorphTarget {
+    pub fn new(positions: Vec<Vec3>, normals: Vec<Vec3>, tangents: Vec<Vec3>) -> Self {
+        Self {
+            positions,
+            normals,
+            tangents,
+        }
+    }
+
+    /// Keep the displacements at `sources`, in order
+    pub(crate) fn reindex(&mut self, sources: &[Index]) {
+        reindex(&mut self.positions, sources);
+        reindex(&mut self.normals, sources);
+        reindex(&mut self.tangents, sources);
+    }
+
+    /// Displacements of one vertex as bits, for welding
+    pub(crate) fn vertex_bits(&self, vertex: usize) -> impl Iterator<Item = u32> + '_ {
+        [&self.positions, &self.normals, &self.tangents]
+            .into_iter()
+            .filter_map(move |d| d.get(vertex))
+            .flat_map(|d| d.to_array().map(f32::to_bits))
+    }
+
+    /// Position, normal and tangent displacements of a vertex, zero when missing
+    pub(crate) fn delta(&self, vertex: usize) -> [f32; 9] {
+        let mut delta = [0.; 9];
+        for (d, data) in
+            delta
+                .chunks_exact_mut(3)
+                .zip([&self.positions, &self.normals, &self.tangents])
+        {
+            if let Some(v) = data.get(vertex) {
+                d.copy_from_slice(&v.to_array());
+            }
+        }
+        delta
+    }
+
+    pub(crate) fn to_json(&self, buffer: &mut GltfBuffer) -> Value {
+        let mut target = json!({});
+        for (key, data) in [
+            ("POSITION", &self.positions),
+            ("NORMAL", &self.normals),
+            ("TANGENT", &self.tangents),
+        ] {
+            if !data.is_empty() {
+                let values: Vec<_> = data.iter().flat_map(|d| d.to_array()).collect();
+                target[key] = buffer
+                    .push_floats(&values, "VEC3", key == "POSITION")
+                    .into();
+            }
+        }
+        target
+    }
+}
+
+#[test]
+fn test_morph_layout() {
+    use crate::compact::NO_ANIMATION;
+    use crate::load_file;
+    let triangle = [0., 0., 0., 1., 0., 0., 0., 1., 0.];
+    let displacements = [0., 0., 1., 0., 0., 2., 0., 0., 3.];
+    let keyframes = [0., 1., 0., 1.];
+    let floats: Vec<f32> = [&triangle[..], &displacements, &keyframes].concat();
+    let bytes: Vec<u8> = floats.iter().flat_map(|f| f.to_le_bytes()).collect();
+    let gltf = json!({
+        "asset": {"version": "2.0"},
+        "buffers": [{"byteLength": 88, "uri": format!(
+            "data:application/octet-stream;base64,{}", base64::encode(bytes))}],
+        "bufferViews": [
+            {"buffer": 0, "byteLength": 72},
+            {"buffer": 0, "byteOffset": 72, "byteLength": 16}
+        ],
+        "accessors": [
+            {"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                "min": [0, 0, 0], "max": [1, 1, 0]},
+            {"bufferView": 0, "byteOffset": 36, "componentType": 5126, "count": 3,
+                "type": "VEC3", "min": [0, 0, 1], "max": [0, 0, 3]},
+            {"bufferView": 1, "componentType": 5126, "count": 2, "type": "SCALAR",
+                "min": [0], "max": [1]},
+            {"bufferView": 1, "byteOffset": 8, "componentType": 5126, "count": 2,
+                "type": "SCALAR"}
+        ],
+        "meshes": [{
+            "primitives": [{"attributes": {"POSITION": 0}, "targets": [{"POSITION": 1}]}],
+            "weights": [0.5]
+        }],
+        // Two nodes with the default weights, one with its own and an animated one
+        "nodes": [{"mesh": 0}, {"mesh": 0}, {"mesh": 0, "weights": [1.0]}, {"mesh": 0}],
+        "animations": [{
+            "samplers": [{"input": 2, "output": 3}],
+            "channels": [{"sampler": 0, "target": {"node": 3, "path": "weights"}}]
+        }],
+        "scenes": [{"nodes": [0, 1, 2, 3]}]
+    });
+    let dir = crate::TestDir::new(&[("morph.gltf", &gltf.to_string())]);
+    let mut doc = load_file(dir.path("morph.gltf"), &Default::default()).unwrap();
+
+    let meshes: Vec<_> = doc.nodes.iter().map(|n| n.mesh.unwrap()).collect();
+    assert_eq!(meshes, [0, 0, 1, 2]);
+    assert_eq!(doc.get_morph_weights(), [0.5, 1., 0.5]);
+    doc.update_animations(0.25);
+    assert_eq!(doc.get_morph_weights(), [0.5, 1., 0.25]);
+
+    let builder = &doc.geo_builder;
+    let compact = builder.compact(&builder.color_layout());
+    let morphs: Vec<_> = compact.animated.iter().map(|a| a.morph).collect();
+    assert_eq!(morphs, [0, 0, 0, 1, 1, 1, 2, 2, 2]);
+    assert!(compact
+        .animated
+        .iter()
+        .all(|a| a.joint_offset == NO_ANIMATION));
+    let m = compact.morphs[2];
+    assert_eq!(
+        [m.first_vertex, m.vertices, m.targets, m.deltas, m.weights],
+        [6, 3, 1, 6, 2]
+    );
+    // The deltas of a vertex follow the vertices of its geometry
+    let vertex = 7;
+    let delta = compact.morph_deltas[(m.deltas + vertex - m.first_vertex) as usize];
+    assert_eq!(delta[..3], [0., 0., 2.]);
+    assert_eq!(delta[3..], [0.; 6]);
+}
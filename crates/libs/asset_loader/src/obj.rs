@@ -0,0 +1,208 @@
+use crate::error::*;
+use crate::export::GltfBuffer;
+use crate::image::{Image, RawImage};
+use gltf::{buffer, Document};
+use log::{info, warn};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// File name of an MTL texture statement, skipping options like `-bm 1.0`
+fn texture_path(statement: &str) -> &str {
+    if statement.starts_with('-') {
+        statement.split_whitespace().last().unwrap_or(statement)
+    } else {
+        statement.trim()
+    }
+}
+
+/// Map MTL parameters to metallic-roughness.
+/// PBR extension parameters (Pr, Pm) are used when present.
+fn material_json(m: &tobj::Material, mut texture: impl FnMut(&str) -> usize) -> Value {
+    let param = |key: &str| {
+        m.unknown_param
+            .get(key)
+            .and_then(|v| v.trim().parse::<f32>().ok())
+    };
+    let [r, g, b] = m.diffuse.unwrap_or([0.8; 3]);
+    let alpha = m
+        .dissolve
+        .or_else(|| param("Tr").map(|tr| 1. - tr))
+        .unwrap_or(1.);
+    // Blinn-Phong exponent to GGX alpha, then to perceptual roughness
+    let roughness = param("Pr").unwrap_or_else(|| {
+        m.shininess
+            .map_or(1., |ns| (2. / (ns.max(0.) + 2.)).sqrt().sqrt())
+    });
+    let mut pbr = json!({
+        "baseColorFactor": [r, g, b, alpha],
+        "metallicFactor": param("Pm").unwrap_or(0.),
+        "roughnessFactor": roughness,
+    });
+    if let Some(t) = &m.diffuse_texture {
+        pbr["baseColorTexture"] = json!({"index": texture(texture_path(t))});
+    }
+
+    let mut material = json!({"name": m.name, "pbrMetallicRoughness": pbr});
+    if alpha < 1. {
+        material["alphaMode"] = "BLEND".into();
+    }
+    if let Some(t) = &m.normal_texture {
+        material["normalTexture"] = json!({"index": texture(texture_path(t))});
+    }
+    if let Some(ke) = m.emissive {
+        material["emissiveFactor"] = json!(ke.map(|e| e.clamp(0., 1.)));
+    }
+    if let Some(t) = m.unknown_param.get("map_Ke") {
+        material["emissiveTexture"] = json!({"index": texture(texture_path(t))});
+    }
+    let mut extensions = json!({});
+    // Illumination models 0 and 1 have no highlights
+    let specular_factor = match m.illumination_model {
+        Some(0 | 1) => 0.,
+        _ => 1.,
+    };
+    if let Some(ks) = m.specular {
+        extensions["KHR_materials_specular"] = json!({
+            "specularFactor": specular_factor,
+            "specularColorFactor": ks,
+        });
+    }
+    if let Some(ni) = m.optical_density {
+        extensions["KHR_materials_ior"] = json!({ "ior": ni });
+    }
+    material["extensions"] = extensions;
+    material
+}
+
+/// Import a Wavefront OBJ and its MTL as an in-memory glTF,
+/// so it goes through the same processing as glTF files.
+pub(crate) fn import_obj(path: &Path) -> Result<(Document, Vec<buffer::Data>, Vec<RawImage>)> {
+    let (models, materials) =
+        tobj::load_obj(path, &tobj::GPU_LOAD_OPTIONS).map_err(|e| Error::Load(e.to_string()))?;
+    let materials = materials.unwrap_or_else(|e| {
+        warn!("Failed to load MTL: {}", e);
+        vec![]
+    });
+    info!(
+        "OBJ models: {}, materials: {}",
+        models.len(),
+        materials.len()
+    );
+    let base = path.parent().unwrap_or_else(|| Path::new("./"));
+
+    // One texture per image file
+    let mut image_paths: HashMap<String, usize> = HashMap::new();
+    let mut image_uris = vec![];
+    let materials_json: Vec<_> = materials
+        .iter()
+        .map(|m| {
+            material_json(m, |uri| {
+                *image_paths.entry(uri.to_string()).or_insert_with(|| {
+                    image_uris.push(uri.to_string());
+                    image_uris.len() - 1
+                })
+            })
+        })
+        .collect();
+
+    let mut buffer = GltfBuffer::default();
+    let mut meshes = vec![];
+    for model in &models {
+        let mesh = &model.mesh;
+        let mut attributes =
+            json!({ "POSITION": buffer.push_floats(&mesh.positions, "VEC3", true) });
+        if !mesh.normals.is_empty() {
+            attributes["NORMAL"] = buffer.push_floats(&mesh.normals, "VEC3", false).into();
+        }
+        if !mesh.texcoords.is_empty() {
+            // OBJ puts the texture origin at the bottom left
+            let uvs: Vec<_> = mesh
+                .texcoords
+                .chunks_exact(2)
+                .flat_map(|uv| [uv[0], 1. - uv[1]])
+                .collect();
+            attributes["TEXCOORD_0"] = buffer.push_floats(&uvs, "VEC2", false).into();
+        }
+        if !mesh.vertex_color.is_empty() {
+            attributes["COLOR_0"] = buffer.push_floats(&mesh.vertex_color, "VEC3", false).into();
+        }
+        let mut primitive = json!({
+            "attributes": attributes,
+            "indices": buffer.push_indices(&mesh.indices),
+        });
+        if let Some(material) = mesh.material_id.filter(|&m| m < materials.len()) {
+            primitive["material"] = material.into();
+        }
+        meshes.push(json!({"name": model.name, "primitives": [primitive]}));
+    }
+
+    let root = json!({
+        "asset": {"version": "2.0", "generator": "rustracer OBJ importer"},
+        "scene": 0,
+        "scenes": [{"nodes": (0..models.len()).collect::<Vec<_>>()}],
+        "nodes": (0..models.len()).map(|m| json!({"mesh": m, "name": models[m].name})).collect::<Vec<_>>(),
+        "meshes": meshes,
+        "materials": materials_json,
+        "textures": (0..image_uris.len()).map(|i| json!({"source": i})).collect::<Vec<_>>(),
+        "images": image_uris.iter().map(|uri| json!({"uri": uri})).collect::<Vec<_>>(),
+        "buffers": [{"byteLength": buffer.data.len()}],
+        "bufferViews": buffer.views,
+        "accessors": buffer.accessors,
+    });
+    let root: gltf::json::Root =
+        serde_json::from_value(root).map_err(|e| Error::Load(e.to_string()))?;
+    let document = Document::from_json(root).map_err(|e| Error::Load(e.to_string()))?;
+
+    let images = image_uris
+        .iter()
+        .map(|uri| {
+            image::open(base.join(uri))
+                .map(|i| RawImage::Loaded(Image::from_dynamic(i)))
+                .unwrap_or_else(|e| RawImage::Missing(e.to_string()))
+        })
+        .collect();
+    Ok((document, vec![buffer::Data(buffer.data)], images))
+}
+
+#[test]
+fn test_import_obj() {
+    let dir = crate::TestDir::new(&[
+        (
+            "quad.mtl",
+            "newmtl glass\nKd 0.2 0.4 0.6\nNs 0\nd 0.5\nmap_Kd -bm 1.0 missing.png\n",
+        ),
+        (
+            "quad.obj",
+            "mtllib quad.mtl\no quad\nv 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n\
+             vt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\nusemtl glass\nf 1/1 2/2 3/3 4/4\n",
+        ),
+    ]);
+
+    let (document, buffers, images) = import_obj(&dir.path("quad.obj")).unwrap();
+    let primitive = document
+        .meshes()
+        .next()
+        .unwrap()
+        .primitives()
+        .next()
+        .unwrap();
+    let reader = primitive.reader(|b| Some(&buffers[b.index()]));
+    assert_eq!(reader.read_indices().unwrap().into_u32().count(), 6);
+    let uvs: Vec<_> = reader.read_tex_coords(0).unwrap().into_f32().collect();
+    assert_eq!(uvs[0], [0., 1.]);
+
+    let material = primitive.material();
+    let pbr = material.pbr_metallic_roughness();
+    assert_eq!(pbr.base_color_factor(), [0.2, 0.4, 0.6, 0.5]);
+    assert_eq!(pbr.roughness_factor(), 1.);
+    assert_eq!(material.alpha_mode(), gltf::material::AlphaMode::Blend);
+    assert!(matches!(
+        document.images().next().map(|i| i.source()),
+        Some(gltf::image::Source::Uri {
+            uri: "missing.png",
+            ..
+        })
+    ));
+    assert!(matches!(images[..], [RawImage::Missing(_)]));
+}
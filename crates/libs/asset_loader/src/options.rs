@@ -0,0 +1,121 @@
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// Normals generated for primitives that have none
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NormalMode {
+    // One normal per triangle, vertices are split
+    Flat,
+    // Averaged across edges flatter than `LoadOptions::crease_angle`
+    #[default]
+    Smooth,
+}
+
+/// Processing applied by `load_file`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LoadOptions {
+    // Fit the scene into a 10 unit box around the origin. Without it, units are kept as authored.
+    pub normalize: bool,
+    // Rotate scenes authored Z-up to the Y-up of glTF
+    pub z_up: bool,
+    // Generate tangents with mikktspace even when the file has them
+    pub regenerate_tangents: bool,
+    // Larger images are downscaled on the CPU, keeping the aspect ratio
+    pub max_texture_size: Option<u32>,
+    pub normals: NormalMode,
+    // Degrees between triangles beyond which smooth normals keep a hard edge
+    pub crease_angle: f32,
+    // Merge vertices with equal attributes. Without it, the vertices of the file are kept.
+    pub weld: bool,
+    // Processed files are stored here and reused while the sources are unchanged
+    pub cache_dir: Option<PathBuf>,
+}
+
+impl Default for LoadOptions {
+    fn default() -> Self {
+        Self {
+            normalize: true,
+            z_up: false,
+            regenerate_tangents: false,
+            max_texture_size: None,
+            normals: NormalMode::default(),
+            crease_angle: 60.,
+            weld: true,
+            cache_dir: None,
+        }
+    }
+}
+
+impl LoadOptions {
+    /// Crease angle in radians, zero for flat normals
+    pub(crate) fn normal_crease_angle(&self) -> f32 {
+        match self.normals {
+            NormalMode::Flat => 0.,
+            NormalMode::Smooth => self.crease_angle.to_radians(),
+        }
+    }
+}
+
+#[test]
+fn test_load_options() {
+    use crate::load_file;
+    use glam::{Mat4, Vec3, Vec4Swizzles};
+    // A bent strip without normals, 20 units long along Z
+    let dir = crate::TestDir::new(&[(
+        "strip.obj",
+        "v 0 0 0\nv 1 0 0\nv 1 0 10\nv 0 0 10\nv 1 5 20\nv 0 5 20\nf 1 2 3 4\nf 4 3 5 6\n",
+    )]);
+    let path = dir.path("strip.obj");
+
+    let flat = LoadOptions {
+        normals: NormalMode::Flat,
+        ..Default::default()
+    };
+    let doc = load_file(&path, &flat).unwrap();
+    // Coplanar triangles share vertices, the bend is split
+    assert_eq!(doc.geo_builder.vertices.len(), 8);
+    let normal = doc.geo_builder.vertices[0].normal.xyz();
+    assert!((normal + Vec3::Y).length() < 1e-5);
+    let size = 10. / 20.;
+    assert!((doc.aabb_trans.transform_vector3(Vec3::Z).length() - size).abs() < 1e-5);
+
+    let options = LoadOptions {
+        normalize: false,
+        z_up: true,
+        ..Default::default()
+    };
+    let doc = load_file(&path, &options).unwrap();
+    let vertices = &doc.geo_builder.vertices;
+    assert_eq!(vertices.len(), 6);
+    assert_eq!(doc.geo_builder.indices.len(), 12);
+    // The shared edge averages the two faces
+    let shared = vertices[2].normal.xyz();
+    assert!(shared.y < 0. && shared.z > 0. && (shared.length() - 1.).abs() < 1e-5);
+    let z_up = Mat4::from_rotation_x(-std::f32::consts::FRAC_PI_2);
+    assert!(doc.aabb_trans.abs_diff_eq(z_up, 1e-6));
+    assert!((doc.aabb_trans.transform_vector3(Vec3::Z) - Vec3::Y).length() < 1e-6);
+    assert_eq!(doc.options(), &options);
+
+    // The faces are 27 degrees apart
+    let sharp = LoadOptions {
+        crease_angle: 20.,
+        ..Default::default()
+    };
+    let doc = load_file(&path, &sharp).unwrap();
+    assert_eq!(doc.geo_builder.vertices.len(), 8);
+
+    // Two triangles of a quad, each with its own vertices
+    let dir = crate::TestDir::new(&[(
+        "quad.obj",
+        "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 1 1 0\nv 0 1 0\nv 0 0 0\nf 1 2 3\nf 4 5 6\n",
+    )]);
+    let path = dir.path("quad.obj");
+    let doc = load_file(&path, &LoadOptions::default()).unwrap();
+    assert_eq!(doc.geo_builder.vertices.len(), 4);
+    let unwelded = LoadOptions {
+        weld: false,
+        ..Default::default()
+    };
+    let doc = load_file(&path, &unwelded).unwrap();
+    assert_eq!(doc.geo_builder.vertices.len(), 6);
+}
@@ -0,0 +1,83 @@
+use crate::error::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Phases of `load_file`, in order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadPhase {
+    // Reading the file and its buffers, or the cache
+    Parse,
+    Meshes,
+    Images,
+    // Skins and the scene graph
+    Skins,
+}
+
+impl LoadPhase {
+    const COUNT: usize = 4;
+
+    /// Part of the load done when the phase starts
+    pub fn fraction(self) -> f32 {
+        self as usize as f32 / Self::COUNT as f32
+    }
+}
+
+/// Progress events and cancellation of a load, cloned into the thread running it.
+/// A cancelled load stops at the start of its next phase.
+#[derive(Clone, Default)]
+pub struct LoadProgress {
+    cancelled: Arc<AtomicBool>,
+    on_phase: Option<Arc<dyn Fn(LoadPhase) + Send + Sync>>,
+}
+
+impl LoadProgress {
+    /// `on_phase` is called from the loading thread when a phase starts
+    pub fn new(on_phase: impl Fn(LoadPhase) + Send + Sync + 'static) -> Self {
+        Self {
+            cancelled: Default::default(),
+            on_phase: Some(Arc::new(on_phase)),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Report the start of a phase, or fail once cancelled
+    pub(crate) fn phase(&self, phase: LoadPhase) -> Result<()> {
+        if self.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+        if let Some(on_phase) = &self.on_phase {
+            on_phase(phase);
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_load_progress() {
+    use crate::load_file_with_progress;
+    use std::sync::Mutex;
+    let dir = crate::TestDir::new(&[("triangle.obj", "v 0 0 0\nv 1 0 0\nv 1 1 0\nf 1 2 3\n")]);
+    let path = dir.path("triangle.obj");
+
+    let phases = Arc::new(Mutex::new(vec![]));
+    let sink = phases.clone();
+    let progress = LoadProgress::new(move |p| sink.lock().unwrap().push(p));
+    load_file_with_progress(&path, &Default::default(), &progress).unwrap();
+    use LoadPhase::*;
+    assert_eq!(*phases.lock().unwrap(), [Parse, Meshes, Images, Skins]);
+    assert_eq!(Images.fraction(), 0.5);
+
+    // Cancelled before it started, as by a newer request
+    phases.lock().unwrap().clear();
+    progress.cancel();
+    let cancelled = load_file_with_progress(&path, &Default::default(), &progress);
+    assert!(matches!(cancelled, Err(Error::Cancelled)));
+    assert!(phases.lock().unwrap().is_empty());
+}
@@ -0,0 +1,123 @@
+use gltf::mesh::Mode;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Problems and workarounds found while loading a file, for triaging broken assets.
+/// Primitives are identified by [mesh, primitive] index.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LoadReport {
+    pub unsupported_extensions: Vec<String>,
+    // Points and lines, they are not rendered
+    #[serde(with = "crate::cache::skipped_primitives")]
+    pub skipped_primitives: Vec<([usize; 2], Mode)>,
+    pub generated_indices: Vec<[usize; 2]>,
+    pub generated_normals: Vec<[usize; 2]>,
+    pub generated_tangents: Vec<[usize; 2]>,
+    // Triangles removed for out of range indices or non-finite positions, and for no area
+    pub invalid_triangles: Vec<([usize; 2], usize)>,
+    pub degenerate_triangles: Vec<([usize; 2], usize)>,
+    // Duplicate and unused vertices removed
+    pub welded_vertices: Vec<([usize; 2], usize)>,
+    // Images replaced by the placeholder, with the reason
+    pub missing_textures: Vec<String>,
+    // Not cached, a cached load has its own timings
+    #[serde(skip)]
+    pub timings: Vec<(&'static str, Duration)>,
+}
+
+impl LoadReport {
+    /// Run one loading phase and record its duration
+    pub(crate) fn time<T>(&mut self, phase: &'static str, f: impl FnOnce() -> T) -> T {
+        let now = Instant::now();
+        let res = f();
+        self.timings.push((phase, now.elapsed()));
+        res
+    }
+
+    /// Add the entries of a report on part of the file, after the existing ones
+    pub(crate) fn append(&mut self, other: LoadReport) {
+        self.unsupported_extensions
+            .extend(other.unsupported_extensions);
+        self.skipped_primitives.extend(other.skipped_primitives);
+        self.generated_indices.extend(other.generated_indices);
+        self.generated_normals.extend(other.generated_normals);
+        self.generated_tangents.extend(other.generated_tangents);
+        self.invalid_triangles.extend(other.invalid_triangles);
+        self.degenerate_triangles.extend(other.degenerate_triangles);
+        self.welded_vertices.extend(other.welded_vertices);
+        self.missing_textures.extend(other.missing_textures);
+        self.timings.extend(other.timings);
+    }
+
+    pub fn has_issues(&self) -> bool {
+        self.sections().iter().any(|(_, lines)| !lines.is_empty())
+    }
+
+    /// Titled lists of problems, for display. Timings are not included.
+    pub fn sections(&self) -> Vec<(&'static str, Vec<String>)> {
+        let primitives = |ids: &[[usize; 2]]| {
+            ids.iter()
+                .map(|[m, p]| format!("Mesh {} primitive {}", m, p))
+                .collect()
+        };
+        let counts = |ids: &[([usize; 2], usize)]| {
+            ids.iter()
+                .map(|([m, p], count)| format!("Mesh {} primitive {}: {}", m, p, count))
+                .collect()
+        };
+        vec![
+            (
+                "Unsupported extensions",
+                self.unsupported_extensions.clone(),
+            ),
+            (
+                "Skipped primitives",
+                self.skipped_primitives
+                    .iter()
+                    .map(|([m, p], mode)| format!("Mesh {} primitive {}: {:?}", m, p, mode))
+                    .collect(),
+            ),
+            ("Generated indices", primitives(&self.generated_indices)),
+            ("Generated normals", primitives(&self.generated_normals)),
+            ("Generated tangents", primitives(&self.generated_tangents)),
+            ("Invalid triangles", counts(&self.invalid_triangles)),
+            ("Degenerate triangles", counts(&self.degenerate_triangles)),
+            ("Welded vertices", counts(&self.welded_vertices)),
+            ("Missing textures", self.missing_textures.clone()),
+        ]
+    }
+
+    pub fn timing_lines(&self) -> Vec<String> {
+        self.timings
+            .iter()
+            .map(|(phase, t)| format!("{}: {:.3}s", phase, t.as_secs_f32()))
+            .collect()
+    }
+}
+
+impl fmt::Display for LoadReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (title, lines) in self.sections() {
+            if !lines.is_empty() {
+                writeln!(f, "{} ({}):", title, lines.len())?;
+                lines.iter().try_for_each(|l| writeln!(f, "  {}", l))?;
+            }
+        }
+        write!(f, "Timings: {}", self.timing_lines().join(", "))
+    }
+}
+
+#[test]
+fn test_load_report() {
+    let mut report = LoadReport::default();
+    assert!(!report.has_issues());
+    assert_eq!(report.time("Phase", || 1), 1);
+    assert_eq!(report.timings[0].0, "Phase");
+    report.generated_normals.push([2, 1]);
+    report.skipped_primitives.push(([0, 3], Mode::Lines));
+    assert!(report.has_issues());
+    let text = report.to_string();
+    assert!(text.contains("Generated normals (1):\n  Mesh 2 primitive 1"));
+    assert!(text.contains("Mesh 0 primitive 3: Lines"));
+}
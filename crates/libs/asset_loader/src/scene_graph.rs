@@ -1,39 +1,54 @@
+use crate::cache;
+use crate::draco::decode_primitives;
 use crate::error::*;
-use crate::geometry::{GeoBuilder, Mesh};
-use crate::image::{process_images_unified, Image};
-use crate::material::{find_linear_textures, Material, MaterialRaw};
+use crate::export::{named, GltfBuffer};
+use crate::geometry::{GeoBuilder, Mesh, PrimitiveData, Vertex};
+use crate::image::{image_label, process_images_unified, Image, RawImage};
+use crate::instancing::read_instances;
+use crate::ktx2::{import_basisu, KHR_TEXTURE_BASISU};
+use crate::material::{find_linear_textures, patch_texture_transforms, Material, MaterialRaw};
+use crate::obj::import_obj;
+use crate::options::LoadOptions;
+use crate::progress::{LoadPhase, LoadProgress};
 use crate::texture::{Sampler, Texture};
 use crate::{
-    check_extensions, check_indices, get_index, get_index_array, get_name, MeshID, Name, NodeID,
-    SceneID,
+    check_extensions, check_indices, get_index, get_index_array, get_name, read_raw_json, MeshID,
+    Name, NodeID, SceneID,
 };
 use glam::Mat4;
 use gltf::buffer;
-use gltf::image;
 use gltf::Document;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
 use crate::aabb::Aabb;
-use crate::animation::{Animation, PropertyOutput};
+use crate::animation::{Animation, AnimationPlayer, Blend};
+use crate::bvh::{Bvh, Hit, Owner};
+use crate::camera::{Camera, CameraView};
 use crate::light::{report_lights, Light, LightRaw};
-use crate::skinning::{Skin, SkinRaw};
+use crate::report::LoadReport;
+use crate::skinning::{joint_offsets, JointRaw, Skin};
 use gltf::scene::Transform;
-use log::info;
+use log::{info, warn};
 use std::iter::once;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::time::Instant;
 
-#[derive(Default)]
+#[derive(Default, Serialize, Deserialize)]
 pub struct Doc {
-    // Only one scene is in use
-    current_scene: SceneID,
-    scenes: Vec<Scene>,
+    // Only one scene is in use at a time
+    pub(crate) current_scene: SceneID,
+    pub(crate) scenes: Vec<Scene>,
     pub nodes: Vec<Node>,
     pub meshes: Vec<Mesh>,
-    materials: Vec<Material>,
+    pub(crate) materials: Vec<Material>,
     pub(crate) textures: Vec<Texture>,
     pub animations: Vec<Animation>,
-    lights: Vec<Light>,
+    // Clips selected from `animations`
+    pub player: AnimationPlayer,
+    pub(crate) lights: Vec<Light>,
+    pub(crate) cameras: Vec<Camera>,
     // default_material_id: MaterialID,
     // default_sampler_id: SamplerID,
     pub(crate) images: Vec<Image>,
@@ -41,6 +56,16 @@ pub struct Doc {
     pub geo_builder: GeoBuilder,
     pub skins: Vec<Skin>,
     pub aabb_trans: Mat4,
+    // KHR_materials_variants names and the selected one
+    pub variants: Vec<String>,
+    material_variant: Option<usize>,
+    pub report: LoadReport,
+    // Set by the caller of a cached load
+    #[serde(skip)]
+    options: LoadOptions,
+    // Resolved path of the loaded file
+    #[serde(skip)]
+    source: PathBuf,
 }
 
 impl Doc {
@@ -48,6 +73,32 @@ impl Doc {
         &self.scenes[self.current_scene]
     }
 
+    pub fn current_scene(&self) -> usize {
+        self.current_scene
+    }
+
+    pub fn scene_names(&self) -> Vec<String> {
+        self.scenes
+            .iter()
+            .map(|s| {
+                s.name
+                    .clone()
+                    .unwrap_or_else(|| format!("Scene {}", s.index))
+            })
+            .collect()
+    }
+
+    /// Make another glTF scene the active one and recompute its transforms.
+    /// Lights and the TLAS have to be rebuilt from the doc afterwards.
+    pub fn set_scene(&mut self, scene: usize) {
+        if scene >= self.scenes.len() {
+            warn!("Scene {} out of range", scene);
+            return;
+        }
+        self.current_scene = scene;
+        self.load_scene();
+    }
+
     pub fn get_materials_raw(&self) -> Vec<MaterialRaw> {
         self.materials.iter().map(MaterialRaw::from).collect()
     }
@@ -73,15 +124,57 @@ impl Doc {
         }
 
         if dlights.is_empty() {
-            dlights.push(LightRaw {
-                ..Default::default()
-            });
+            dlights.push(LightRaw::default());
         }
         [dlights, plights]
     }
 
+    pub fn options(&self) -> &LoadOptions {
+        &self.options
+    }
+
+    /// Path the file was loaded from, after the model search paths
+    pub fn source(&self) -> &Path {
+        &self.source
+    }
+
+    pub fn material_variant(&self) -> Option<usize> {
+        self.material_variant
+    }
+
+    /// Switch every primitive to its material for `variant`, or back to the default
+    /// materials with `None`. Primitives without a mapping keep their default material.
+    /// The geometry buffer has to be re-uploaded afterwards.
+    pub fn set_material_variant(&mut self, variant: Option<usize>) {
+        if let Some(v) = variant.filter(|&v| v >= self.variants.len()) {
+            warn!("Material variant {} out of range", v);
+            return;
+        }
+        for p in self.meshes.iter().flat_map(|m| m.primitives.iter()) {
+            self.geo_builder
+                .set_material(p.geometry_id, p.variant_material(variant));
+        }
+        self.material_variant = variant;
+    }
+
+    /// All cameras of the current scene, after the `aabb_trans` normalization.
+    pub fn get_cameras(&self) -> Vec<CameraView> {
+        let mut cameras = Vec::new();
+        let mut f = |node: &Node| {
+            if let Some(camera) = node.camera.map(|c| &self.cameras[c]) {
+                cameras.push(camera.to_view(node.get_world_transform()));
+            }
+        };
+        self.traverse_root_nodes(&mut f);
+        cameras
+    }
+
     pub fn traverse_root_nodes<F: FnMut(&Node)>(&self, f: &mut F) {
-        self.get_current_scene()
+        self.traverse_scene_root_nodes(self.get_current_scene(), f);
+    }
+
+    fn traverse_scene_root_nodes<F: FnMut(&Node)>(&self, scene: &Scene, f: &mut F) {
+        scene
             .root_nodes
             .iter()
             .map(|&node| &self.nodes[node])
@@ -89,8 +182,34 @@ impl Doc {
     }
 
     pub fn need_compute(&self) -> bool {
-        // Add morph later
-        !self.skins.is_empty()
+        !self.skins.is_empty() || self.geo_builder.has_morph_targets()
+    }
+
+    /// Morph weights of every morphed geometry, packed like `MorphGeometry::weights`.
+    /// Nodes sharing a geometry have the same weights, see `duplicate_meshes_for_morphs`.
+    pub fn get_morph_weights(&self) -> Vec<f32> {
+        let layout = self.geo_builder.morph_layout();
+        let len: usize = layout.iter().flatten().map(|m| m.targets as usize).sum();
+        // Bound even without morph targets
+        let mut weights = vec![0.; len.max(1)];
+        let mut f = |node: &Node| {
+            let Some(mesh) = node.mesh.map(|m| &self.meshes[m]) else {
+                return;
+            };
+            for p in &mesh.primitives {
+                if let Some(m) = &layout[p.geometry_id as usize] {
+                    let (offset, targets) = (m.weights as usize, m.targets as usize);
+                    weights[offset..offset + targets]
+                        .iter_mut()
+                        .zip(&node.morph_weights)
+                        .for_each(|(w, &weight)| *w = weight);
+                }
+            }
+        };
+        self.scenes
+            .iter()
+            .for_each(|scene| self.traverse_scene_root_nodes(scene, &mut f));
+        weights
     }
 
     // From Kajiya
@@ -102,14 +221,21 @@ impl Doc {
             .for_each(|child| self.iter_gltf_node_tree(child, f))
     }
 
-    fn new(doc: &Document, buffers: Vec<buffer::Data>, gltf_images: Vec<image::Data>) -> Self {
+    fn new(
+        doc: &Document,
+        buffers: Vec<buffer::Data>,
+        gltf_images: Vec<RawImage>,
+        decoded: HashMap<[usize; 2], PrimitiveData>,
+        options: &LoadOptions,
+        progress: &LoadProgress,
+    ) -> Result<Self> {
         let current_scene = doc
             .default_scene()
             .unwrap_or(doc.scenes().next().expect("No scene"))
             .index();
         let scenes: Vec<_> = doc.scenes().map(Scene::from).collect();
         check_indices!(scenes);
-        let nodes: Vec<_> = doc.nodes().map(Node::from).collect();
+        let mut nodes: Vec<_> = doc.nodes().map(Node::from).collect();
         check_indices!(nodes);
         let lights: Vec<_> = doc
             .lights()
@@ -118,11 +244,17 @@ impl Doc {
             .collect();
         check_indices!(lights);
         report_lights(&lights);
+        let cameras: Vec<_> = doc.cameras().map(Camera::from).collect();
+        check_indices!(cameras);
 
         let materials: Vec<_> = doc.materials().map(Material::from).collect();
         check_indices!(materials);
 
         let mut geo_builder = GeoBuilder::new(buffers, &materials);
+        geo_builder.decoded = decoded;
+        geo_builder.crease_angle = options.normal_crease_angle();
+        geo_builder.regenerate_tangents = options.regenerate_tangents;
+        geo_builder.weld = options.weld;
 
         let animations: Vec<_> = doc
             .animations()
@@ -133,24 +265,56 @@ impl Doc {
         // doc.
 
         let now = Instant::now();
-        let meshes: Vec<_> = doc
-            .meshes()
-            .map(|m| Mesh::new(m, &mut geo_builder))
-            .collect();
+        let meshes = geo_builder.build_meshes(doc);
         check_indices!(meshes);
         info!(
             "Finish processing meshes, time:{}s",
             now.elapsed().as_secs()
         );
+        let mut report = std::mem::take(&mut geo_builder.report);
+        report.timings.push(("Meshes", now.elapsed()));
+        if !report.skipped_primitives.is_empty() {
+            warn!(
+                "{} point or line primitives are not rendered",
+                report.skipped_primitives.len()
+            );
+        }
+        // Nodes without their own weights use the mesh defaults
+        nodes
+            .iter_mut()
+            .filter(|n| n.morph_weights.is_empty())
+            .for_each(|n| {
+                if let Some(mesh) = n.mesh.map(|m| &meshes[m]) {
+                    n.morph_weights = mesh.weights.clone();
+                }
+            });
+        nodes
+            .iter_mut()
+            .for_each(|n| n.rest_weights = n.morph_weights.clone());
 
+        progress.phase(LoadPhase::Images)?;
         let linear = find_linear_textures(doc);
 
+        report.missing_textures = gltf_images
+            .iter()
+            .zip(doc.images())
+            .filter_map(|(raw, info)| match raw {
+                RawImage::Missing(reason) => Some(format!(
+                    "Image {} {}: {}",
+                    info.index(),
+                    image_label(&info),
+                    reason
+                )),
+                _ => None,
+            })
+            .collect();
         let now = Instant::now();
-        let images = process_images_unified(&gltf_images, doc, &linear);
+        let images = process_images_unified(gltf_images, doc, &linear, options.max_texture_size);
         info!(
             "Finish processing images, time:{}s",
             now.elapsed().as_secs()
         );
+        report.timings.push(("Images", now.elapsed()));
 
         let samplers: Vec<_> = once(Sampler::default())
             .chain(doc.samplers().map(Sampler::from))
@@ -162,14 +326,29 @@ impl Doc {
             .collect::<Vec<_>>();
         check_indices!(textures);
 
+        progress.phase(LoadPhase::Skins)?;
         let skins: Vec<_> = doc
             .skins()
             .map(|s| Skin::new(s, &geo_builder.buffers))
             .collect();
         check_indices!(skins);
 
+        let mut player = AnimationPlayer::new(&animations);
+        if !animations.is_empty() {
+            player.play(0);
+        }
+
+        let variants: Vec<_> = doc
+            .variants()
+            .into_iter()
+            .flat_map(|vs| vs.map(|v| v.name().to_string()))
+            .collect();
+        if !variants.is_empty() {
+            info!("Material variants: {:?}", variants);
+        }
+
         geo_builder.buffers = Vec::with_capacity(0);
-        Self {
+        Ok(Self {
             current_scene,
             scenes,
             nodes,
@@ -180,160 +359,325 @@ impl Doc {
             // default_sampler_id: 0,
             images,
             animations,
+            player,
             samplers,
             geo_builder,
             lights,
+            cameras,
             skins,
             aabb_trans: Default::default(),
-        }
+            variants,
+            material_variant: None,
+            report,
+            options: options.clone(),
+            source: PathBuf::new(),
+        })
     }
 
-    fn duplicate_mesh_for_non_affine_transform(&mut self) {
-        let mesh_len = self.meshes.len();
-        let mut mesh_to_node = vec![HashMap::new(); mesh_len];
+    /// Skinned vertices carry the offset of their skin in the packed joint buffer,
+    /// so a mesh shared by different skins, or by skinned and static nodes, gets one copy per skin.
+    fn duplicate_meshes_for_skins(&mut self) {
+        let mut mesh_to_skins = vec![BTreeMap::<_, BTreeSet<NodeID>>::new(); self.meshes.len()];
         let mut f = |node: &Node| {
             if let Some(mesh) = node.mesh {
-                mesh_to_node[mesh].insert(node.index, node.need_compute_pass());
+                mesh_to_skins[mesh]
+                    .entry(node.skin)
+                    .or_default()
+                    .insert(node.index);
             }
         };
-        self.traverse_root_nodes(&mut f);
-        let mut mesh_skin_record: HashMap<usize, usize> = HashMap::new();
-        for (mesh, map) in mesh_to_node.into_iter().enumerate() {
-            let (non_affine, affine): (Vec<_>, Vec<_>) = map.into_iter().partition(|x| x.1);
-            if non_affine.is_empty() {
-                continue;
-            }
-            assert!(affine.is_empty());
-            for (node, _) in non_affine {
-                let skin_index = self.nodes[node].skin.unwrap();
-                // Each mesh should only has one skins
-                if let Some(&skin) = mesh_skin_record.get(&mesh) {
-                    assert_eq!(skin_index, skin);
+        // Every scene, so that switching scenes does not need another pass
+        self.scenes
+            .iter()
+            .for_each(|scene| self.traverse_scene_root_nodes(scene, &mut f));
+        let offsets = joint_offsets(&self.skins);
+        for (mesh, skins) in mesh_to_skins.into_iter().enumerate() {
+            // Static nodes come first and keep the original geometry
+            for (i, (skin, nodes)) in skins.into_iter().enumerate() {
+                let mesh = if i == 0 {
+                    mesh
+                } else {
+                    let copy = self.duplicate_mesh(mesh);
+                    info!("Duplicating mesh {} as {} for skin {:?}", mesh, copy, skin);
+                    nodes.iter().for_each(|&n| self.nodes[n].mesh = Some(copy));
+                    copy
+                };
+                let Some(offset) = skin.map(|s| offsets[s] as i32) else {
                     continue;
-                }
-                mesh_skin_record.insert(mesh, skin_index);
-                for p_index in 0..self.meshes[mesh].primitives.len() {
-                    let geometry_id = self.meshes[mesh].primitives[p_index].geometry_id as usize;
-                    let geo_builder = &mut self.geo_builder;
-                    let [vertex_offset, index_offset, _material_id] =
-                        geo_builder.offsets[geometry_id];
-                    let [vertex_offset, _index_offset] =
-                        [vertex_offset as usize, index_offset as usize];
-                    let [vertex_length, _index_length] = geo_builder.len[geometry_id];
-                    let dup_vertices =
-                        &mut geo_builder.vertices[vertex_offset..vertex_offset + vertex_length];
-                    dup_vertices.iter_mut().for_each(|v| {
-                        assert_eq!(v.skin_index, -1);
-                        v.skin_index = skin_index as i32;
-                    });
+                };
+                let geo_builder = &mut self.geo_builder;
+                for p in &self.meshes[mesh].primitives {
+                    let geo_id = p.geometry_id as usize;
+                    let v_offset = geo_builder.offsets[geo_id][0] as usize;
+                    let [v_len, _] = geo_builder.len[geo_id];
+                    geo_builder.vertices[v_offset..v_offset + v_len]
+                        .iter_mut()
+                        .for_each(|v| v.joint_offset = offset);
                 }
             }
-            // for (node, _) in non_affine {
-            //     self.duplicate_mesh_for_node(mesh, node);
-            // }
         }
     }
 
-    // fn duplicate_mesh_for_node(&mut self, mesh: usize, node: usize) {
-    //     info!(
-    //         "Duplicating node: {} name: {:?}",
-    //         node, self.nodes[node].name
-    //     );
-    //     let new_mesh_id = self.meshes.len();
-    //     let skin_index = self.nodes[node].skin.unwrap();
-    //     assert_eq!(self.nodes[node].mesh.unwrap(), mesh);
-    //     *self.nodes[node].mesh.as_mut().unwrap() = new_mesh_id;
-    //     self.meshes.push(self.meshes[mesh].clone());
-    //
-    //     for p_index in 0..self.meshes[new_mesh_id].primitives.len() {
-    //         let geometry_id = self.meshes[new_mesh_id].primitives[p_index].geometry_id as usize;
-    //         let geo_builder = &mut self.geo_builder;
-    //         let [vertex_offset, index_offset, material_id] = geo_builder.offsets[geometry_id];
-    //         let [vertex_offset, index_offset] = [vertex_offset as usize, index_offset as usize];
-    //         let [vertex_length, index_length] = geo_builder.len[geometry_id];
-    //         let new_primitive_id = geo_builder.next_geo_id(material_id);
-    //         self.meshes[new_mesh_id].primitives[p_index].geometry_id = new_primitive_id;
-    //         let cur_vertex_len = geo_builder.vertices.len();
-    //         let cur_index_len = geo_builder.indices.len();
-    //         let mut dup_vertices =
-    //             geo_builder.vertices[vertex_offset..vertex_offset + vertex_length].to_vec();
-    //         dup_vertices.iter_mut().for_each(|v| {
-    //             assert_eq!(v.skin_index, -1);
-    //             v.skin_index = skin_index as i32;
-    //         });
-    //         // dup_vertices.
-    //         geo_builder.vertices.extend(dup_vertices);
-    //         geo_builder
-    //             .indices
-    //             .extend(geo_builder.indices[index_offset..index_offset + index_length].to_vec());
-    //         let [vertex_offset, index_offset] = [cur_vertex_len as u32, cur_index_len as u32];
-    //         geo_builder.len.push([vertex_length, index_length]);
-    //         geo_builder
-    //             .offsets
-    //             .push([vertex_offset, index_offset, material_id]);
-    //     }
-    // }
-
-    fn load_scene(&mut self, _document: &Document) {
+    /// Morphed geometry is blended with the weights of its geometry, so nodes sharing a mesh
+    /// get one copy per set of weights. Nodes with animated weights always get their own.
+    fn duplicate_meshes_for_morphs(&mut self) {
+        let animated: BTreeSet<NodeID> = self
+            .animations
+            .iter()
+            .flat_map(|a| &a.channels)
+            .filter(|c| c.animates_weights())
+            .map(|c| c.target)
+            .collect();
+        // Static nodes by their weights, then each animated node
+        type Key = (Option<NodeID>, Vec<u32>);
+        let mut mesh_to_weights = vec![BTreeMap::<Key, BTreeSet<NodeID>>::new(); self.meshes.len()];
+        let mut f = |node: &Node| {
+            let Some(mesh) = node.mesh else {
+                return;
+            };
+            let key = if animated.contains(&node.index) {
+                (Some(node.index), vec![])
+            } else {
+                (
+                    None,
+                    node.morph_weights.iter().map(|w| w.to_bits()).collect(),
+                )
+            };
+            mesh_to_weights[mesh]
+                .entry(key)
+                .or_default()
+                .insert(node.index);
+        };
+        self.scenes
+            .iter()
+            .for_each(|scene| self.traverse_scene_root_nodes(scene, &mut f));
+        for (mesh, weights) in mesh_to_weights.into_iter().enumerate() {
+            if !self.meshes[mesh].has_morph_targets(&self.geo_builder) {
+                continue;
+            }
+            // The first set of weights keeps the original geometry
+            for (_, nodes) in weights.into_iter().skip(1) {
+                let copy = self.duplicate_mesh(mesh);
+                info!(
+                    "Duplicating mesh {} as {} for nodes {:?}",
+                    mesh, copy, nodes
+                );
+                nodes.iter().for_each(|&n| self.nodes[n].mesh = Some(copy));
+            }
+        }
+    }
+
+    fn duplicate_mesh(&mut self, mesh: MeshID) -> MeshID {
+        let mut copy = self.meshes[mesh].clone();
+        copy.index = self.meshes.len();
+        for p in &mut copy.primitives {
+            p.geometry_id = self.geo_builder.duplicate(p.geometry_id);
+        }
+        self.meshes.push(copy);
+        self.meshes.len() - 1
+    }
+
+    fn load_scene(&mut self) {
         let scene = &self.scenes[self.current_scene];
         let root_nodes = scene.root_nodes.clone();
         let aabbs: Vec<_> = root_nodes
             .iter()
             .filter_map(|i| self.get_node_aabb(*i))
             .collect();
-        let aabb = Aabb::union(&aabbs).unwrap();
-        self.aabb_trans = aabb.get_transform();
-        root_nodes
-            .into_iter()
-            .for_each(|n| self.update_parent_transform(n, self.aabb_trans));
+        let up = if self.options.z_up {
+            Mat4::from_rotation_x(-std::f32::consts::FRAC_PI_2)
+        } else {
+            Mat4::IDENTITY
+        };
+        self.aabb_trans = match Aabb::union(&aabbs) {
+            Some(aabb) if self.options.normalize => (aabb * up).get_transform() * up,
+            Some(_) => up,
+            None => {
+                warn!("Scene {} has no geometry", self.current_scene);
+                up
+            }
+        };
+        self.update_transforms(true);
     }
 
-    fn update_local_transform(&mut self, node_id: NodeID, new_local: Transform) {
-        let node = &mut self.nodes[node_id];
-        node.local_transform = new_local;
-        let parent = node.parent_transform_cache;
-        self.update_parent_transform(node_id, parent);
+    /// Recompute the cached transforms below nodes whose local transform changed.
+    /// Each node of the current scene is visited once, parents before children.
+    fn update_transforms(&mut self, force: bool) {
+        let mut stack: Vec<_> = self.scenes[self.current_scene]
+            .root_nodes
+            .iter()
+            .map(|&n| (n, self.aabb_trans, force))
+            .collect();
+        while let Some((node_id, parent, parent_dirty)) = stack.pop() {
+            let node = &mut self.nodes[node_id];
+            let dirty = parent_dirty || node.dirty;
+            if dirty {
+                node.world_transform_cache = parent * node.get_local_transform();
+                node.dirty = false;
+            }
+            let world = node.world_transform_cache;
+            stack.extend(node.children.iter().map(|&c| (c, world, dirty)));
+        }
     }
 
-    fn update_parent_transform(&mut self, node_id: NodeID, new_parent: Mat4) {
-        let node = &mut self.nodes[node_id];
-        node.parent_transform_cache = new_parent;
-        let local_transform = node.get_local_transform();
-        node.children
-            .clone()
-            .into_iter()
-            .for_each(|c| self.update_parent_transform(c, new_parent * local_transform));
+    pub fn animation_names(&self) -> Vec<String> {
+        self.animations
+            .iter()
+            .map(|a| {
+                a.name()
+                    .map_or_else(|| format!("Animation {}", a.index), str::to_string)
+            })
+            .collect()
     }
 
-    pub fn animate(&mut self, t: f32) {
-        let all: Vec<_> = self
+    pub fn find_animation(&self, name: &str) -> Option<usize> {
+        self.animations.iter().position(|a| a.name() == Some(name))
+    }
+
+    /// Advance the player by `dt` seconds and pose the animated nodes
+    pub fn update_animations(&mut self, dt: f32) {
+        self.player.advance(dt);
+        self.apply_animations();
+    }
+
+    /// Pose the animated nodes from the clips of the player, blended by weight.
+    /// Properties no clip animates go back to the rest pose.
+    /// World transforms are updated in one pass afterwards.
+    pub fn apply_animations(&mut self) {
+        let mut blends: HashMap<NodeID, Blend> = self
             .animations
             .iter()
             .flat_map(|a| &a.channels)
-            .map(|c| {
-                let target = c.target;
-                let trans = c.get_transform(t);
-                (target, trans)
-            })
+            .map(|c| (c.target, Blend::default()))
             .collect();
-        all.into_iter().for_each(|(target, trans)| {
-            let transform = self.nodes[target].animate(trans);
-            self.update_local_transform(target, transform);
-        });
+        for clip in &self.player.clips {
+            for channel in &self.animations[clip.animation].channels {
+                if let Some(blend) = blends.get_mut(&channel.target) {
+                    blend.add(channel.get_transform(clip.time), clip.weight);
+                }
+            }
+        }
+        for (target, blend) in blends {
+            let node = &mut self.nodes[target];
+            let (transform, weights) =
+                blend.finish(node.rest_transform.clone(), &node.rest_weights);
+            node.morph_weights = weights;
+            // Paused or finished clips leave the pose as it is
+            if !same_transform(&node.local_transform, &transform) {
+                node.local_transform = transform;
+                node.dirty = true;
+            }
+        }
+        self.update_transforms(false);
     }
 
     pub fn static_scene(&self) -> bool {
         self.animations.is_empty()
     }
 
-    pub fn get_skins(&self) -> Vec<SkinRaw> {
-        let skins: Vec<_> = self
+    /// Joint matrices of every skin, packed in skin order
+    pub fn get_skins(&self) -> Vec<JointRaw> {
+        self.skins
+            .iter()
+            .flat_map(|s| s.get_skin_matrices(&self.nodes))
+            .collect()
+    }
+
+    /// BVH over the triangles of the current scene in world space, for picking.
+    /// Skinned geometry is posed by the current joint matrices, morph targets are not applied.
+    pub fn build_bvh(&self) -> Bvh {
+        let geo_builder = &self.geo_builder;
+        let joints: Vec<_> = self
             .skins
             .iter()
-            .map(|s| s.get_skin_matrices(&self.nodes))
+            .flat_map(|s| s.skinning_matrices(&self.nodes))
             .collect();
+        let skin = |v: &Vertex| {
+            let position = v.position.truncate();
+            if v.joint_offset < 0 {
+                return position;
+            }
+            let joint = |k: usize| joints[v.joint_offset as usize + v.joints[k] as usize];
+            let matrix = (0..4).fold(Mat4::ZERO, |m, k| m + joint(k) * v.weights[k]);
+            matrix.transform_point3(position)
+        };
+        let mut owners = vec![];
+        let mut triangles = vec![];
+        let mut f = |node: &Node| {
+            let Some(mesh) = node.mesh else {
+                return;
+            };
+            // Like the renderer, skinned meshes ignore the node transform
+            let transforms = if node.skin.is_none() {
+                node.get_instance_transforms()
+            } else {
+                vec![Mat4::IDENTITY]
+            };
+            for (instance, transform) in transforms.into_iter().enumerate() {
+                for (primitive, p) in self.meshes[mesh].primitives.iter().enumerate() {
+                    let geo_id = p.geometry_id as usize;
+                    let [v_offset, i_offset, _] = geo_builder.offsets[geo_id];
+                    let [v_len, i_len] = geo_builder.len[geo_id];
+                    let vertices: Vec<_> = geo_builder.vertices[v_offset as usize..][..v_len]
+                        .iter()
+                        .map(|v| transform.transform_point3(skin(v)))
+                        .collect();
+                    let owner = owners.len() as u32;
+                    owners.push(Owner {
+                        node: node.index,
+                        instance,
+                        mesh,
+                        primitive,
+                        geometry_id: p.geometry_id,
+                        material: geo_builder.material_id[geo_id],
+                    });
+                    let indices = &geo_builder.indices[i_offset as usize..][..i_len];
+                    triangles.extend(indices.chunks_exact(3).enumerate().map(|(i, t)| {
+                        let v = |k: usize| vertices[t[k] as usize];
+                        ([v(0), v(1), v(2)], owner, i as u32)
+                    }));
+                }
+            }
+        };
+        self.traverse_root_nodes(&mut f);
+        Bvh::new(owners, triangles)
+    }
 
-        skins
+    /// Labels and values describing a picked triangle, for display
+    pub fn hit_properties(&self, hit: &Hit) -> Vec<(&'static str, String)> {
+        let named = |index: usize, name: &Name| match name {
+            Some(name) => format!("{} ({})", index, name),
+            None => index.to_string(),
+        };
+        let mut properties = vec![
+            ("Node", named(hit.node, &self.nodes[hit.node].name)),
+            ("Mesh", named(hit.mesh, &self.meshes[hit.mesh].name)),
+            ("Primitive", hit.primitive.to_string()),
+            ("Triangle", hit.triangle.to_string()),
+        ];
+        if !self.nodes[hit.node].instances.is_empty() {
+            properties.insert(1, ("Instance", hit.instance.to_string()));
+        }
+        // Files without materials use the default one
+        match self.materials.get(hit.material) {
+            Some(material) => {
+                let pbr = &material.metallic_roughness_info;
+                properties.extend([
+                    ("Material", named(hit.material, &material.name)),
+                    ("Base color", format!("{:.3?}", material.base_color)),
+                    ("Metallic", format!("{:.3}", pbr.metallic_factor)),
+                    ("Roughness", format!("{:.3}", pbr.roughness_factor)),
+                    ("Alpha mode", format!("{:?}", material.alpha_mode)),
+                ]);
+            }
+            None => properties.push(("Material", "Default".to_string())),
+        }
+        properties.extend([
+            ("Position", format!("{:.3?}", hit.position.to_array())),
+            ("Distance", format!("{:.3}", hit.distance)),
+            ("Barycentrics", format!("{:.3?}", hit.barycentrics)),
+        ]);
+        properties
     }
 
     pub fn get_node_aabb(&self, node: usize) -> Option<Aabb> {
@@ -344,7 +688,11 @@ impl Doc {
             .filter_map(|c| self.get_node_aabb(*c))
             .collect();
         if let Some(local) = cur.mesh.and_then(|m| self.meshes[m].get_aabb()) {
-            childs.push(local);
+            if cur.instances.is_empty() {
+                childs.push(local);
+            } else {
+                childs.extend(cur.instances.iter().map(|&t| local * t));
+            }
         }
         childs
             .iter_mut()
@@ -353,12 +701,19 @@ impl Doc {
     }
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct Scene {
     index: usize,
     name: Name,
     pub root_nodes: Vec<NodeID>,
 }
 
+impl Scene {
+    pub(crate) fn to_json(&self) -> Value {
+        named(json!({ "nodes": self.root_nodes }), &self.name)
+    }
+}
+
 impl From<gltf::Scene<'_>> for Scene {
     fn from(scene: gltf::Scene) -> Self {
         Self {
@@ -369,50 +724,124 @@ impl From<gltf::Scene<'_>> for Scene {
     }
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct Node {
     index: usize,
     pub skin: Option<usize>,
     name: Name,
     children: Vec<NodeID>,
     light: Option<usize>,
+    camera: Option<usize>,
     pub mesh: Option<MeshID>,
+    // EXT_mesh_gpu_instancing transforms, applied before the node transform
+    pub(crate) instances: Vec<Mat4>,
+    #[serde(with = "crate::cache::TransformDef")]
     local_transform: Transform,
-    parent_transform_cache: Mat4,
+    world_transform_cache: Mat4,
+    // Local transform changed since the last `Doc::update_transforms`
+    dirty: bool,
+    morph_weights: Vec<f32>,
+    // Pose without animations
+    #[serde(with = "crate::cache::TransformDef")]
+    rest_transform: Transform,
+    rest_weights: Vec<f32>,
 }
 
 impl Node {
     pub fn is_leaf(&self) -> bool {
         self.children.is_empty()
     }
+
+    /// Written in the rest pose, animations are exported separately
+    pub(crate) fn to_json(&self, buffer: &mut GltfBuffer) -> Value {
+        let mut node = named(json!({}), &self.name);
+        match self.rest_transform.clone() {
+            Transform::Matrix { matrix } => node["matrix"] = json!(matrix.concat()),
+            Transform::Decomposed {
+                translation,
+                rotation,
+                scale,
+            } => {
+                node["translation"] = json!(translation);
+                node["rotation"] = json!(rotation);
+                node["scale"] = json!(scale);
+            }
+        }
+        if !self.children.is_empty() {
+            node["children"] = json!(self.children);
+        }
+        for (key, index) in [
+            ("mesh", self.mesh),
+            ("skin", self.skin),
+            ("camera", self.camera),
+        ] {
+            if let Some(index) = index {
+                node[key] = index.into();
+            }
+        }
+        if !self.rest_weights.is_empty() {
+            node["weights"] = json!(self.rest_weights);
+        }
+        let mut extensions = json!({});
+        if let Some(light) = self.light {
+            extensions["KHR_lights_punctual"] = json!({ "light": light });
+        }
+        if !self.instances.is_empty() {
+            let (mut t, mut r, mut s) = (vec![], vec![], vec![]);
+            for instance in &self.instances {
+                let (scale, rotation, translation) = instance.to_scale_rotation_translation();
+                t.extend(translation.to_array());
+                r.extend(rotation.to_array());
+                s.extend(scale.to_array());
+            }
+            extensions["EXT_mesh_gpu_instancing"] = json!({"attributes": {
+                "TRANSLATION": buffer.push_floats(&t, "VEC3", false),
+                "ROTATION": buffer.push_floats(&r, "VEC4", false),
+                "SCALE": buffer.push_floats(&s, "VEC3", false),
+            }});
+        }
+        if extensions.as_object().is_some_and(|e| !e.is_empty()) {
+            node["extensions"] = extensions;
+        }
+        node
+    }
+}
+
+fn same_transform(a: &Transform, b: &Transform) -> bool {
+    match (a, b) {
+        (
+            Transform::Decomposed {
+                translation: t0,
+                rotation: r0,
+                scale: s0,
+            },
+            Transform::Decomposed {
+                translation: t1,
+                rotation: r1,
+                scale: s1,
+            },
+        ) => t0 == t1 && r0 == r1 && s0 == s1,
+        _ => a.clone().matrix() == b.clone().matrix(),
+    }
 }
 
 impl Node {
     pub fn get_world_transform(&self) -> Mat4 {
-        self.parent_transform_cache * self.get_local_transform()
+        self.world_transform_cache
     }
-    pub fn get_local_transform(&self) -> Mat4 {
-        Mat4::from_cols_array_2d(&self.local_transform.clone().matrix())
+    /// World transforms of every EXT_mesh_gpu_instancing instance,
+    /// or just the node itself without instancing.
+    pub fn get_instance_transforms(&self) -> Vec<Mat4> {
+        let world = self.get_world_transform();
+        if self.instances.is_empty() {
+            vec![world]
+        } else {
+            self.instances.iter().map(|&t| world * t).collect()
+        }
     }
 
-    pub fn animate(&self, update: PropertyOutput) -> Transform {
-        let (mut t, mut r, mut s) = self.local_transform.clone().decomposed();
-        match update {
-            PropertyOutput::Translation(t_new) => {
-                t = t_new;
-            }
-            PropertyOutput::Rotation(r_new) => {
-                r = r_new;
-            }
-            PropertyOutput::Scale(s_new) => {
-                s = s_new;
-            }
-            _ => {}
-        }
-        Transform::Decomposed {
-            translation: t,
-            scale: s,
-            rotation: r,
-        }
+    pub fn get_local_transform(&self) -> Mat4 {
+        Mat4::from_cols_array_2d(&self.local_transform.clone().matrix())
     }
 
     pub fn need_compute_pass(&self) -> bool {
@@ -428,43 +857,136 @@ impl From<gltf::Node<'_>> for Node {
             name: get_name!(node),
             children: get_index_array!(node.children()),
             light: get_index!(node.light()),
+            camera: get_index!(node.camera()),
+            instances: vec![],
             mesh: get_index!(node.mesh()),
             local_transform: node.transform(),
-            parent_transform_cache: Mat4::IDENTITY,
+            rest_transform: node.transform(),
+            world_transform_cache: Mat4::from_cols_array_2d(&node.transform().matrix()),
+            dirty: false,
+            morph_weights: node.weights().map_or(vec![], |w| w.to_vec()),
+            rest_weights: vec![],
         }
     }
 }
 
-pub fn load_file<P: AsRef<Path>>(path: P) -> Result<Doc> {
+/// Load a glTF or OBJ file. Recoverable problems are listed in `Doc::report`.
+pub fn load_file<P: AsRef<Path>>(path: P, options: &LoadOptions) -> Result<Doc> {
+    load_file_with_progress(path, options, &LoadProgress::default())
+}
+
+/// `load_file` reporting its phases, it fails with `Error::Cancelled` once `progress` is cancelled
+pub fn load_file_with_progress<P: AsRef<Path>>(
+    path: P,
+    options: &LoadOptions,
+    progress: &LoadProgress,
+) -> Result<Doc> {
+    progress.phase(LoadPhase::Parse)?;
     let now = Instant::now();
     let name = path.as_ref().to_str().unwrap_or_default().to_string();
     info!("Start loading glTF <<{}>>", name);
     let path = resource_manager::load_model(path).map_err(|e| Error::Load(e.to_string()))?;
-    let (document, buffers, gltf_images) =
-        gltf::import(path).map_err(|e| Error::Load(e.to_string()))?;
+    let is_obj = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("obj"));
+    // Extensions the gltf crate does not fully expose
+    let raw_json = if is_obj { None } else { read_raw_json(&path) };
+    if raw_json.is_none() && !is_obj {
+        warn!("Failed to read raw glTF JSON");
+    }
+    let cache_path = options.cache_dir.as_ref().and_then(|dir| {
+        cache::cache_path(dir, &path, raw_json.as_ref(), options)
+            .map_err(|e| warn!("{}", e))
+            .ok()
+    });
+    match cache_path.as_deref().map(cache::load) {
+        Some(Ok(Some(mut doc))) => {
+            info!("Loaded <<{}>> from the cache", name);
+            doc.options = options.clone();
+            doc.source = path;
+            doc.report.timings = vec![("Cache", now.elapsed())];
+            return Ok(doc);
+        }
+        Some(Err(e)) => warn!("{}", e),
+        _ => {}
+    }
+    let uses_basisu = raw_json
+        .as_ref()
+        .and_then(|json| json.get("extensionsUsed")?.as_array())
+        .is_some_and(|used| used.iter().any(|e| e == KHR_TEXTURE_BASISU));
+    let (document, buffers, gltf_images) = match &raw_json {
+        _ if is_obj => import_obj(&path)?,
+        Some(json) if uses_basisu => import_basisu(&path, json)?,
+        _ => {
+            let (document, buffers, images) =
+                gltf::import(&path).map_err(|e| Error::Load(e.to_string()))?;
+            (
+                document,
+                buffers,
+                images.into_iter().map(RawImage::Gltf).collect(),
+            )
+        }
+    };
 
     info!(
         "Finish loading glTF {}, time:{}s",
         name,
         now.elapsed().as_secs()
     );
-    check_extensions(&document);
+    let mut report = LoadReport {
+        timings: vec![("Import", now.elapsed())],
+        unsupported_extensions: check_extensions(&document),
+        ..Default::default()
+    };
+
+    let instances = raw_json
+        .as_ref()
+        .map_or(vec![], |json| read_instances(json, &document, &buffers));
+
+    progress.phase(LoadPhase::Meshes)?;
+    let decoded = report.time("Draco", || match &raw_json {
+        Some(json) => decode_primitives(json, &document, &buffers),
+        None => Ok(HashMap::new()),
+    })?;
 
-    let mut doc = Doc::new(&document, buffers, gltf_images);
+    let mut doc = Doc::new(&document, buffers, gltf_images, decoded, options, progress)?;
+    doc.source = path.clone();
+    doc.report.unsupported_extensions = report.unsupported_extensions;
+    doc.report.timings.splice(0..0, report.timings);
+    if let Some(json) = &raw_json {
+        patch_texture_transforms(json, &mut doc.materials);
+    }
+    for (node, transforms) in instances {
+        doc.nodes[node].instances = transforms;
+    }
     if !doc.skins.is_empty() {
-        doc.duplicate_mesh_for_non_affine_transform();
+        doc.duplicate_meshes_for_skins();
+    }
+    if doc.geo_builder.has_morph_targets() {
+        doc.duplicate_meshes_for_morphs();
     }
     info!("Skin length: {}", doc.skins.len());
-    doc.load_scene(&document);
+    doc.load_scene();
     if !doc.static_scene() {
         info!("Animation available.");
     }
+    doc.report.timings.push(("Total", now.elapsed()));
+    if doc.report.has_issues() {
+        warn!("Load report for {}:\n{}", name, doc.report);
+    } else {
+        info!("{}", doc.report);
+    }
+    if let Some(cache_path) = cache_path {
+        if let Err(e) = cache::store(&cache_path, &doc) {
+            warn!("{}", e);
+        }
+    }
     Ok(doc)
 }
 
 #[test]
 fn test() {
-    let doc = load_file("type59.gltf").expect("TODO: panic message");
+    let doc = load_file("type59.gltf", &LoadOptions::default()).expect("TODO: panic message");
     for (i, image) in doc.images.iter().enumerate() {
         assert_eq!(image.index, i);
         println!("Image: {} index:{} {:?}", i, image.index, image.source);
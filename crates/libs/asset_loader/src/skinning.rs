@@ -1,14 +1,13 @@
 use glam::Mat4;
 use gltf::buffer;
-use log::warn;
-use std::default::Default;
+use serde::{Deserialize, Serialize};
 
+use crate::export::{named, GltfBuffer};
 use crate::scene_graph::Node;
 use crate::{get_index, get_index_array, get_name, Name, NodeID};
+use serde_json::{json, Value};
 
-pub const MAX_JOINTS: usize = 256;
-pub type SkinRaw = [JointRaw; MAX_JOINTS];
-
+#[derive(Serialize, Deserialize)]
 pub struct Skin {
     pub index: usize,
     name: Name,
@@ -36,18 +35,50 @@ impl Skin {
         }
     }
 
-    pub fn get_skin_matrices(&self, nodes: &[Node]) -> SkinRaw {
-        let len = self.joints.len();
-        if len > MAX_JOINTS {
-            warn!("Too many joints: {}; current max: {}", len, MAX_JOINTS);
-        }
-        let mut res: SkinRaw = [Default::default(); MAX_JOINTS];
-        let len = len.min(MAX_JOINTS);
-        self.joints[0..len].iter().enumerate().for_each(|(i, j)| {
-            res[i] = (j.compute_skinning_matrix(nodes)).into();
-        });
-        res
+    pub(crate) fn to_json(&self, buffer: &mut GltfBuffer) -> Value {
+        let ibms: Vec<_> = self
+            .joints
+            .iter()
+            .flat_map(|j| j.ibm.to_cols_array())
+            .collect();
+        named(
+            json!({
+                "joints": self.joints.iter().map(|j| j.node).collect::<Vec<_>>(),
+                "inverseBindMatrices": buffer.push_floats(&ibms, "MAT4", false),
+            }),
+            &self.name,
+        )
+    }
+
+    pub fn joint_count(&self) -> usize {
+        self.joints.len()
+    }
+
+    pub fn get_skin_matrices<'a>(
+        &'a self,
+        nodes: &'a [Node],
+    ) -> impl Iterator<Item = JointRaw> + 'a {
+        self.skinning_matrices(nodes).map(JointRaw::from)
     }
+
+    pub(crate) fn skinning_matrices<'a>(
+        &'a self,
+        nodes: &'a [Node],
+    ) -> impl Iterator<Item = Mat4> + 'a {
+        self.joints.iter().map(|j| j.compute_skinning_matrix(nodes))
+    }
+}
+
+/// First joint of each skin in the packed joint buffer
+pub(crate) fn joint_offsets(skins: &[Skin]) -> Vec<usize> {
+    skins
+        .iter()
+        .scan(0, |offset, s| {
+            let first = *offset;
+            *offset += s.joint_count();
+            Some(first)
+        })
+        .collect()
 }
 
 #[repr(C)]
@@ -64,6 +95,7 @@ impl From<Mat4> for JointRaw {
     }
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct Joint {
     node: NodeID,
     ibm: Mat4,
@@ -80,3 +112,16 @@ impl Joint {
         nodes[self.node].get_world_transform() * self.ibm
     }
 }
+
+#[test]
+fn test_joint_offsets() {
+    let skin = |joints: usize| Skin {
+        index: 0,
+        name: None,
+        joints: (0..joints)
+            .map(|n| Joint::from((n, Mat4::IDENTITY)))
+            .collect(),
+    };
+    let skins = [skin(300), skin(0), skin(2)];
+    assert_eq!(joint_offsets(&skins), [0, 300, 300]);
+}
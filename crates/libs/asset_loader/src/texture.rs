@@ -1,6 +1,8 @@
 use crate::{get_name, Name};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Texture {
     pub image_index: usize,
     pub index: usize,
@@ -19,7 +21,7 @@ impl Default for Texture {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct Sampler {
     pub mag_filter: MagFilter,
     pub min_filter: MinFilter,
@@ -40,13 +42,40 @@ impl Default for Sampler {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+impl Sampler {
+    /// With the OpenGL enums of glTF
+    pub(crate) fn to_json(self) -> Value {
+        let wrap = |w: WrapMode| match w {
+            WrapMode::ClampToEdge => 33071,
+            WrapMode::MirroredRepeat => 33648,
+            WrapMode::Repeat => 10497,
+        };
+        json!({
+            "magFilter": match self.mag_filter {
+                MagFilter::Nearest => 9728,
+                MagFilter::Linear => 9729,
+            },
+            "minFilter": match self.min_filter {
+                MinFilter::Nearest => 9728,
+                MinFilter::Linear => 9729,
+                MinFilter::NearestMipmapNearest => 9984,
+                MinFilter::LinearMipmapNearest => 9985,
+                MinFilter::NearestMipmapLinear => 9986,
+                MinFilter::LinearMipmapLinear => 9987,
+            },
+            "wrapS": wrap(self.wrap_s),
+            "wrapT": wrap(self.wrap_t),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum MagFilter {
     Nearest,
     Linear,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum MinFilter {
     Nearest,
     Linear,
@@ -56,7 +85,7 @@ pub enum MinFilter {
     LinearMipmapLinear,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum WrapMode {
     ClampToEdge,
     MirroredRepeat,
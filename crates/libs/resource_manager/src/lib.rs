@@ -39,7 +39,9 @@ fn find_gltf(search: &PathBuf) -> Option<PathBuf> {
         if entry
             .file_name()
             .to_str()
-            .filter(|name| name.ends_with(".gltf") || name.ends_with(".glb"))
+            .filter(|name| {
+                name.ends_with(".gltf") || name.ends_with(".glb") || name.ends_with(".obj")
+            })
             .is_some()
         {
             return Some(entry.path());
@@ -265,7 +265,7 @@ impl CommandBuffer {
                     .subresource_range(vk::ImageSubresourceRange {
                         aspect_mask: vk::ImageAspectFlags::COLOR,
                         base_mip_level: 0,
-                        level_count: 1,
+                        level_count: b.image.mip_levels,
                         base_array_layer: 0,
                         layer_count,
                     })
@@ -351,6 +351,42 @@ impl CommandBuffer {
         };
     }
 
+    /// Copy tightly packed mip levels starting at the given buffer offsets
+    pub fn copy_buffer_to_image_mips(
+        &self,
+        src: &Buffer,
+        dst: &Image,
+        layout: vk::ImageLayout,
+        level_offsets: &[vk::DeviceSize],
+    ) {
+        let regions = level_offsets
+            .iter()
+            .enumerate()
+            .map(|(level, &offset)| {
+                vk::BufferImageCopy::builder()
+                    .buffer_offset(offset)
+                    .image_subresource(vk::ImageSubresourceLayers {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        mip_level: level as _,
+                        base_array_layer: 0,
+                        layer_count: 1,
+                    })
+                    .image_extent(vk::Extent3D {
+                        width: (dst.extent.width >> level).max(1),
+                        height: (dst.extent.height >> level).max(1),
+                        depth: 1,
+                    })
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        unsafe {
+            self.device
+                .inner
+                .cmd_copy_buffer_to_image(self.inner, src.inner, dst.inner, layout, &regions);
+        };
+    }
+
     pub fn build_acceleration_structures(
         &self,
         as_build_geo_info: &vk::AccelerationStructureBuildGeometryInfoKHR,
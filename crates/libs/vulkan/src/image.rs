@@ -17,6 +17,7 @@ pub struct Image {
     allocation: Option<Allocation>,
     pub format: vk::Format,
     pub extent: vk::Extent3D,
+    pub mip_levels: u32,
     is_swapchain: bool, // if set, image should not be destroyed
 }
 
@@ -32,12 +33,12 @@ impl Image {
         usage: vk::ImageUsageFlags,
         memory_location: MemoryLocation,
         format: vk::Format,
-        width: u32,
-        height: u32,
+        extent: vk::Extent2D,
+        mip_levels: u32,
     ) -> Result<Self> {
         let extent = vk::Extent3D {
-            width,
-            height,
+            width: extent.width,
+            height: extent.height,
             depth: 1,
         };
 
@@ -45,7 +46,7 @@ impl Image {
             .image_type(vk::ImageType::TYPE_2D)
             .format(format)
             .extent(extent)
-            .mip_levels(1)
+            .mip_levels(mip_levels)
             .array_layers(1)
             .samples(vk::SampleCountFlags::TYPE_1)
             .tiling(vk::ImageTiling::OPTIMAL)
@@ -76,6 +77,7 @@ impl Image {
             allocation: Some(allocation),
             format,
             extent,
+            mip_levels,
             is_swapchain: false,
         })
     }
@@ -131,6 +133,7 @@ impl Image {
             allocation: Some(allocation),
             format,
             extent,
+            mip_levels: 1,
             is_swapchain: false,
         })
     }
@@ -155,6 +158,7 @@ impl Image {
             allocation: None,
             format,
             extent,
+            mip_levels: 1,
             is_swapchain: true,
         }
     }
@@ -167,7 +171,7 @@ impl Image {
             .subresource_range(vk::ImageSubresourceRange {
                 aspect_mask: vk::ImageAspectFlags::COLOR,
                 base_mip_level: 0,
-                level_count: 1,
+                level_count: self.mip_levels,
                 base_array_layer: 0,
                 layer_count: 1,
             });
@@ -209,6 +213,18 @@ impl Context {
         format: vk::Format,
         width: u32,
         height: u32,
+    ) -> Result<Image> {
+        self.create_image_with_mips(usage, memory_location, format, width, height, 1)
+    }
+
+    pub fn create_image_with_mips(
+        &self,
+        usage: vk::ImageUsageFlags,
+        memory_location: MemoryLocation,
+        format: vk::Format,
+        width: u32,
+        height: u32,
+        mip_levels: u32,
     ) -> Result<Image> {
         Image::new_2d(
             self.device.clone(),
@@ -216,8 +232,8 @@ impl Context {
             usage,
             memory_location,
             format,
-            width,
-            height,
+            vk::Extent2D { width, height },
+            mip_levels,
         )
     }
 